@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+
+use ethers::types::{Address, U256};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::Router;
+
+/// Balance of a token summed over the tracked accounts, token decimals
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct TokenTotal {
+    // lowercase address
+    pub token: String,
+    pub symbol: String,
+    pub balance: Decimal,
+    pub balances: HashMap<Address, Decimal>,
+}
+
+/// Staking in a reward tracker summed over the tracked accounts
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct StakingTotal {
+    pub reward_tracker: Address,
+    // 18 decimals
+    pub staked_amount: U256,
+    // reward token decimals
+    pub claimable: U256,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct AccountTotals {
+    // selected then watched accounts
+    pub accounts: Vec<Address>,
+    // tokens held by at least one account, in config order
+    pub tokens: Vec<TokenTotal>,
+    pub staking: Vec<StakingTotal>,
+    pub open_positions: usize,
+}
+
+impl Router {
+    /// Fetches `accounts` along with the selected account in every fetch, replaces the previous list
+    pub fn watch_accounts(&mut self, accounts: Vec<Address>) {
+        self.config.watched_accounts = vec![];
+        for account in accounts {
+            if !self.config.watched_accounts.contains(&account) {
+                self.config.watched_accounts.push(account);
+            }
+        }
+    }
+
+    /// Totals of the last fetched data of the tracked accounts
+    pub fn account_totals(&self) -> anyhow::Result<AccountTotals> {
+        let accounts = self.config.tracked_accounts()?;
+        let tokens = self
+            .config
+            .tokens
+            .iter()
+            .filter_map(|token| {
+                let balances: HashMap<Address, Decimal> = accounts
+                    .iter()
+                    .filter_map(|account| Some((*account, *token.balances.as_ref()?.get(account)?)))
+                    .collect();
+                if balances.is_empty() {
+                    return None;
+                }
+                Some(TokenTotal {
+                    token: token.address.clone(),
+                    symbol: token.symbol.clone(),
+                    balance: balances.values().sum(),
+                    balances,
+                })
+            })
+            .collect();
+
+        let mut staking: Vec<StakingTotal> = vec![];
+        for info in accounts.iter().flat_map(|account| self.vault.state.get_staking_info(account)) {
+            match staking.iter_mut().find(|total| total.reward_tracker == info.reward_tracker) {
+                Some(total) => {
+                    total.staked_amount += info.staked_amount;
+                    total.claimable += info.claimable;
+                }
+                None => staking.push(StakingTotal { reward_tracker: info.reward_tracker, staked_amount: info.staked_amount, claimable: info.claimable }),
+            }
+        }
+
+        let open_positions = accounts.iter().map(|account| self.vault.state.get_positions(account).len()).sum();
+        Ok(AccountTotals { accounts, tokens, staking, open_positions })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contracts::staking::StakingInfo;
+    use crate::fetch_plan::FetchPlan;
+    use crate::RouterTrait;
+
+    const ACCOUNT: &str = "0x1f9840a85d5af5bf1d1762f925bdaddc4201f984";
+    const TREASURY: &str = "0x50c121da29cbd8bd96bdb6c6965f93f09c2fc598";
+
+    fn create_router() -> Router {
+        let mut router = Router::new();
+        router.initilize(97).unwrap();
+        router.set_account(ACCOUNT.to_string());
+        router
+    }
+
+    #[test]
+    fn watched_accounts_are_fetched_along() {
+        let mut router = create_router();
+        let (account, treasury): (Address, Address) = (ACCOUNT.parse().unwrap(), TREASURY.parse().unwrap());
+        // the selected account is tracked once
+        router.watch_accounts(vec![treasury, account, treasury]);
+        assert_eq!(router.config.watched_accounts, vec![treasury, account]);
+        assert_eq!(router.config.tracked_accounts().unwrap(), vec![account, treasury]);
+
+        let batches = FetchPlan::balances().build_calls(&router.config).unwrap();
+        let (tokens, spenders) = (router.config.tokens.len(), router.config.contract_spender.len());
+        assert_eq!(batches[0].1.len(), tokens * 2);
+        assert_eq!(batches[1].1.len(), tokens * spenders * 2);
+    }
+
+    #[test]
+    fn account_totals_sum_tracked_accounts() {
+        let mut router = create_router();
+        let (account, treasury): (Address, Address) = (ACCOUNT.parse().unwrap(), TREASURY.parse().unwrap());
+        router.watch_accounts(vec![treasury]);
+        router.config.tokens[0].update_balance(&ACCOUNT.to_string(), U256::exp10(18));
+        router.config.tokens[0].update_balance(&TREASURY.to_string(), U256::exp10(18) * 2);
+        // not tracked anymore, ignored
+        router.config.tokens[1].update_balance(&format!("{:?}", Address::repeat_byte(1)), U256::exp10(18));
+
+        let reward_tracker = Address::repeat_byte(2);
+        let staking = |staked: u64| StakingInfo { reward_tracker, staked_amount: U256::from(staked), claimable: U256::from(1), ..StakingInfo::default() };
+        router.vault.state.staking_info = Some(HashMap::from([(account, vec![staking(100)]), (treasury, vec![staking(50)])]));
+
+        let totals = router.account_totals().unwrap();
+        assert_eq!(totals.accounts, vec![account, treasury]);
+        assert_eq!(totals.tokens.len(), 1);
+        assert_eq!(totals.tokens[0].balance, Decimal::from(3));
+        assert_eq!(totals.tokens[0].balances[&treasury], Decimal::from(2));
+        assert_eq!(totals.staking, vec![StakingTotal { reward_tracker, staked_amount: U256::from(150), claimable: U256::from(2) }]);
+        assert_eq!(totals.open_positions, 0);
+    }
+}
@@ -1,3 +1,4 @@
+use ethers::types::Address;
 use serde::{Deserialize, Serialize};
 use crate::contracts::token::Token;
 use crate::contracts::multicall::Multicall;
@@ -11,7 +12,10 @@ pub struct Config {
     pub chain: Chain,
     pub tokens: Vec<Token>,
     pub contract_address: ContractAddress,
-    pub contract_spender : Vec<Spender>
+    pub contract_spender : Vec<Spender>,
+    // fetched along with the selected account, see `Router::watch_accounts`
+    #[serde(default)]
+    pub watched_accounts: Vec<Address>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
@@ -36,6 +40,9 @@ pub struct ContractAddress {
     pub reward_router: String,
     pub futurx_gateway : String,
     pub reward_tracker_fee_plp : String,
+    // fsPLP, staked fPLP, empty when the chain has none
+    #[serde(default)]
+    pub reward_tracker_fee_staked_plp: String,
     pub vester_plp :String,
 }
 
@@ -49,6 +56,20 @@ impl Config {
         self.selected_account = Some(account);
     }
 
+    /// Selected account first then the watched ones, once each
+    pub fn tracked_accounts(&self) -> anyhow::Result<Vec<Address>> {
+        let mut accounts: Vec<Address> = vec![];
+        if let Some(account) = self.selected_account.as_deref() {
+            accounts.push(account.parse()?);
+        }
+        for account in &self.watched_accounts {
+            if !accounts.contains(account) {
+                accounts.push(*account);
+            }
+        }
+        Ok(accounts)
+    }
+
     pub fn get_token_by_token_address(&self, token_address: String) -> Option<Token> {
         let mut token: Option<Token> = None;
         for t in self.tokens.iter() {
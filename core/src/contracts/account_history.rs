@@ -0,0 +1,437 @@
+use std::collections::HashMap;
+
+use ethabi::{Contract, ParamType, RawLog};
+use ethers::providers::Middleware;
+use ethers::types::{Address, Log, H256, U256};
+use ethers::utils::keccak256;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::tx::gateway::GATEWAY_ABI;
+use crate::tx::http_provider;
+use super::events::{decode_log, summarize, TradeKind, VaultEvent};
+use super::scanner::{LogScanner, ScanCheckpoint};
+use super::vault_logic::{expand_decimals, BASIS_POINTS_DIVISOR};
+
+lazy_static! {
+    static ref GATEWAY_CONTRACT: Contract = Contract::load(GATEWAY_ABI.as_bytes()).expect("Invalid gateway ABI");
+    static ref TRANSFER_TOPIC: H256 = H256::from(keccak256("Transfer(address,address,uint256)"));
+}
+
+// vault events joined per transaction, the account is not indexed so they are matched after the scan
+const VAULT_EVENTS: [&str; 6] = ["BuyUSDP", "SellUSDP", "Swap", "IncreaseUsdgAmount", "IncreaseFeeReserves", "CollectFees"];
+// gateway events, filtered by the indexed account
+const GATEWAY_EVENTS: [&str; 3] = ["Swap", "ExecuteIncreasePosition", "ExecuteDecreasePosition"];
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum HistoryAction {
+    MintPlp,
+    RedeemPlp,
+    Swap,
+    IncreasePosition,
+    DecreasePosition,
+}
+
+/// One trade of an account. Token amounts in their decimals, USD in 30 decimals.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct HistoryEntry {
+    pub block_number: u64,
+    // unix seconds of the block
+    pub timestamp: u64,
+    pub transaction_hash: H256,
+    pub log_index: u64,
+    pub action: HistoryAction,
+    pub token_in: Option<Address>,
+    pub token_out: Option<Address>,
+    pub amount_in: U256,
+    pub amount_out: U256,
+    // USDP minted or redeemed, swap value, or position size delta
+    pub usd_value: U256,
+    // in fee_token, zero for positions whose fees are in USD only
+    pub fee_amount: U256,
+    pub fee_token: Option<Address>,
+    pub fee_usd: U256,
+    // positions only
+    pub index_token: Option<Address>,
+    pub is_long: Option<bool>,
+    pub collateral_delta_usd: U256,
+}
+
+/// PLP minted to or burnt from `account` in a transaction, from the PLP Transfer logs
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PlpTransfer {
+    pub account: Address,
+    pub amount: U256,
+}
+
+/// USDP has 18 decimals, USD values 30
+fn usdp_to_usd(amount: U256) -> U256 {
+    amount * expand_decimals(1, 12)
+}
+
+fn log_position(log: &Log) -> (u64, H256, u64) {
+    (
+        log.block_number.unwrap_or_default().as_u64(),
+        log.transaction_hash.unwrap_or_default(),
+        log.log_index.unwrap_or_default().low_u64(),
+    )
+}
+
+/// Entries of `account` from scanned logs, sorted by block and log index.
+/// PLP is minted through the PLP manager, so its BuyUSDP/SellUSDP belong to the account that the PLP was minted to
+/// or burnt from in the transaction, see `plp_transfers`. These entries trade PLP, not the USDP of the vault events.
+#[allow(clippy::too_many_arguments)]
+pub fn build_history(
+    account: Address,
+    plp_manager: Address,
+    plp_token: Address,
+    usdp: Address,
+    vault_logs: &[Log],
+    gateway_logs: &[Log],
+    plp_transfers: &HashMap<H256, PlpTransfer>,
+    timestamps: &HashMap<u64, u64>,
+) -> Vec<HistoryEntry> {
+    let mut vault_events: HashMap<H256, Vec<VaultEvent>> = HashMap::new();
+    // CollectFees by log index, a transaction can execute the requests of several accounts
+    let mut collected_fees: HashMap<H256, Vec<(u64, U256)>> = HashMap::new();
+    for log in vault_logs {
+        if let (Some(tx_hash), Some(event)) = (log.transaction_hash, decode_log(log)) {
+            if let VaultEvent::CollectFees(fees) = &event {
+                collected_fees.entry(tx_hash).or_default().push((log_position(log).2, fees.total_fee));
+            }
+            vault_events.entry(tx_hash).or_default().push(event);
+        }
+    }
+    let no_events = vec![];
+    let mut entries = vec![];
+
+    // PLP mints and redeems
+    for log in vault_logs {
+        let (block_number, transaction_hash, log_index) = log_position(log);
+        let plp_transfer = plp_transfers.get(&transaction_hash).filter(|transfer| transfer.account == account);
+        let is_account = |event_account: Address| event_account == account || (event_account == plp_manager && plp_transfer.is_some());
+        let (action, token, usdp_amount, fee_basis_points, summary) = match decode_log(log) {
+            Some(VaultEvent::BuyUsdp(buy)) if is_account(buy.account) => {
+                (HistoryAction::MintPlp, buy.token, buy.usdp_amount, buy.fee_basis_points, summarize(&[VaultEvent::BuyUsdp(buy)], usdp))
+            }
+            Some(VaultEvent::SellUsdp(sell)) if is_account(sell.account) => {
+                (HistoryAction::RedeemPlp, sell.token, sell.usdp_amount, sell.fee_basis_points, summarize(&[VaultEvent::SellUsdp(sell)], usdp))
+            }
+            _ => continue,
+        };
+        let summary = match summary {
+            Some(summary) => summary,
+            None => continue,
+        };
+        let usd_value = usdp_to_usd(usdp_amount);
+        let fee_basis_points = U256::from(fee_basis_points).min(*BASIS_POINTS_DIVISOR - 1);
+        let fee_usd = match action {
+            // USDP is minted after the fee
+            HistoryAction::MintPlp => usd_value * fee_basis_points / (*BASIS_POINTS_DIVISOR - fee_basis_points),
+            _ => usd_value * fee_basis_points / *BASIS_POINTS_DIVISOR,
+        };
+        let (token_in, token_out, amount_in, amount_out) = match (action, plp_transfer) {
+            (HistoryAction::MintPlp, Some(plp)) => (summary.token_in, plp_token, summary.amount_in, plp.amount),
+            (HistoryAction::RedeemPlp, Some(plp)) => (plp_token, summary.token_out, plp.amount, summary.amount_out),
+            // USDP bought or sold by the account itself
+            _ => (summary.token_in, summary.token_out, summary.amount_in, summary.amount_out),
+        };
+        entries.push(HistoryEntry {
+            block_number,
+            timestamp: timestamps.get(&block_number).copied().unwrap_or_default(),
+            transaction_hash,
+            log_index,
+            action,
+            token_in: Some(token_in),
+            token_out: Some(token_out),
+            amount_in,
+            amount_out,
+            usd_value,
+            fee_amount: summary.fee_amount,
+            fee_token: Some(token),
+            fee_usd,
+            index_token: None,
+            is_long: None,
+            collateral_delta_usd: U256::zero(),
+        });
+    }
+
+    // swaps and executed position requests
+    for log in gateway_logs {
+        let (block_number, transaction_hash, log_index) = log_position(log);
+        let event = match log.topics.first().and_then(|topic| GATEWAY_CONTRACT.events().find(|event| event.signature() == *topic)) {
+            Some(event) => event,
+            None => continue,
+        };
+        let params = match event.parse_log(RawLog { topics: log.topics.clone(), data: log.data.to_vec() }) {
+            Ok(parsed) => parsed.params,
+            Err(_) => continue,
+        };
+        let param = |name: &str| params.iter().find(|param| param.name == name).map(|param| param.value.clone());
+        let uint = |name: &str| param(name).and_then(|value| value.into_uint()).unwrap_or_default();
+        let path: Vec<Address> = param("path")
+            .and_then(|value| value.into_array())
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|token| token.into_address())
+            .collect();
+        let tx_events = vault_events.get(&transaction_hash).unwrap_or(&no_events);
+        // the vault collects the fees of a position right before the gateway emits its execution
+        let position_fee = collected_fees
+            .get(&transaction_hash)
+            .and_then(|fees| fees.iter().filter(|(index, _)| *index < log_index).max_by_key(|(index, _)| *index))
+            .map(|(_, fee)| *fee);
+
+        let mut entry = HistoryEntry {
+            block_number,
+            timestamp: timestamps.get(&block_number).copied().unwrap_or_default(),
+            transaction_hash,
+            log_index,
+            action: HistoryAction::Swap,
+            token_in: path.first().copied(),
+            token_out: path.last().copied(),
+            amount_in: U256::zero(),
+            amount_out: U256::zero(),
+            usd_value: U256::zero(),
+            fee_amount: U256::zero(),
+            fee_token: None,
+            fee_usd: position_fee.unwrap_or_default(),
+            index_token: param("indexToken").and_then(|value| value.into_address()),
+            is_long: param("isLong").and_then(|value| value.into_bool()),
+            collateral_delta_usd: U256::zero(),
+        };
+        match event.name.as_str() {
+            "Swap" => {
+                let token_in = param("tokenIn").and_then(|value| value.into_address());
+                let usd_value = tx_events.iter().find_map(|event| match event {
+                    VaultEvent::IncreaseUsdpAmount(usdp) if Some(usdp.token) == token_in => Some(usdp_to_usd(usdp.amount)),
+                    _ => None,
+                });
+                let summary = summarize(tx_events, usdp).filter(|summary| summary.kind == TradeKind::Swap);
+                entry.token_in = token_in;
+                entry.token_out = param("tokenOut").and_then(|value| value.into_address());
+                entry.amount_in = uint("amountIn");
+                entry.amount_out = uint("amountOut");
+                entry.usd_value = usd_value.unwrap_or_default();
+                if let Some(summary) = summary {
+                    entry.fee_amount = summary.fee_amount;
+                    entry.fee_token = Some(summary.fee_token);
+                    entry.fee_usd = entry.usd_value * U256::from(summary.fee_basis_points) / *BASIS_POINTS_DIVISOR;
+                } else {
+                    entry.fee_usd = U256::zero();
+                }
+            }
+            "ExecuteIncreasePosition" => {
+                entry.action = HistoryAction::IncreasePosition;
+                entry.amount_in = uint("amountIn");
+                entry.usd_value = uint("sizeDelta");
+                entry.token_out = None;
+            }
+            "ExecuteDecreasePosition" => {
+                entry.action = HistoryAction::DecreasePosition;
+                entry.usd_value = uint("sizeDelta");
+                entry.collateral_delta_usd = uint("collateralDelta");
+                entry.token_in = None;
+            }
+            _ => continue,
+        }
+        entries.push(entry);
+    }
+
+    entries.sort_by_key(|entry| (entry.block_number, entry.log_index));
+    entries
+}
+
+/// Scan the vault and the gateway from `from_block` to the head and build the history of `account`.
+/// The owners of PLP manager mints and redeems come from two more scans of the PLP transfers
+/// from and to the account, no request is made per transaction. The block of each entry is fetched for its timestamp.
+#[allow(clippy::too_many_arguments)]
+pub async fn fetch_account_history<M: Middleware + 'static>(
+    provider: &M,
+    vault: Address,
+    gateway: Address,
+    plp_manager: Address,
+    plp_token: Address,
+    usdp: Address,
+    account: Address,
+    from_block: u64,
+) -> anyhow::Result<Vec<HistoryEntry>> {
+    let checkpoint = ScanCheckpoint::from_block(from_block);
+    let vault_logs = LogScanner::vault_events(vault, &VAULT_EVENTS)?
+        .with_confirmations(0)
+        .scan(provider, &checkpoint, None)
+        .await?
+        .logs;
+    let gateway_topics = GATEWAY_EVENTS
+        .iter()
+        .map(|name| Ok(GATEWAY_CONTRACT.event(name)?.signature()))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let gateway_logs = LogScanner::new(gateway, gateway_topics)
+        .with_indexed_account(account)
+        .with_confirmations(0)
+        .scan(provider, &checkpoint, None)
+        .await?
+        .logs;
+
+    // PLP minted to and burnt from the account, the PLP manager mints and redeems of these transactions are its own
+    let mut plp_transfers: HashMap<H256, PlpTransfer> = HashMap::new();
+    let scanners = [
+        LogScanner::new(plp_token, vec![*TRANSFER_TOPIC]).with_indexed_account(Address::zero()).with_second_indexed_account(account),
+        LogScanner::new(plp_token, vec![*TRANSFER_TOPIC]).with_indexed_account(account).with_second_indexed_account(Address::zero()),
+    ];
+    for scanner in scanners {
+        for log in scanner.with_confirmations(0).scan(provider, &checkpoint, None).await?.logs {
+            let amount = ethabi::decode(&[ParamType::Uint(256)], &log.data).ok().and_then(|value| value[0].clone().into_uint());
+            if let (Some(tx_hash), Some(amount)) = (log.transaction_hash, amount) {
+                plp_transfers.entry(tx_hash).or_insert(PlpTransfer { account, amount: U256::zero() }).amount += amount;
+            }
+        }
+    }
+
+    let entries = build_history(account, plp_manager, plp_token, usdp, &vault_logs, &gateway_logs, &plp_transfers, &HashMap::new());
+    let mut timestamps = HashMap::new();
+    for entry in &entries {
+        if let std::collections::hash_map::Entry::Vacant(timestamp) = timestamps.entry(entry.block_number) {
+            if let Some(block) = provider.get_block(entry.block_number).await? {
+                timestamp.insert(block.timestamp.low_u64());
+            }
+        }
+    }
+    Ok(entries
+        .into_iter()
+        .map(|entry| HistoryEntry { timestamp: timestamps.get(&entry.block_number).copied().unwrap_or_default(), ..entry })
+        .collect())
+}
+
+/// `fetch_account_history` with the contracts and rpc of `config`. Takes copies, not the router,
+/// so the router isn't borrowed during the scan.
+pub async fn fetch_config_history(config: &Config, usdp: Address, account: &str, from_block: u64) -> anyhow::Result<Vec<HistoryEntry>> {
+    let contract_address = &config.contract_address;
+    fetch_account_history(
+        &http_provider(&config.chain)?,
+        contract_address.vault.parse()?,
+        contract_address.futurx_gateway.parse()?,
+        contract_address.plp_manager.parse()?,
+        contract_address.plp_token.parse()?,
+        usdp,
+        account.parse()?,
+        from_block,
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::types::U64;
+
+    use super::*;
+    use crate::contracts::events::event_topic;
+
+    const ACCOUNT: &str = "0x1f9840a85d5af5bf1d1762f925bdaddc4201f984";
+    const PLP_MANAGER: &str = "0x1ed5051bbfa6b80f30d50d14761634ebb770f024";
+    const USDT: &str = "0x542e4676238562b518b968a1d03626d544a7bca2";
+    const BTC: &str = "0xc4900937c3222ca28cd4b300eb2575ee0868540f";
+
+    fn address(value: &str) -> ethabi::Token {
+        ethabi::Token::Address(value.parse().unwrap())
+    }
+
+    fn uint(value: U256) -> ethabi::Token {
+        ethabi::Token::Uint(value)
+    }
+
+    fn create_log(topics: Vec<H256>, params: &[ethabi::Token], block: u64, tx: u8, index: u64) -> Log {
+        Log {
+            topics,
+            data: ethabi::encode(params).into(),
+            block_number: Some(U64::from(block)),
+            transaction_hash: Some(H256::repeat_byte(tx)),
+            log_index: Some(U256::from(index)),
+            ..Log::default()
+        }
+    }
+
+    fn gateway_topics(name: &str) -> Vec<H256> {
+        let account: Address = ACCOUNT.parse().unwrap();
+        vec![GATEWAY_CONTRACT.event(name).unwrap().signature(), H256::from(account)]
+    }
+
+    #[test]
+    fn build_history_works() {
+        let account: Address = ACCOUNT.parse().unwrap();
+        let plp_manager: Address = PLP_MANAGER.parse().unwrap();
+        let (plp_token, usdp) = (Address::repeat_byte(3), Address::repeat_byte(2));
+        let vault_logs = vec![
+            // mint through the PLP manager, sent by the account
+            create_log(vec![event_topic("BuyUSDP").unwrap()], &[address(PLP_MANAGER), address(USDT), uint(U256::exp10(20)), uint(U256::exp10(18) * 997 / 10), uint(U256::from(30))], 10, 1, 0),
+            // mint of another account
+            create_log(vec![event_topic("BuyUSDP").unwrap()], &[address(PLP_MANAGER), address(USDT), uint(U256::exp10(20)), uint(U256::exp10(20)), uint(U256::from(30))], 11, 2, 0),
+            // swap 1000 USDT for BTC
+            create_log(vec![event_topic("IncreaseUsdgAmount").unwrap()], &[address(USDT), uint(U256::exp10(21))], 12, 3, 0),
+            create_log(vec![event_topic("Swap").unwrap()], &[address(ACCOUNT), address(USDT), address(BTC), uint(U256::exp10(21)), uint(U256::exp10(17) * 5), uint(U256::exp10(15) * 499), uint(U256::from(20))], 12, 3, 1),
+            // fees of another account's request executed in the same transaction
+            create_log(vec![event_topic("CollectFees").unwrap()], &[uint(U256::exp10(30) * 5), uint(U256::zero()), uint(U256::exp10(30) * 5)], 13, 4, 0),
+            create_log(vec![event_topic("CollectFees").unwrap()], &[uint(U256::exp10(30)), uint(U256::zero()), uint(U256::exp10(30))], 13, 4, 2),
+            // redeem of 10 PLP for USDT through the PLP manager
+            create_log(vec![event_topic("SellUSDP").unwrap()], &[address(PLP_MANAGER), address(USDT), uint(U256::exp10(19)), uint(U256::exp10(17) * 99), uint(U256::from(100))], 14, 5, 0),
+        ];
+        let gateway_logs = vec![
+            create_log(gateway_topics("Swap"), &[address(USDT), address(BTC), uint(U256::exp10(21)), uint(U256::exp10(15) * 499)], 12, 3, 2),
+            create_log(gateway_topics("ExecuteIncreasePosition"), &[
+                ethabi::Token::Array(vec![address(USDT)]),
+                address(BTC),
+                uint(U256::exp10(20)),
+                uint(U256::zero()),
+                uint(U256::exp10(33)),
+                ethabi::Token::Bool(true),
+                uint(U256::zero()),
+                uint(U256::zero()),
+                uint(U256::zero()),
+                uint(U256::zero()),
+            ], 13, 4, 3),
+        ];
+        let plp_transfers = HashMap::from([
+            (H256::repeat_byte(1), PlpTransfer { account, amount: U256::exp10(18) * 66 }),
+            (H256::repeat_byte(2), PlpTransfer { account: Address::repeat_byte(9), amount: U256::exp10(18) }),
+            (H256::repeat_byte(5), PlpTransfer { account, amount: U256::exp10(18) * 10 }),
+        ]);
+        let timestamps = HashMap::from([(10, 1_000), (12, 1_024)]);
+
+        let history = build_history(account, plp_manager, plp_token, usdp, &vault_logs, &gateway_logs, &plp_transfers, &timestamps);
+        assert_eq!(
+            history.iter().map(|entry| entry.action).collect::<Vec<_>>(),
+            vec![HistoryAction::MintPlp, HistoryAction::Swap, HistoryAction::IncreasePosition, HistoryAction::RedeemPlp]
+        );
+
+        let mint = &history[0];
+        assert_eq!(mint.timestamp, 1_000);
+        // the account receives PLP, the USDP stays in the PLP manager
+        assert_eq!(mint.token_in, Some(USDT.parse().unwrap()));
+        assert_eq!(mint.amount_in, U256::exp10(20));
+        assert_eq!(mint.token_out, Some(plp_token));
+        assert_eq!(mint.amount_out, U256::exp10(18) * 66);
+        assert_eq!(mint.usd_value, U256::exp10(29) * 997);
+        // 99.7 USD minted after a 0.3% fee
+        assert_eq!(mint.fee_usd, U256::exp10(29) * 3);
+        assert_eq!(mint.fee_amount, U256::exp10(17) * 3);
+
+        let swap = &history[1];
+        assert_eq!(swap.amount_out, U256::exp10(15) * 499);
+        assert_eq!(swap.usd_value, U256::exp10(33));
+        assert_eq!(swap.fee_token, Some(BTC.parse().unwrap()));
+        assert_eq!(swap.fee_usd, U256::exp10(30) * 2);
+
+        let position = &history[2];
+        assert_eq!(position.usd_value, U256::exp10(33));
+        assert_eq!(position.fee_usd, U256::exp10(30));
+        assert_eq!(position.is_long, Some(true));
+        assert_eq!(position.index_token, Some(BTC.parse().unwrap()));
+
+        let redeem = &history[3];
+        assert_eq!(redeem.token_in, Some(plp_token));
+        assert_eq!(redeem.amount_in, U256::exp10(18) * 10);
+        assert_eq!(redeem.token_out, Some(USDT.parse().unwrap()));
+        assert_eq!(redeem.amount_out, U256::exp10(17) * 99);
+    }
+}
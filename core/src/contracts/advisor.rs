@@ -0,0 +1,108 @@
+use ethabi::ethereum_types::U256;
+use serde::{Deserialize, Serialize};
+
+use super::quote::{usd_to_plp_amount, usd_to_token_amount, Quote};
+use super::token::Token;
+use super::vault::VaultState;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PlpAdvice {
+    // cheapest available quote, None when no token can take the trade
+    pub best: Option<Quote>,
+    // every tradeable token, available quotes first, then by cost
+    pub ranking: Vec<Quote>,
+}
+
+impl PlpAdvice {
+    fn new(mut ranking: Vec<Quote>) -> Self {
+        ranking.sort_by(|a, b| {
+            b.is_available
+                .cmp(&a.is_available)
+                .then(a.cost_basis_points.cmp(&b.cost_basis_points))
+        });
+        let best = ranking.first().filter(|quote| quote.is_available).cloned();
+        Self { best, ranking }
+    }
+}
+
+impl VaultState {
+    /// Rank every tradeable token by the effective cost of minting `usd_amount` (30 decimals) of PLP.
+    /// The cost includes the dynamic fee and the price spread, tokens over `max_usdp_amount` are
+    /// kept in the ranking but marked unavailable.
+    pub fn advise_buy_plp(&self, usd_amount: U256, tokens: &[Token]) -> PlpAdvice {
+        let ranking = tokens
+            .iter()
+            .filter(|token| token.is_tradeable.unwrap_or(false))
+            .map(|token| {
+                let min_price = token.min_price.map(|price| price.raw).unwrap_or_default();
+                let amount_in = usd_to_token_amount(usd_amount, min_price, token.decimals);
+                self.quote_buy_plp(token, amount_in)
+            })
+            .collect();
+        PlpAdvice::new(ranking)
+    }
+
+    /// Rank every tradeable token by the effective cost of redeeming `usd_amount` (30 decimals) of PLP.
+    /// Tokens without enough `available_amount` are kept in the ranking but marked unavailable.
+    pub fn advise_sell_plp(&self, usd_amount: U256, tokens: &[Token]) -> PlpAdvice {
+        let plp_amount = usd_to_plp_amount(self, usd_amount);
+        let ranking = tokens
+            .iter()
+            .filter(|token| token.is_tradeable.unwrap_or(false))
+            .map(|token| self.quote_sell_plp(token, plp_amount))
+            .collect();
+        PlpAdvice::new(ranking)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contracts::fixtures::{create_state, create_token, BTC, USDT};
+    use crate::contracts::vault_logic::expand_decimals;
+
+    fn create_tokens() -> Vec<Token> {
+        vec![
+            // over weight
+            create_token(USDT, "USDT", 700),
+            // under weight
+            create_token(BTC, "BTC", 300),
+        ]
+    }
+
+    #[test]
+    fn advise_buy_plp_prefers_under_weight_token() {
+        let state = create_state();
+        let advice = state.advise_buy_plp(expand_decimals(10, 30), &create_tokens());
+        assert_eq!(advice.ranking.len(), 2);
+        let best = advice.best.expect("no best quote");
+        assert_eq!(best.token_in, BTC);
+        assert!(best.fee_basis_points < advice.ranking[1].fee_basis_points);
+    }
+
+    #[test]
+    fn advise_sell_plp_prefers_over_weight_token() {
+        let state = create_state();
+        let advice = state.advise_sell_plp(expand_decimals(10, 30), &create_tokens());
+        let best = advice.best.expect("no best quote");
+        assert_eq!(best.token_out, USDT);
+    }
+
+    #[test]
+    fn advise_buy_plp_ranks_capped_tokens_last() {
+        let state = create_state();
+        let mut tokens = create_tokens();
+        tokens[1].max_usdp_amount = Some(expand_decimals(305, 18));
+        let advice = state.advise_buy_plp(expand_decimals(10, 30), &tokens);
+        assert_eq!(advice.best.expect("no best quote").token_in, USDT);
+        assert!(!advice.ranking[1].is_available);
+    }
+
+    #[test]
+    fn advise_returns_no_best_when_nothing_available() {
+        let state = create_state();
+        let advice = state.advise_sell_plp(expand_decimals(5000, 30), &create_tokens());
+        assert!(advice.best.is_none());
+        assert_eq!(advice.ranking.len(), 2);
+    }
+}
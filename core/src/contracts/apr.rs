@@ -0,0 +1,173 @@
+use ethers::types::{Address, U256};
+use serde::{Deserialize, Serialize};
+
+use super::staking::RewardTrackerInfo;
+use super::token::Token;
+use super::vault::VaultState;
+use super::vault_logic::{expand_decimals, VaultLogic, BASIS_POINTS_DIVISOR};
+
+const SECONDS_PER_YEAR: u64 = 365 * 24 * 60 * 60;
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+#[derive(Default, Debug, Serialize, Deserialize, Clone)]
+pub struct TrackerApr {
+    pub reward_tracker: Address,
+    pub reward_token: Address,
+    pub apr_basis_points: u64,
+    // total_supply valued at the PLP sell price, 30 decimals
+    pub staked_usd: U256,
+    // rewards distributed to all stakers, reward token decimals and USD 30 decimals
+    pub pool_rewards_per_day: U256,
+    pub pool_rewards_usd_per_day: U256,
+    // share of the pool rewards of the account, zero without account
+    pub account_rewards_per_day: U256,
+    pub account_rewards_usd_per_day: U256,
+}
+
+#[derive(Default, Debug, Serialize, Deserialize, Clone)]
+pub struct StakingApr {
+    pub trackers: Vec<TrackerApr>,
+    // sum of the tracker APRs
+    pub apr_basis_points: u64,
+    pub pool_rewards_usd_per_day: U256,
+    pub account_rewards_usd_per_day: U256,
+}
+
+impl VaultState {
+    /// APR of staked PLP for every reward tracker, from `tokensPerInterval` and the reward token
+    /// price in `tokens`. Rewards of `account` are projected from its share of the tracker supply.
+    pub fn staking_apr(&self, tokens: &[Token], account: Option<&Address>) -> StakingApr {
+        let plp_price = self.get_plp_price(false);
+        let staking_info = account.map(|account| self.get_staking_info(account)).unwrap_or_default();
+
+        let trackers: Vec<TrackerApr> = self
+            .reward_trackers
+            .iter()
+            .map(|tracker| {
+                let account_staked = staking_info
+                    .iter()
+                    .find(|staking| staking.reward_tracker == tracker.address)
+                    .map(|staking| staking.staked_amount)
+                    .unwrap_or_default();
+                tracker_apr(tracker, tokens, plp_price, account_staked)
+            })
+            .collect();
+
+        StakingApr {
+            apr_basis_points: trackers.iter().map(|tracker| tracker.apr_basis_points).sum(),
+            pool_rewards_usd_per_day: trackers.iter().fold(U256::zero(), |sum, tracker| sum + tracker.pool_rewards_usd_per_day),
+            account_rewards_usd_per_day: trackers.iter().fold(U256::zero(), |sum, tracker| sum + tracker.account_rewards_usd_per_day),
+            trackers,
+        }
+    }
+}
+
+fn tracker_apr(tracker: &RewardTrackerInfo, tokens: &[Token], plp_price: U256, account_staked: U256) -> TrackerApr {
+    let reward_token = tokens
+        .iter()
+        .find(|token| token.address.parse::<Address>().map(|address| address == tracker.reward_token).unwrap_or(false));
+    let (reward_price, reward_decimals) = match reward_token {
+        Some(token) => (token.min_price.map(|price| price.raw).unwrap_or_default(), token.decimals as u32),
+        None => (U256::zero(), 18),
+    };
+    let to_usd = |amount: U256| amount * reward_price / expand_decimals(1, reward_decimals);
+
+    // ^18 * ^18 * ^12 / ^18 = ^30
+    let staked_usd = tracker.total_supply * plp_price * expand_decimals(1, 12) / expand_decimals(1, 18);
+    let yearly_rewards_usd = to_usd(tracker.tokens_per_interval * U256::from(SECONDS_PER_YEAR));
+    let apr_basis_points = if staked_usd.is_zero() {
+        0
+    } else {
+        (yearly_rewards_usd * *BASIS_POINTS_DIVISOR / staked_usd).low_u64()
+    };
+
+    let pool_rewards_per_day = tracker.tokens_per_interval * U256::from(SECONDS_PER_DAY);
+    let account_rewards_per_day = if tracker.total_supply.is_zero() {
+        U256::zero()
+    } else {
+        pool_rewards_per_day * account_staked / tracker.total_supply
+    };
+    TrackerApr {
+        reward_tracker: tracker.address,
+        reward_token: tracker.reward_token,
+        apr_basis_points,
+        staked_usd,
+        pool_rewards_per_day,
+        pool_rewards_usd_per_day: to_usd(pool_rewards_per_day),
+        account_rewards_per_day,
+        account_rewards_usd_per_day: to_usd(account_rewards_per_day),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use super::*;
+    use crate::contracts::staking::StakingInfo;
+    use crate::contracts::token::Price;
+
+    const REWARD_TRACKER: &str = "0x50c121da29cbd8bd96bdb6c6965f93f09c2fc598";
+    const WETH: &str = "0xc4900937c3222ca28cd4b300eb2575ee0868540f";
+    const ACCOUNT: &str = "0x1f9840a85d5af5bf1d1762f925bdaddc4201f984";
+
+    fn create_state() -> VaultState {
+        let mut state = VaultState {
+            usdp_supply: expand_decimals(1_000_000, 18),
+            // PLP at 1 USD
+            total_aum: [expand_decimals(1_000_000, 30), expand_decimals(1_000_000, 30)],
+            plp_supply: expand_decimals(1_000_000, 18),
+            ..VaultState::default()
+        };
+        let tracker = RewardTrackerInfo {
+            address: REWARD_TRACKER.parse().unwrap(),
+            reward_token: WETH.parse().unwrap(),
+            // 0.001 WETH per second
+            tokens_per_interval: expand_decimals(1, 15),
+            total_supply: expand_decimals(1_000_000, 18),
+        };
+        let staking = StakingInfo {
+            reward_tracker: tracker.address,
+            staked_amount: expand_decimals(10_000, 18),
+            ..StakingInfo::default()
+        };
+        state.update_staking(Some(ACCOUNT.parse().unwrap()), vec![(tracker, Some(staking))]);
+        state
+    }
+
+    fn create_tokens() -> Vec<Token> {
+        let mut weth = Token::new(97, WETH, "Wrapped Ether", "WETH", 18, "");
+        weth.min_price = Some(Price::new(Decimal::from(2000)));
+        vec![weth]
+    }
+
+    #[test]
+    fn staking_apr_works() {
+        let state = create_state();
+        let account: Address = ACCOUNT.parse().unwrap();
+        let apr = state.staking_apr(&create_tokens(), Some(&account));
+        assert_eq!(apr.trackers.len(), 1);
+        let tracker = &apr.trackers[0];
+        // 31536 WETH * 2000 USD / 1M USD staked = 6307.2%
+        assert_eq!(tracker.apr_basis_points, 630720);
+        assert_eq!(apr.apr_basis_points, 630720);
+        assert_eq!(tracker.staked_usd, expand_decimals(1_000_000, 30));
+        assert_eq!(tracker.pool_rewards_per_day, expand_decimals(86400, 15));
+        assert_eq!(apr.pool_rewards_usd_per_day, expand_decimals(172800, 30));
+        // 1% of the supply
+        assert_eq!(tracker.account_rewards_per_day, expand_decimals(864, 15));
+        assert_eq!(apr.account_rewards_usd_per_day, expand_decimals(1728, 30));
+    }
+
+    #[test]
+    fn staking_apr_without_account_or_price() {
+        let state = create_state();
+        let apr = state.staking_apr(&create_tokens(), None);
+        assert!(apr.account_rewards_usd_per_day.is_zero());
+        assert_eq!(apr.apr_basis_points, 630720);
+
+        let apr = state.staking_apr(&[], None);
+        assert_eq!(apr.apr_basis_points, 0);
+        assert_eq!(apr.trackers[0].pool_rewards_per_day, expand_decimals(86400, 15));
+    }
+}
@@ -0,0 +1,219 @@
+use ethabi::ethereum_types::U256;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use super::token::Token;
+use super::vault::VaultState;
+use super::vault_logic::{expand_decimals, VaultLogic};
+
+const USDP_DECIMALS: u32 = 18;
+// weights are expressed in percent with 4 decimals
+const WEIGHT_SCALE: u32 = 4;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum FeeImpact {
+    Rebate,
+    Neutral,
+    Tax,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TokenComposition {
+    pub address: String,
+    pub symbol: String,
+    // target share of the pool in percent, tokenWeight / totalTokenWeight
+    pub target_weight: Decimal,
+    // current share of the pool in percent, usdpAmount / usdpSupply
+    pub current_weight: Decimal,
+    // current_weight - target_weight, negative when the token is under weight
+    pub deviation: Decimal,
+    // pool amount valued at min price, 30 decimals
+    pub usd_value: U256,
+    // fee for a marginal deposit / withdrawal of this token
+    pub buy_fee_basis_points: u32,
+    pub sell_fee_basis_points: u32,
+    pub deposit_impact: FeeImpact,
+    pub withdraw_impact: FeeImpact,
+}
+
+impl VaultState {
+    /// Current vs target weight of every tradeable token in the pool.
+    /// The fee basis points are computed for a 1 USDP deposit / withdrawal, so they show
+    /// whether the next mint or redeem with the token earns a rebate or pays tax.
+    /// Tokens without weight (delisted or vault info not fetched) aren't in the pool and are skipped.
+    pub fn composition(&self, tokens: &[Token]) -> Vec<TokenComposition> {
+        let probe = expand_decimals(1, USDP_DECIMALS);
+        let base_fee_basis_points = self.mint_burn_fee_basis_points.low_u32();
+        tokens
+            .iter()
+            .filter(|token| token.is_tradeable.unwrap_or(false) && token.token_weight.unwrap_or(0) > 0)
+            .map(|token| {
+                let token_weight = token.token_weight.unwrap_or(0);
+                let usdp_amount = token.usdp_amount.unwrap_or(U256::zero());
+
+                let target_weight = to_weight(U256::from(token_weight), self.total_token_weights);
+                let current_weight = to_weight(usdp_amount, self.usdp_supply);
+
+                let usd_value = match (token.pool_amounts, token.min_price) {
+                    (Some(pool_amounts), Some(min_price)) => {
+                        pool_amounts * min_price.raw / expand_decimals(1, token.decimals as u32)
+                    }
+                    _ => U256::zero(),
+                };
+
+                let buy_fee_basis_points = self.get_fee_basis_points(token_weight, &usdp_amount, &probe, true);
+                let sell_fee_basis_points = self.get_fee_basis_points(token_weight, &usdp_amount, &probe, false);
+
+                TokenComposition {
+                    address: token.address.clone(),
+                    symbol: token.symbol.clone(),
+                    target_weight,
+                    current_weight,
+                    deviation: current_weight - target_weight,
+                    usd_value,
+                    buy_fee_basis_points,
+                    sell_fee_basis_points,
+                    deposit_impact: fee_impact(buy_fee_basis_points, base_fee_basis_points),
+                    withdraw_impact: fee_impact(sell_fee_basis_points, base_fee_basis_points),
+                }
+            })
+            .collect()
+    }
+
+    /// Total pool value of the given compositions, 30 decimals
+    pub fn composition_usd_value(compositions: &[TokenComposition]) -> U256 {
+        compositions.iter().fold(U256::zero(), |total, c| total + c.usd_value)
+    }
+}
+
+/// Sort for the "Buy PLP" page: lowest deposit fee first, most under weight token on ties
+pub fn sort_by_cheapest_entry(compositions: &mut [TokenComposition]) {
+    compositions.sort_by(|a, b| {
+        a.buy_fee_basis_points
+            .cmp(&b.buy_fee_basis_points)
+            .then(a.deviation.cmp(&b.deviation))
+    });
+}
+
+/// Sort for the "Sell PLP" page: lowest withdrawal fee first, most over weight token on ties
+pub fn sort_by_cheapest_exit(compositions: &mut [TokenComposition]) {
+    compositions.sort_by(|a, b| {
+        a.sell_fee_basis_points
+            .cmp(&b.sell_fee_basis_points)
+            .then(b.deviation.cmp(&a.deviation))
+    });
+}
+
+fn to_weight(amount: U256, total: U256) -> Decimal {
+    if total.is_zero() {
+        return Decimal::ZERO;
+    }
+    let scaled = amount * U256::from(100) * expand_decimals(1, WEIGHT_SCALE) / total;
+    Decimal::new(scaled.low_u64() as i64, WEIGHT_SCALE)
+}
+
+fn fee_impact(fee_basis_points: u32, base_fee_basis_points: u32) -> FeeImpact {
+    if fee_basis_points < base_fee_basis_points {
+        FeeImpact::Rebate
+    } else if fee_basis_points > base_fee_basis_points {
+        FeeImpact::Tax
+    } else {
+        FeeImpact::Neutral
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::contracts::token::Price;
+
+    fn create_state() -> VaultState {
+        let mut state = VaultState::default();
+        state.usdp_supply = expand_decimals(1000, 18);
+        state.total_token_weights = U256::from(100);
+        state.mint_burn_fee_basis_points = U256::from(30);
+        state.tax_basis_points = 50;
+        state.has_dynamic_fees = true;
+        state
+    }
+
+    fn create_token(address: &str, symbol: &str, weight: u64, usdp_amount: u32) -> Token {
+        let mut token = Token::new(97, address, symbol, symbol, 18, "");
+        token.is_tradeable = Some(true);
+        token.token_weight = Some(weight);
+        token.usdp_amount = Some(expand_decimals(usdp_amount, 18));
+        token.pool_amounts = Some(expand_decimals(usdp_amount, 18));
+        token.min_price = Some(Price::new(dec!(1)));
+        token
+    }
+
+    fn create_tokens() -> Vec<Token> {
+        vec![
+            // target 50%, current 70%
+            create_token("0x542e4676238562b518b968a1d03626d544a7bca2", "USDT", 50, 700),
+            // target 50%, current 30%
+            create_token("0xc4900937c3222ca28cd4b300eb2575ee0868540f", "BTC", 50, 300),
+        ]
+    }
+
+    #[test]
+    fn composition_works() {
+        let state = create_state();
+        let composition = state.composition(&create_tokens());
+        assert_eq!(composition.len(), 2);
+
+        assert_eq!(composition[0].target_weight, dec!(50));
+        assert_eq!(composition[0].current_weight, dec!(70));
+        assert_eq!(composition[0].deviation, dec!(20));
+        assert_eq!(composition[0].usd_value, expand_decimals(700, 30));
+        assert_eq!(composition[0].deposit_impact, FeeImpact::Tax);
+        assert_eq!(composition[0].withdraw_impact, FeeImpact::Rebate);
+
+        assert_eq!(composition[1].current_weight, dec!(30));
+        assert_eq!(composition[1].deviation, dec!(-20));
+        assert_eq!(composition[1].deposit_impact, FeeImpact::Rebate);
+        assert_eq!(composition[1].withdraw_impact, FeeImpact::Tax);
+
+        assert_eq!(VaultState::composition_usd_value(&composition), expand_decimals(1000, 30));
+    }
+
+    #[test]
+    fn composition_skips_non_tradeable_tokens() {
+        let state = create_state();
+        let mut tokens = create_tokens();
+        tokens[1].is_tradeable = Some(false);
+        assert_eq!(state.composition(&tokens).len(), 1);
+    }
+
+    #[test]
+    fn composition_skips_zero_weight_tokens() {
+        let state = create_state();
+        let mut tokens = create_tokens();
+        // delisted, the fee of a zero target amount is undefined
+        tokens[1].token_weight = Some(0);
+        let composition = state.composition(&tokens);
+        assert_eq!(composition.len(), 1);
+        assert_eq!(composition[0].symbol, "USDT");
+    }
+
+    #[test]
+    fn composition_is_neutral_without_dynamic_fees() {
+        let mut state = create_state();
+        state.has_dynamic_fees = false;
+        let composition = state.composition(&create_tokens());
+        assert!(composition.iter().all(|c| c.deposit_impact == FeeImpact::Neutral));
+        assert!(composition.iter().all(|c| c.buy_fee_basis_points == 30));
+    }
+
+    #[test]
+    fn sort_by_cheapest_entry_works() {
+        let state = create_state();
+        let mut composition = state.composition(&create_tokens());
+        sort_by_cheapest_entry(&mut composition);
+        assert_eq!(composition[0].symbol, "BTC");
+        sort_by_cheapest_exit(&mut composition);
+        assert_eq!(composition[0].symbol, "USDT");
+    }
+}
@@ -0,0 +1,385 @@
+use ethabi::{Contract, LogParam, RawLog};
+use ethers::types::{Address, Log, TransactionReceipt, H256, U256};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+
+use super::quote::{Quote, QuoteKind};
+use super::vault_logic::BASIS_POINTS_DIVISOR;
+
+lazy_static! {
+    static ref VAULT_CONTRACT: Contract = Contract::load(include_str!("../../abi/vault.json").as_bytes()).expect("Invalid vault ABI");
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct BuyUsdpEvent {
+    pub account: Address,
+    pub token: Address,
+    // deposited, fees included
+    pub token_amount: U256,
+    pub usdp_amount: U256,
+    pub fee_basis_points: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct SellUsdpEvent {
+    pub account: Address,
+    pub token: Address,
+    pub usdp_amount: U256,
+    // received, after fees
+    pub token_amount: U256,
+    pub fee_basis_points: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct SwapEvent {
+    pub account: Address,
+    pub token_in: Address,
+    pub token_out: Address,
+    pub amount_in: U256,
+    pub amount_out: U256,
+    pub amount_out_after_fees: U256,
+    pub fee_basis_points: u64,
+}
+
+/// Fees of a position change, USD 30 decimals
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct CollectFeesEvent {
+    pub position_fee: U256,
+    pub borrow_fee: U256,
+    pub total_fee: U256,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct TokenAmountEvent {
+    pub token: Address,
+    pub amount: U256,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct AccountAmountEvent {
+    pub account: Address,
+    pub amount: U256,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct UpdateBorrowingRateEvent {
+    pub token: Address,
+    pub borrowing_rate: U256,
+}
+
+/// Events of vault.json, admin events (Initialized, OwnershipTransferred, WhitelistCallerChanged) are skipped
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum VaultEvent {
+    BuyUsdp(BuyUsdpEvent),
+    SellUsdp(SellUsdpEvent),
+    Swap(SwapEvent),
+    CollectFees(CollectFeesEvent),
+    IncreasePoolAmount(TokenAmountEvent),
+    DecreasePoolAmount(TokenAmountEvent),
+    IncreaseReservedAmount(TokenAmountEvent),
+    DecreaseReservedAmount(TokenAmountEvent),
+    IncreaseUsdpAmount(TokenAmountEvent),
+    DecreaseUsdpAmount(TokenAmountEvent),
+    IncreaseFeeReserves(TokenAmountEvent),
+    IncreaseGuaranteedUsd(TokenAmountEvent),
+    DecreaseGuaranteedUsd(TokenAmountEvent),
+    IncreaseDebtAmount(AccountAmountEvent),
+    DecreaseDebtAmount(AccountAmountEvent),
+    IncreasePositionReserves(U256),
+    DecreasePositionReserves(U256),
+    UpdateBorrowingRate(UpdateBorrowingRateEvent),
+}
+
+fn address(params: &[LogParam], index: usize) -> Option<Address> {
+    params.get(index)?.value.clone().into_address()
+}
+
+fn uint(params: &[LogParam], index: usize) -> Option<U256> {
+    params.get(index)?.value.clone().into_uint()
+}
+
+fn token_amount(params: &[LogParam]) -> Option<TokenAmountEvent> {
+    Some(TokenAmountEvent { token: address(params, 0)?, amount: uint(params, 1)? })
+}
+
+fn account_amount(params: &[LogParam]) -> Option<AccountAmountEvent> {
+    Some(AccountAmountEvent { account: address(params, 0)?, amount: uint(params, 1)? })
+}
+
+/// Topic0 of a vault event, eg: `event_topic("Swap")`
+pub fn event_topic(name: &str) -> Option<H256> {
+    VAULT_CONTRACT.event(name).ok().map(|event| event.signature())
+}
+
+/// Typed vault event of a log, None for other contracts' events and skipped events
+pub fn decode_log(log: &Log) -> Option<VaultEvent> {
+    let topic = *log.topics.first()?;
+    let event = VAULT_CONTRACT.events().find(|event| event.signature() == topic)?;
+    let params = event
+        .parse_log(RawLog { topics: log.topics.clone(), data: log.data.to_vec() })
+        .ok()?
+        .params;
+    let params = params.as_slice();
+    let event = match event.name.as_str() {
+        "BuyUSDP" => VaultEvent::BuyUsdp(BuyUsdpEvent {
+            account: address(params, 0)?,
+            token: address(params, 1)?,
+            token_amount: uint(params, 2)?,
+            usdp_amount: uint(params, 3)?,
+            fee_basis_points: uint(params, 4)?.low_u64(),
+        }),
+        "SellUSDP" => VaultEvent::SellUsdp(SellUsdpEvent {
+            account: address(params, 0)?,
+            token: address(params, 1)?,
+            usdp_amount: uint(params, 2)?,
+            token_amount: uint(params, 3)?,
+            fee_basis_points: uint(params, 4)?.low_u64(),
+        }),
+        "Swap" => VaultEvent::Swap(SwapEvent {
+            account: address(params, 0)?,
+            token_in: address(params, 1)?,
+            token_out: address(params, 2)?,
+            amount_in: uint(params, 3)?,
+            amount_out: uint(params, 4)?,
+            amount_out_after_fees: uint(params, 5)?,
+            fee_basis_points: uint(params, 6)?.low_u64(),
+        }),
+        "CollectFees" => VaultEvent::CollectFees(CollectFeesEvent {
+            position_fee: uint(params, 0)?,
+            borrow_fee: uint(params, 1)?,
+            total_fee: uint(params, 2)?,
+        }),
+        "IncreasePoolAmount" => VaultEvent::IncreasePoolAmount(token_amount(params)?),
+        "DecreasePoolAmount" => VaultEvent::DecreasePoolAmount(token_amount(params)?),
+        "IncreaseReservedAmount" => VaultEvent::IncreaseReservedAmount(token_amount(params)?),
+        "DecreaseReservedAmount" => VaultEvent::DecreaseReservedAmount(token_amount(params)?),
+        "IncreaseUsdgAmount" => VaultEvent::IncreaseUsdpAmount(token_amount(params)?),
+        "DecreaseUsdgAmount" => VaultEvent::DecreaseUsdpAmount(token_amount(params)?),
+        "IncreaseFeeReserves" => VaultEvent::IncreaseFeeReserves(token_amount(params)?),
+        "IncreaseGuaranteedUsd" => VaultEvent::IncreaseGuaranteedUsd(token_amount(params)?),
+        "DecreaseGuaranteedUsd" => VaultEvent::DecreaseGuaranteedUsd(token_amount(params)?),
+        "IncreaseDebtAmount" => VaultEvent::IncreaseDebtAmount(account_amount(params)?),
+        "DecreaseDebtAmount" => VaultEvent::DecreaseDebtAmount(account_amount(params)?),
+        "IncreasePositionReserves" => VaultEvent::IncreasePositionReserves(uint(params, 0)?),
+        "DecreasePositionReserves" => VaultEvent::DecreasePositionReserves(uint(params, 0)?),
+        "UpdateBorrowingRate" => VaultEvent::UpdateBorrowingRate(UpdateBorrowingRateEvent {
+            token: address(params, 0)?,
+            borrowing_rate: uint(params, 1)?,
+        }),
+        _ => return None,
+    };
+    Some(event)
+}
+
+/// Vault events of a receipt in log order, logs of other contracts are ignored
+pub fn decode_receipt(receipt: &TransactionReceipt, vault: Address) -> Vec<VaultEvent> {
+    receipt
+        .logs
+        .iter()
+        .filter(|log| log.address == vault)
+        .filter_map(decode_log)
+        .collect()
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum TradeKind {
+    BuyUsdp,
+    SellUsdp,
+    Swap,
+}
+
+/// Executed amounts of a confirmed mint, redeem or swap, in the decimals of their token
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct TradeSummary {
+    pub kind: TradeKind,
+    pub account: Address,
+    pub token_in: Address,
+    pub token_out: Address,
+    pub amount_in: U256,
+    // received, after fees
+    pub amount_out: U256,
+    pub fee_basis_points: u64,
+    // in `fee_token`, from IncreaseFeeReserves when the vault emitted it
+    pub fee_amount: U256,
+    pub fee_token: Address,
+}
+
+impl TradeSummary {
+    /// Executed vs quoted amount out, negative when less was received.
+    /// None when the quote isn't for the same trade, BuyPlp quotes are in PLP and can't be compared to USDP.
+    pub fn amount_out_diff_basis_points(&self, quote: &Quote) -> Option<i64> {
+        let same_kind = matches!((self.kind, quote.kind), (TradeKind::Swap, QuoteKind::Swap) | (TradeKind::SellUsdp, QuoteKind::SellPlp));
+        let token_out: Address = quote.token_out.parse().ok()?;
+        if !same_kind || token_out != self.token_out || quote.amount_out.is_zero() {
+            return None;
+        }
+        let diff = |a: U256, b: U256| ((a - b) * *BASIS_POINTS_DIVISOR / quote.amount_out).low_u64() as i64;
+        if self.amount_out >= quote.amount_out {
+            Some(diff(self.amount_out, quote.amount_out))
+        } else {
+            Some(-diff(quote.amount_out, self.amount_out))
+        }
+    }
+}
+
+/// Summary of the first mint, redeem or swap of `events`, see `decode_receipt`
+pub fn summarize(events: &[VaultEvent], usdp: Address) -> Option<TradeSummary> {
+    let fee_reserves = |token: Address| {
+        events.iter().find_map(|event| match event {
+            VaultEvent::IncreaseFeeReserves(fee) if fee.token == token => Some(fee.amount),
+            _ => None,
+        })
+    };
+    let summary = events.iter().find_map(|event| match event {
+        VaultEvent::BuyUsdp(buy) => Some(TradeSummary {
+            kind: TradeKind::BuyUsdp,
+            account: buy.account,
+            token_in: buy.token,
+            token_out: usdp,
+            amount_in: buy.token_amount,
+            amount_out: buy.usdp_amount,
+            fee_basis_points: buy.fee_basis_points,
+            // the fee is taken from the deposit
+            fee_amount: buy.token_amount * U256::from(buy.fee_basis_points) / *BASIS_POINTS_DIVISOR,
+            fee_token: buy.token,
+        }),
+        VaultEvent::SellUsdp(sell) => {
+            let fee_basis_points = U256::from(sell.fee_basis_points).min(*BASIS_POINTS_DIVISOR - 1);
+            Some(TradeSummary {
+                kind: TradeKind::SellUsdp,
+                account: sell.account,
+                token_in: usdp,
+                token_out: sell.token,
+                amount_in: sell.usdp_amount,
+                amount_out: sell.token_amount,
+                fee_basis_points: sell.fee_basis_points,
+                // the event amount is after fees
+                fee_amount: sell.token_amount * fee_basis_points / (*BASIS_POINTS_DIVISOR - fee_basis_points),
+                fee_token: sell.token,
+            })
+        }
+        VaultEvent::Swap(swap) => Some(TradeSummary {
+            kind: TradeKind::Swap,
+            account: swap.account,
+            token_in: swap.token_in,
+            token_out: swap.token_out,
+            amount_in: swap.amount_in,
+            amount_out: swap.amount_out_after_fees,
+            fee_basis_points: swap.fee_basis_points,
+            fee_amount: swap.amount_out.saturating_sub(swap.amount_out_after_fees),
+            fee_token: swap.token_out,
+        }),
+        _ => None,
+    })?;
+    Some(TradeSummary {
+        fee_amount: fee_reserves(summary.fee_token).unwrap_or(summary.fee_amount),
+        ..summary
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VAULT: &str = "0x1ed5051bbfa6b80f30d50d14761634ebb770f024";
+    const ACCOUNT: &str = "0x1f9840a85d5af5bf1d1762f925bdaddc4201f984";
+    const USDT: &str = "0x542e4676238562b518b968a1d03626d544a7bca2";
+    const BTC: &str = "0xc4900937c3222ca28cd4b300eb2575ee0868540f";
+
+    fn create_log(name: &str, params: &[ethabi::Token]) -> Log {
+        Log {
+            address: VAULT.parse().unwrap(),
+            topics: vec![event_topic(name).unwrap()],
+            data: ethabi::encode(params).into(),
+            ..Log::default()
+        }
+    }
+
+    fn create_swap_log() -> Log {
+        create_log("Swap", &[
+            ethabi::Token::Address(ACCOUNT.parse().unwrap()),
+            ethabi::Token::Address(USDT.parse().unwrap()),
+            ethabi::Token::Address(BTC.parse().unwrap()),
+            ethabi::Token::Uint(U256::exp10(21)),
+            ethabi::Token::Uint(U256::exp10(17) * 5),
+            ethabi::Token::Uint(U256::exp10(15) * 499),
+            ethabi::Token::Uint(U256::from(20)),
+        ])
+    }
+
+    fn create_fee_log(token: &str, amount: U256) -> Log {
+        create_log("IncreaseFeeReserves", &[ethabi::Token::Address(token.parse().unwrap()), ethabi::Token::Uint(amount)])
+    }
+
+    #[test]
+    fn decode_log_works() {
+        match decode_log(&create_swap_log()) {
+            Some(VaultEvent::Swap(swap)) => {
+                assert_eq!(swap.token_out, BTC.parse::<Address>().unwrap());
+                assert_eq!(swap.amount_out_after_fees, U256::exp10(15) * 499);
+                assert_eq!(swap.fee_basis_points, 20);
+            }
+            event => panic!("expected a Swap event, got {:?}", event),
+        }
+        let log = create_log("UpdateBorrowingRate", &[ethabi::Token::Address(BTC.parse().unwrap()), ethabi::Token::Uint(U256::from(100))]);
+        assert_eq!(decode_log(&log), Some(VaultEvent::UpdateBorrowingRate(UpdateBorrowingRateEvent {
+            token: BTC.parse().unwrap(),
+            borrowing_rate: U256::from(100),
+        })));
+        // unknown topic
+        assert_eq!(decode_log(&Log { topics: vec![H256::repeat_byte(1)], ..Log::default() }), None);
+    }
+
+    #[test]
+    fn decode_receipt_works() {
+        let mut other = create_swap_log();
+        other.address = Address::repeat_byte(1);
+        let receipt = TransactionReceipt {
+            logs: vec![create_swap_log(), other, create_fee_log(BTC, U256::exp10(15))],
+            ..TransactionReceipt::default()
+        };
+        let events = decode_receipt(&receipt, VAULT.parse().unwrap());
+        assert_eq!(events.len(), 2);
+        assert!(matches!(events[1], VaultEvent::IncreaseFeeReserves(_)));
+    }
+
+    #[test]
+    fn summarize_works() {
+        let usdp = Address::repeat_byte(2);
+        let events: Vec<_> = [create_swap_log(), create_fee_log(BTC, U256::exp10(14))].iter().filter_map(decode_log).collect();
+        let summary = summarize(&events, usdp).unwrap();
+        assert_eq!(summary.kind, TradeKind::Swap);
+        assert_eq!(summary.amount_in, U256::exp10(21));
+        assert_eq!(summary.amount_out, U256::exp10(15) * 499);
+        // the fee reserves event wins over amount_out - amount_out_after_fees
+        assert_eq!(summary.fee_amount, U256::exp10(14));
+
+        let quote = Quote {
+            kind: QuoteKind::Swap,
+            token_in: USDT.to_string(),
+            token_out: BTC.to_string(),
+            amount_in: U256::exp10(21),
+            amount_out: U256::exp10(17) * 5,
+            fee_basis_points: 20,
+            fee_usd: U256::zero(),
+            spread_basis_points: 0,
+            cost_basis_points: 20,
+            is_available: true,
+            unavailable_reason: None,
+            stale_data: vec![],
+        };
+        assert_eq!(summary.amount_out_diff_basis_points(&quote), Some(-20));
+
+        let sell = create_log("SellUSDP", &[
+            ethabi::Token::Address(ACCOUNT.parse().unwrap()),
+            ethabi::Token::Address(USDT.parse().unwrap()),
+            ethabi::Token::Uint(U256::exp10(21)),
+            ethabi::Token::Uint(U256::exp10(18) * 997),
+            ethabi::Token::Uint(U256::from(30)),
+        ]);
+        let summary = summarize(&[decode_log(&sell).unwrap()], usdp).unwrap();
+        assert_eq!(summary.token_in, usdp);
+        assert_eq!(summary.fee_amount, U256::exp10(18) * 3);
+        assert_eq!(summary.amount_out_diff_basis_points(&quote), None);
+    }
+}
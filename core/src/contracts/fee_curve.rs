@@ -0,0 +1,142 @@
+use ethabi::ethereum_types::U256;
+use serde::{Deserialize, Serialize};
+
+use super::quote::{Quote, QuoteKind};
+use super::token::Token;
+use super::vault::VaultState;
+use super::vault_logic::expand_decimals;
+
+const PLP_DECIMALS: u8 = 18;
+const PRICE_DECIMALS: u32 = 30;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CurvePoint {
+    pub amount_in: U256,
+    pub amount_out: U256,
+    pub fee_basis_points: u64,
+    pub fee_usd: U256,
+    // amount_out received per unit of amount_in after fees, 30 decimals
+    pub effective_price: U256,
+    // false once the trade hits max USDP amount or the available liquidity
+    pub is_available: bool,
+}
+
+impl CurvePoint {
+    fn new(quote: Quote, decimals_in: u8, decimals_out: u8) -> Self {
+        let effective_price = if quote.amount_in.is_zero() {
+            U256::zero()
+        } else {
+            quote.amount_out * expand_decimals(1, PRICE_DECIMALS) * expand_decimals(1, decimals_in as u32)
+                / expand_decimals(1, decimals_out as u32)
+                / quote.amount_in
+        };
+        Self {
+            amount_in: quote.amount_in,
+            amount_out: quote.amount_out,
+            fee_basis_points: quote.fee_basis_points,
+            fee_usd: quote.fee_usd,
+            effective_price,
+            is_available: quote.is_available,
+        }
+    }
+}
+
+// more points than a chart can show, bounds the work of a caller supplied `steps`
+pub const MAX_CURVE_STEPS: u32 = 1000;
+
+/// `steps` evenly spaced amounts from `from` to `to`, both included. `steps` is clamped to `MAX_CURVE_STEPS`
+pub fn amount_range(from: U256, to: U256, steps: u32) -> Vec<U256> {
+    let steps = steps.min(MAX_CURVE_STEPS);
+    if steps < 2 || to <= from {
+        return vec![from];
+    }
+    let step = (to - from) / U256::from(steps - 1);
+    (0..steps)
+        .map(|index| if index == steps - 1 { to } else { from + step * U256::from(index) })
+        .collect()
+}
+
+impl VaultState {
+    /// Fee and output of minting PLP with each of `amounts` of `token`, amounts in token decimals
+    pub fn fee_curve_buy_plp(&self, token: &Token, amounts: &[U256]) -> Vec<CurvePoint> {
+        amounts
+            .iter()
+            .map(|amount| CurvePoint::new(self.quote_buy_plp(token, *amount), token.decimals, PLP_DECIMALS))
+            .collect()
+    }
+
+    /// Fee and output of redeeming each of `plp_amounts` of PLP for `token`
+    pub fn fee_curve_sell_plp(&self, token: &Token, plp_amounts: &[U256]) -> Vec<CurvePoint> {
+        plp_amounts
+            .iter()
+            .map(|amount| CurvePoint::new(self.quote_sell_plp(token, *amount), PLP_DECIMALS, token.decimals))
+            .collect()
+    }
+
+    /// Fee and output of swapping each of `amounts` of `token_in` for `token_out`
+    pub fn fee_curve_swap(&self, token_in: &Token, token_out: &Token, amounts: &[U256]) -> Vec<CurvePoint> {
+        amounts
+            .iter()
+            .map(|amount| CurvePoint::new(self.quote_swap(token_in, token_out, *amount), token_in.decimals, token_out.decimals))
+            .collect()
+    }
+
+    /// Fee curve for any `kind`, `token_out` is only used for swaps
+    pub fn fee_curve(&self, kind: QuoteKind, token_in: &Token, token_out: Option<&Token>, amounts: &[U256]) -> Vec<CurvePoint> {
+        match (kind, token_out) {
+            (QuoteKind::BuyPlp, _) => self.fee_curve_buy_plp(token_in, amounts),
+            (QuoteKind::SellPlp, _) => self.fee_curve_sell_plp(token_in, amounts),
+            (QuoteKind::Swap, Some(token_out)) => self.fee_curve_swap(token_in, token_out, amounts),
+            (QuoteKind::Swap, None) => vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contracts::fixtures::{create_state, create_token, BTC, USDT};
+
+    #[test]
+    fn amount_range_works() {
+        let range = amount_range(U256::from(10), U256::from(100), 4);
+        assert_eq!(range, vec![U256::from(10), U256::from(40), U256::from(70), U256::from(100)]);
+        assert_eq!(amount_range(U256::from(10), U256::from(100), 1), vec![U256::from(10)]);
+        assert_eq!(amount_range(U256::from(100), U256::from(10), 5), vec![U256::from(100)]);
+
+        let range = amount_range(U256::zero(), U256::exp10(24), u32::MAX);
+        assert_eq!(range.len(), MAX_CURVE_STEPS as usize);
+        assert_eq!(range[range.len() - 1], U256::exp10(24));
+    }
+
+    #[test]
+    fn fee_curve_buy_plp_grows_with_size() {
+        let state = create_state();
+        let token = create_token(USDT, "USDT", 500);
+        let amounts = amount_range(expand_decimals(10, 18), expand_decimals(400, 18), 5);
+        let curve = state.fee_curve_buy_plp(&token, &amounts);
+        assert_eq!(curve.len(), 5);
+        assert!(curve.windows(2).all(|points| points[0].fee_basis_points <= points[1].fee_basis_points));
+        assert!(curve.windows(2).all(|points| points[0].effective_price >= points[1].effective_price));
+        // 1 USD token, PLP at 1 USD, 30 bps + tax
+        assert_eq!(curve[0].effective_price, expand_decimals(1, 30) * (10000 - curve[0].fee_basis_points) / 10000);
+    }
+
+    #[test]
+    fn fee_curve_swap_and_sell_plp() {
+        let state = create_state();
+        let usdt = create_token(USDT, "USDT", 500);
+        let btc = create_token(BTC, "BTC", 500);
+        let amounts = amount_range(expand_decimals(100, 18), expand_decimals(600, 18), 3);
+
+        let curve = state.fee_curve(QuoteKind::Swap, &usdt, Some(&btc), &amounts);
+        assert_eq!(curve.len(), 3);
+        assert!(curve[0].is_available);
+        // more than the BTC pool
+        assert!(!curve[2].is_available);
+
+        let curve = state.fee_curve(QuoteKind::SellPlp, &usdt, None, &amounts);
+        assert!(curve[0].fee_basis_points < curve[1].fee_basis_points);
+        assert!(state.fee_curve(QuoteKind::Swap, &usdt, None, &amounts).is_empty());
+    }
+}
@@ -0,0 +1,46 @@
+use ethabi::ethereum_types::U256;
+use rust_decimal::Decimal;
+
+use super::token::{Price, Token};
+use super::vault::VaultState;
+use super::vault_logic::expand_decimals;
+
+pub const USDT: &str = "0x542e4676238562b518b968a1d03626d544a7bca2";
+pub const BTC: &str = "0xc4900937c3222ca28cd4b300eb2575ee0868540f";
+
+// 1000 USDP minted against 1000 PLP worth 1000 USD, with dynamic fees on
+pub fn create_state() -> VaultState {
+    VaultState {
+        usdp_supply: expand_decimals(1000, 18),
+        total_token_weights: U256::from(100),
+        mint_burn_fee_basis_points: U256::from(30),
+        swap_fee_basis_points: U256::from(30),
+        stable_swap_fee_basis_points: U256::from(4),
+        margin_fee_basis_points: U256::from(10),
+        tax_basis_points: 50,
+        has_dynamic_fees: true,
+        is_swap_enabled: true,
+        total_aum: [expand_decimals(1000, 30), expand_decimals(1000, 30)],
+        plp_supply: expand_decimals(1000, 18),
+        ..VaultState::default()
+    }
+}
+
+// tradeable token worth 1 USD with half of the target weights and no USDP cap
+pub fn create_token(address: &str, symbol: &str, usdp_amount: u32) -> Token {
+    let mut token = Token::new(97, address, symbol, symbol, 18, "");
+    token.is_tradeable = Some(true);
+    token.is_stable_token = Some(false);
+    token.token_weight = Some(50);
+    token.max_usdp_amount = Some(U256::zero());
+    token.update_vault_info(expand_decimals(usdp_amount, 18), U256::zero(), expand_decimals(usdp_amount, 18), U256::zero());
+    set_price(&mut token, Price::new(Decimal::ONE));
+    token
+}
+
+pub fn set_price(token: &mut Token, price: Price) {
+    token.min_price = Some(price);
+    token.max_price = Some(price);
+    token.ask_price = Some(price);
+    token.bid_price = Some(price);
+}
@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::scheduler::{DataClass, RefreshIntervals};
+use crate::utils::now_millis;
+use super::quote::Quote;
+use super::vault::VaultState;
+
+/// When a data class was fetched
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct FetchedAt {
+    // head block when known, the wall clock is the reference for the age
+    pub block_number: Option<u64>,
+    // unix millis
+    pub timestamp: u64,
+}
+
+impl FetchedAt {
+    pub fn now(block_number: Option<u64>) -> Self {
+        Self { block_number, timestamp: now_millis() }
+    }
+
+    pub fn age_ms(&self, now: u64) -> u64 {
+        now.saturating_sub(self.timestamp)
+    }
+}
+
+/// Max age of the data a quote reads, 0 for no limit
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct FreshnessPolicy {
+    pub max_age: RefreshIntervals,
+    // unavailable quotes on stale data, otherwise they only list it in `stale_data`
+    pub refuse_stale: bool,
+}
+
+impl Default for FreshnessPolicy {
+    fn default() -> Self {
+        Self {
+            max_age: RefreshIntervals {
+                token_configuration_ms: 0,
+                vault_state_ms: 300_000,
+                vault_info_ms: 300_000,
+                prices_ms: 60_000,
+                balances_ms: 0,
+            },
+            refuse_stale: false,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct FreshnessReport {
+    pub fetched_at: HashMap<DataClass, FetchedAt>,
+    pub policy: FreshnessPolicy,
+    // classes older than the policy allows
+    pub stale: Vec<DataClass>,
+}
+
+// inputs of every quote
+const QUOTE_INPUTS: [DataClass; 4] = [DataClass::TokenConfiguration, DataClass::VaultState, DataClass::VaultInfo, DataClass::Prices];
+
+impl VaultState {
+    pub fn record_fetch(&mut self, classes: &[DataClass], fetched_at: FetchedAt) {
+        for class in classes {
+            self.fetched_at.insert(*class, fetched_at);
+        }
+    }
+
+    /// Classes of `classes` older than the policy allows at `now`.
+    /// Classes never fetched aren't stale, their missing values make quotes unavailable already.
+    pub fn stale_data(&self, classes: &[DataClass], now: u64) -> Vec<DataClass> {
+        classes
+            .iter()
+            .filter(|class| {
+                match (self.freshness_policy.max_age.interval(**class), self.fetched_at.get(class)) {
+                    (Some(max_age), Some(fetched_at)) => fetched_at.age_ms(now) > max_age.as_millis() as u64,
+                    _ => false,
+                }
+            })
+            .copied()
+            .collect()
+    }
+
+    pub fn freshness(&self) -> FreshnessReport {
+        FreshnessReport {
+            fetched_at: self.fetched_at.clone(),
+            policy: self.freshness_policy,
+            stale: self.stale_data(&DataClass::ALL, now_millis()),
+        }
+    }
+
+    /// Flags a quote computed on stale data, unavailable when the policy refuses it
+    pub(crate) fn check_freshness(&self, mut quote: Quote) -> Quote {
+        quote.stale_data = self.stale_data(&QUOTE_INPUTS, now_millis());
+        if quote.stale_data.is_empty() || !self.freshness_policy.refuse_stale || !quote.is_available {
+            return quote;
+        }
+        quote.is_available = false;
+        quote.unavailable_reason = Some(format!("stale data: {:?}", quote.stale_data));
+        quote
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contracts::fixtures::{self, create_state, BTC, USDT};
+    use crate::contracts::token::Token;
+    use crate::contracts::vault_logic::expand_decimals;
+
+    fn create_token(address: &str) -> Token {
+        let mut token = fixtures::create_token(address, "USDT", 500);
+        token.is_stable_token = Some(true);
+        token
+    }
+
+    #[test]
+    fn stale_data_follows_policy() {
+        let mut state = create_state();
+        let now = now_millis();
+        assert!(state.stale_data(&QUOTE_INPUTS, now).is_empty());
+
+        state.record_fetch(&[DataClass::TokenConfiguration, DataClass::Prices], FetchedAt { block_number: Some(10), timestamp: now - 3_600_000 });
+        state.record_fetch(&[DataClass::VaultInfo], FetchedAt { block_number: Some(20), timestamp: now - 1_000 });
+        // token configuration has no max age
+        assert_eq!(state.stale_data(&QUOTE_INPUTS, now), vec![DataClass::Prices]);
+        assert!(state.stale_data(&QUOTE_INPUTS, now - 3_590_000).is_empty());
+        assert_eq!(state.freshness().stale, vec![DataClass::Prices]);
+    }
+
+    #[test]
+    fn quotes_warn_or_refuse_on_stale_prices() {
+        let mut state = create_state();
+        let (usdt, usdc) = (create_token(USDT), create_token(BTC));
+        state.record_fetch(&[DataClass::Prices], FetchedAt { block_number: None, timestamp: now_millis() - 120_000 });
+
+        let quote = state.quote_swap(&usdt, &usdc, expand_decimals(1, 18));
+        assert!(quote.is_available);
+        assert_eq!(quote.stale_data, vec![DataClass::Prices]);
+
+        state.freshness_policy.refuse_stale = true;
+        let quote = state.quote_buy_plp(&usdt, expand_decimals(1, 18));
+        assert!(!quote.is_available);
+        assert_eq!(quote.unavailable_reason, Some("stale data: [Prices]".to_string()));
+
+        state.record_fetch(&[DataClass::Prices], FetchedAt::now(Some(30)));
+        assert!(state.quote_buy_plp(&usdt, expand_decimals(1, 18)).is_available);
+    }
+}
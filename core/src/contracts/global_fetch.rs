@@ -7,13 +7,15 @@ use ethers::{types::Bytes};
 use ethabi::{ethereum_types::Address, ethereum_types::U256};
 
 
-#[async_trait(?Send)]
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
 pub trait GlobalFetch {
     async fn fetch_balances(&self, update_tokens: &TokensArc) -> anyhow::Result<()>;
     async fn fetch_allowance(&self, update_tokens: &TokensArc ) -> anyhow::Result<()>;
 }
 
-#[async_trait(?Send)]
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
 impl GlobalFetch for Config {
     async fn fetch_balances(&self, update_tokens: &TokensArc ) -> anyhow::Result<()> {
         // let mut tokens = update_tokens.lock().await;
@@ -0,0 +1,269 @@
+use std::str::FromStr;
+
+use ethers::types::{Address, U256};
+use serde::{Deserialize, Serialize};
+
+use super::account_history::{HistoryAction, HistoryEntry};
+use super::token::Token;
+
+const USD_DECIMALS: u32 = 30;
+const USDP_DECIMALS: u32 = 18;
+
+/// CSV header, in the order of the `ExportRow` fields
+pub const CSV_COLUMNS: [&str; 12] = [
+    "timestamp",
+    "transaction_hash",
+    "action",
+    "token_in",
+    "amount_in",
+    "token_out",
+    "amount_out",
+    "usd_value",
+    "fee_token",
+    "fee_amount",
+    "fee_usd",
+    "block_number",
+];
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    Csv,
+    Json,
+}
+
+impl FromStr for ExportFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(format: &str) -> anyhow::Result<Self> {
+        match format.to_lowercase().as_str() {
+            "csv" => Ok(ExportFormat::Csv),
+            "json" => Ok(ExportFormat::Json),
+            _ => Err(anyhow::anyhow!("unknown export format {}, expected csv or json", format)),
+        }
+    }
+}
+
+/// One history entry with token symbols and decimal amounts, the JSON export is an array of rows.
+/// Tokens not in the config are written as their address with raw amounts, empty strings when not applicable.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ExportRow {
+    // unix seconds
+    pub timestamp: u64,
+    pub transaction_hash: String,
+    // mint_plp, redeem_plp, swap, increase_position or decrease_position
+    pub action: String,
+    pub token_in: String,
+    pub amount_in: String,
+    pub token_out: String,
+    pub amount_out: String,
+    // USD at execution
+    pub usd_value: String,
+    pub fee_token: String,
+    pub fee_amount: String,
+    pub fee_usd: String,
+    pub block_number: u64,
+}
+
+impl ExportRow {
+    fn csv_fields(&self) -> [String; 12] {
+        [
+            self.timestamp.to_string(),
+            self.transaction_hash.clone(),
+            self.action.clone(),
+            self.token_in.clone(),
+            self.amount_in.clone(),
+            self.token_out.clone(),
+            self.amount_out.clone(),
+            self.usd_value.clone(),
+            self.fee_token.clone(),
+            self.fee_amount.clone(),
+            self.fee_usd.clone(),
+            self.block_number.to_string(),
+        ]
+    }
+}
+
+pub fn action_name(action: HistoryAction) -> &'static str {
+    match action {
+        HistoryAction::MintPlp => "mint_plp",
+        HistoryAction::RedeemPlp => "redeem_plp",
+        HistoryAction::Swap => "swap",
+        HistoryAction::IncreasePosition => "increase_position",
+        HistoryAction::DecreasePosition => "decrease_position",
+    }
+}
+
+/// 1.500000 -> 1.5
+fn format_amount(value: U256, decimals: u32) -> String {
+    let value = ethers::utils::format_units(value, decimals).unwrap_or_else(|_| value.to_string());
+    if value.contains('.') {
+        value.trim_end_matches('0').trim_end_matches('.').to_string()
+    } else {
+        value
+    }
+}
+
+struct TokenLabels<'a> {
+    tokens: &'a [Token],
+    usdp: Address,
+}
+
+impl TokenLabels<'_> {
+    // symbol and decimals, the address and no decimals for unknown tokens
+    fn label(&self, token: Address) -> (String, u32) {
+        if token == self.usdp {
+            return ("USDP".to_string(), USDP_DECIMALS);
+        }
+        self.tokens
+            .iter()
+            .find(|t| t.address.parse::<Address>().map(|address| address == token).unwrap_or(false))
+            .map(|t| (t.symbol.clone(), t.decimals as u32))
+            .unwrap_or_else(|| (format!("{:?}", token), 0))
+    }
+
+    fn token_amount(&self, token: Option<Address>, amount: U256) -> (String, String) {
+        match token {
+            Some(token) => {
+                let (symbol, decimals) = self.label(token);
+                (symbol, format_amount(amount, decimals))
+            }
+            None if amount.is_zero() => (String::new(), String::new()),
+            None => (String::new(), amount.to_string()),
+        }
+    }
+}
+
+pub fn export_rows(entries: &[HistoryEntry], tokens: &[Token], usdp: Address) -> Vec<ExportRow> {
+    let labels = TokenLabels { tokens, usdp };
+    entries
+        .iter()
+        .map(|entry| {
+            let (token_in, amount_in) = labels.token_amount(entry.token_in, entry.amount_in);
+            let (token_out, amount_out) = labels.token_amount(entry.token_out, entry.amount_out);
+            let (fee_token, fee_amount) = labels.token_amount(entry.fee_token, entry.fee_amount);
+            ExportRow {
+                timestamp: entry.timestamp,
+                transaction_hash: format!("{:?}", entry.transaction_hash),
+                action: action_name(entry.action).to_string(),
+                token_in,
+                amount_in,
+                token_out,
+                amount_out,
+                usd_value: format_amount(entry.usd_value, USD_DECIMALS),
+                fee_token,
+                fee_amount,
+                fee_usd: format_amount(entry.fee_usd, USD_DECIMALS),
+                block_number: entry.block_number,
+            }
+        })
+        .collect()
+}
+
+// quoted only when needed, RFC 4180
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Header line then one line per row, CRLF separated
+pub fn to_csv(rows: &[ExportRow]) -> String {
+    let mut csv = CSV_COLUMNS.join(",");
+    csv.push_str("\r\n");
+    for row in rows {
+        let fields: Vec<String> = row.csv_fields().iter().map(|field| csv_field(field)).collect();
+        csv.push_str(&fields.join(","));
+        csv.push_str("\r\n");
+    }
+    csv
+}
+
+pub fn to_json(rows: &[ExportRow]) -> anyhow::Result<String> {
+    Ok(serde_json::to_string_pretty(rows)?)
+}
+
+/// Account history as CSV or JSON, see `ExportRow` for the columns
+pub fn export(entries: &[HistoryEntry], tokens: &[Token], usdp: Address, format: ExportFormat) -> anyhow::Result<String> {
+    let rows = export_rows(entries, tokens, usdp);
+    match format {
+        ExportFormat::Csv => Ok(to_csv(&rows)),
+        ExportFormat::Json => to_json(&rows),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::types::H256;
+
+    use super::*;
+
+    const USDT: &str = "0x542e4676238562b518b968a1d03626d544a7bca2";
+    const BTC: &str = "0xc4900937c3222ca28cd4b300eb2575ee0868540f";
+
+    fn create_tokens() -> Vec<Token> {
+        vec![Token::new(97, USDT, "Tether USD", "USDT", 6, ""), Token::new(97, BTC, "Bitcoin", "BTC", 8, "")]
+    }
+
+    fn create_entries() -> Vec<HistoryEntry> {
+        let swap = HistoryEntry {
+            block_number: 12,
+            timestamp: 1_024,
+            transaction_hash: H256::repeat_byte(3),
+            log_index: 2,
+            action: HistoryAction::Swap,
+            token_in: Some(USDT.parse().unwrap()),
+            token_out: Some(BTC.parse().unwrap()),
+            amount_in: U256::from(1_000_500_000u64),
+            amount_out: U256::from(4_990_000u64),
+            usd_value: U256::exp10(33),
+            fee_amount: U256::from(10_000u64),
+            fee_token: Some(BTC.parse().unwrap()),
+            fee_usd: U256::exp10(30) * 2,
+            index_token: None,
+            is_long: None,
+            collateral_delta_usd: U256::zero(),
+        };
+        let position = HistoryEntry {
+            block_number: 13,
+            action: HistoryAction::DecreasePosition,
+            token_in: None,
+            amount_in: U256::zero(),
+            amount_out: U256::zero(),
+            fee_amount: U256::zero(),
+            fee_token: None,
+            usd_value: U256::exp10(29) * 15,
+            index_token: Some(BTC.parse().unwrap()),
+            is_long: Some(false),
+            ..swap.clone()
+        };
+        vec![swap, position]
+    }
+
+    #[test]
+    fn to_csv_works() {
+        let rows = export_rows(&create_entries(), &create_tokens(), Address::repeat_byte(2));
+        let csv = to_csv(&rows);
+        let lines: Vec<&str> = csv.split("\r\n").collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], "timestamp,transaction_hash,action,token_in,amount_in,token_out,amount_out,usd_value,fee_token,fee_amount,fee_usd,block_number");
+        assert_eq!(
+            lines[1],
+            format!("1024,{:?},swap,USDT,1000.5,BTC,0.0499,1000,BTC,0.0001,2,12", H256::repeat_byte(3))
+        );
+        assert!(lines[2].contains(",decrease_position,,,BTC,0,1.5,,,2,13"));
+        assert_eq!(lines[3], "");
+
+        assert_eq!(csv_field("a,\"b\""), "\"a,\"\"b\"\"\"");
+    }
+
+    #[test]
+    fn export_json_works() {
+        let json = export(&create_entries(), &create_tokens(), Address::repeat_byte(2), "JSON".parse().unwrap()).unwrap();
+        let rows: Vec<ExportRow> = serde_json::from_str(&json).unwrap();
+        assert_eq!(rows, export_rows(&create_entries(), &create_tokens(), Address::repeat_byte(2)));
+        assert_eq!(rows[1].action, "decrease_position");
+        assert!("xml".parse::<ExportFormat>().is_err());
+    }
+}
@@ -4,4 +4,22 @@ pub mod vault;
 pub mod global_fetch;
 pub mod vault_logic;
 pub mod types;
+pub mod composition;
+pub mod quote;
+pub mod advisor;
 
+pub mod optimizer;
+pub mod simulator;
+pub mod fee_curve;
+pub mod staking;
+pub mod apr;
+pub mod vester;
+pub mod events;
+pub mod scanner;
+pub mod account_history;
+pub mod history_export;
+pub mod freshness;
+pub mod positions;
+
+#[cfg(test)]
+pub(crate) mod fixtures;
@@ -17,18 +17,22 @@ abigen!(
     "./abi/multicall.json",
 );
 
-#[async_trait(?Send)]
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
 pub trait ChainMulticallTrait {
     //! execute multicall
     //! pass calls to multicall contract
     //! pass the interface and function name to decode the return data
     async fn execute_multicall(&self, calls: Vec<(Address, Bytes)>, interface: String, fn_name: &str) -> Result<Vec<Vec<Token>>, String>;
     async fn execute_multicall_raw(&self, calls: Vec<(Address, Bytes)>) -> Result<Vec<Bytes>, String>;
+    /// execute multicall, with the number of the block the calls were read at
+    async fn execute_multicall_at_block(&self, calls: Vec<(Address, Bytes)>) -> Result<(u64, Vec<Bytes>), String>;
     async fn get_balance(&self, address: &String) -> Result<U256, String>;
     fn random_rpc(&self) -> usize;
 }
 
-#[async_trait(?Send)]
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
 impl ChainMulticallTrait for Chain {
     async fn execute_multicall(&self, calls: Vec<(Address, Bytes)>, interface: String, fn_name: &str) -> Result<Vec<Vec<Token>>, String>{
         let return_data = self.execute_multicall_raw(calls).await;
@@ -36,20 +40,23 @@ impl ChainMulticallTrait for Chain {
         Ok(decode_return_data(return_data.unwrap(), interface.clone(), fn_name))
     }
     async fn execute_multicall_raw(&self, calls: Vec<(Address, Bytes)>) -> Result<Vec<Bytes>, String>{
+        let (_, return_data) = self.execute_multicall_at_block(calls).await?;
+        Ok(return_data)
+    }
 
-
+    async fn execute_multicall_at_block(&self, calls: Vec<(Address, Bytes)>) -> Result<(u64, Vec<Bytes>), String> {
         let random_index = self.random_rpc();
 
         log::print(format!("random_index {}, rpcs: {}", random_index, self.rpc_urls.len()).as_str());
 
-        let provider = Provider::<Http>::try_from(self.rpc_urls[random_index].clone()).expect("invalid rpc url, check your config");
-        let client = Arc::new(provider.clone());
-        let address: Address = self.multicall_address.parse().expect("invalid multicall address, check your config");
+        let provider = Provider::<Http>::try_from(self.rpc_urls[random_index].clone()).map_err(|e| e.to_string())?;
+        let client = Arc::new(provider);
+        let address: Address = self.multicall_address.parse().map_err(|_| "invalid multicall address, check your config".to_string())?;
         let multicall = Multicall::new(address, client);
         log::print(format!("multicall address {:?}, start calling", address.to_string()).as_str());
-        let (_block_number, return_data) = multicall.aggregate(calls).call().await.expect("Failed to execute multicall");
+        let (block_number, return_data) = multicall.aggregate(calls).call().await.map_err(|e| e.to_string())?;
         log::print(format!("multicall done {}", return_data.len()).as_str());
-        Ok(return_data)
+        Ok((block_number.as_u64(), return_data))
     }
 
     async fn get_balance(&self, address: &String) -> Result<U256, String> {
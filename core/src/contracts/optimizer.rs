@@ -0,0 +1,197 @@
+use ethabi::ethereum_types::U256;
+use serde::{Deserialize, Serialize};
+
+use super::quote::{usd_to_token_amount, Quote};
+use super::simulator::{token_to_usd, Simulator};
+use super::token::Token;
+use super::vault::VaultState;
+
+pub const DEFAULT_MINT_CHUNKS: u32 = 20;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MintStep {
+    pub token: String,
+    pub symbol: String,
+    // amount of token to pay, token decimals
+    pub amount_in: U256,
+    // value of amount_in at min price, 30 decimals
+    pub usd_amount: U256,
+    // quote against the state left by the previous steps
+    pub quote: Quote,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MintPlan {
+    // execute in order, each step is quoted after the previous ones
+    pub steps: Vec<MintStep>,
+    pub total_usd: U256,
+    pub total_fee_usd: U256,
+    pub total_plp_amount: U256,
+    // part of the budget not covered by balances or max USDP amounts
+    pub unallocated_usd: U256,
+}
+
+impl VaultState {
+    /// Split a `usd_budget` (30 decimals) PLP mint across the account's wallet tokens so that the
+    /// total dynamic fee is minimal. The budget is allocated in `chunks` slices, each slice going
+    /// to the token with the cheapest marginal fee after the previous slices.
+    pub fn optimize_plp_mint(&self, usd_budget: U256, tokens: &[Token], account: &String, chunks: u32) -> MintPlan {
+        let chunks = chunks.max(1);
+        let chunk_usd = usd_budget / U256::from(chunks);
+
+        let mut simulator = Simulator::new(self, tokens);
+        let mut balances: Vec<U256> = tokens.iter().map(|token| token.get_balance_raw(account)).collect();
+        // (token index, amount_in) in allocation order
+        let mut allocations: Vec<(usize, U256)> = vec![];
+        let mut remaining_usd = usd_budget;
+
+        while !remaining_usd.is_zero() {
+            let slice_usd = if remaining_usd < chunk_usd * 2 { remaining_usd } else { chunk_usd };
+            let best = simulator
+                .tokens
+                .iter()
+                .enumerate()
+                .filter(|(index, token)| token.is_tradeable.unwrap_or(false) && !balances[*index].is_zero())
+                .filter_map(|(index, token)| {
+                    let min_price = token.min_price?.raw;
+                    let amount_in = usd_to_token_amount(slice_usd, min_price, token.decimals).min(balances[index]);
+                    let quote = simulator.state.quote_buy_plp(token, amount_in);
+                    if quote.is_available && !quote.amount_out.is_zero() {
+                        Some((index, amount_in, quote.cost_basis_points))
+                    } else {
+                        None
+                    }
+                })
+                .min_by_key(|(_, _, cost_basis_points)| *cost_basis_points);
+
+            let (index, amount_in) = match best {
+                Some((index, amount_in, _)) => (index, amount_in),
+                None => break,
+            };
+            let token = &tokens[index];
+            if simulator.buy_plp(&token.address, amount_in).is_err() {
+                break;
+            }
+            let usd_in = token_to_usd(amount_in, token.min_price.map(|price| price.raw).unwrap_or_default(), token.decimals);
+            balances[index] -= amount_in;
+            remaining_usd = remaining_usd.saturating_sub(usd_in.max(U256::one()));
+
+            match allocations.iter_mut().find(|(allocated, _)| *allocated == index) {
+                Some((_, allocated_amount)) => *allocated_amount += amount_in,
+                None => allocations.push((index, amount_in)),
+            }
+        }
+
+        self.quote_allocations(tokens, allocations, remaining_usd)
+    }
+
+    // re-quote the merged steps in execution order, a step refused by the vault is left unallocated
+    fn quote_allocations(&self, tokens: &[Token], allocations: Vec<(usize, U256)>, unallocated_usd: U256) -> MintPlan {
+        let mut simulator = Simulator::new(self, tokens);
+        let mut plan = MintPlan {
+            steps: vec![],
+            total_usd: U256::zero(),
+            total_fee_usd: U256::zero(),
+            total_plp_amount: U256::zero(),
+            unallocated_usd,
+        };
+        for (index, amount_in) in allocations {
+            let token = &tokens[index];
+            let usd_amount = token_to_usd(amount_in, token.min_price.map(|price| price.raw).unwrap_or_default(), token.decimals);
+            let quote = match simulator.buy_plp(&token.address, amount_in) {
+                Ok(quote) => quote,
+                Err(_) => {
+                    plan.unallocated_usd += usd_amount;
+                    continue;
+                }
+            };
+            plan.total_usd += usd_amount;
+            plan.total_fee_usd += quote.fee_usd;
+            plan.total_plp_amount += quote.amount_out;
+            plan.steps.push(MintStep {
+                token: token.address.clone(),
+                symbol: token.symbol.clone(),
+                amount_in,
+                usd_amount,
+                quote,
+            });
+        }
+        plan
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contracts::fixtures::{self, create_state, BTC, USDT};
+    use crate::contracts::vault_logic::expand_decimals;
+
+    const ACCOUNT: &str = "0x1f9840a85d5af5bf1d1762f925bdaddc4201f984";
+
+    fn create_token(address: &str, symbol: &str, usdp_amount: u32, balance: u32) -> Token {
+        let mut token = fixtures::create_token(address, symbol, usdp_amount);
+        token.update_balance(&ACCOUNT.to_string(), expand_decimals(balance, 18));
+        token
+    }
+
+    fn create_tokens() -> Vec<Token> {
+        vec![
+            create_token(USDT, "USDT", 550, 1000),
+            create_token(BTC, "BTC", 450, 1000),
+        ]
+    }
+
+    #[test]
+    fn optimize_plp_mint_splits_large_mint() {
+        let state = create_state();
+        let tokens = create_tokens();
+        let plan = state.optimize_plp_mint(expand_decimals(400, 30), &tokens, &ACCOUNT.to_string(), DEFAULT_MINT_CHUNKS);
+        assert_eq!(plan.steps.len(), 2);
+        // the under weight token is used first
+        assert_eq!(plan.steps[0].symbol, "BTC");
+        assert!(plan.unallocated_usd.is_zero());
+        assert_eq!(plan.total_usd, expand_decimals(400, 30));
+
+        // cheaper than putting everything in a single token
+        let single = state.quote_buy_plp(&tokens[1], expand_decimals(400, 18));
+        assert!(plan.total_fee_usd < single.fee_usd);
+        assert!(plan.total_plp_amount > single.amount_out);
+    }
+
+    #[test]
+    fn optimize_plp_mint_respects_balances() {
+        let state = create_state();
+        let mut tokens = create_tokens();
+        tokens[1] = create_token(BTC, "BTC", 450, 50);
+        let plan = state.optimize_plp_mint(expand_decimals(400, 30), &tokens, &ACCOUNT.to_string(), DEFAULT_MINT_CHUNKS);
+        let btc = plan.steps.iter().find(|step| step.symbol == "BTC").expect("no BTC step");
+        assert_eq!(btc.amount_in, expand_decimals(50, 18));
+        assert_eq!(plan.total_usd, expand_decimals(400, 30));
+    }
+
+    #[test]
+    fn optimize_plp_mint_reports_unallocated_budget() {
+        let state = create_state();
+        let mut tokens = create_tokens();
+        tokens[0].max_usdp_amount = Some(expand_decimals(600, 18));
+        tokens[1] = create_token(BTC, "BTC", 450, 100);
+        let plan = state.optimize_plp_mint(expand_decimals(400, 30), &tokens, &ACCOUNT.to_string(), DEFAULT_MINT_CHUNKS);
+        assert!(!plan.unallocated_usd.is_zero());
+        assert!(plan.steps.iter().all(|step| step.quote.is_available));
+        assert!(plan.total_usd < expand_decimals(400, 30));
+    }
+
+    #[test]
+    fn refused_steps_are_unallocated() {
+        let state = create_state();
+        let mut tokens = create_tokens();
+        // USDT can't take 100 more USDP anymore
+        tokens[0].max_usdp_amount = Some(expand_decimals(600, 18));
+        let allocations = vec![(0, expand_decimals(100, 18)), (1, expand_decimals(50, 18))];
+        let plan = state.quote_allocations(&tokens, allocations, expand_decimals(10, 30));
+        assert_eq!(plan.steps.len(), 1);
+        assert_eq!(plan.steps[0].symbol, "BTC");
+        assert_eq!(plan.total_usd, expand_decimals(50, 30));
+        assert_eq!(plan.unallocated_usd, expand_decimals(110, 30));
+    }
+}
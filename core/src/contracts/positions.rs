@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+
+use ethabi::ParamType;
+use ethers::types::{Address, Bytes, U256};
+use serde::{Deserialize, Serialize};
+
+use crate::utils::get_encode_address_and_params;
+use super::token::Token;
+use super::vault::VaultState;
+
+/// Vault.getPositionInfo of an account, index token and side
+#[derive(Default, Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct PositionInfo {
+    pub index_token: Address,
+    pub is_long: bool,
+    // index token decimals
+    pub reserved_amount: U256,
+    pub entry_borrowing_rate: U256,
+    pub collateral_token: Address,
+}
+
+impl PositionInfo {
+    pub fn is_open(&self) -> bool {
+        !self.reserved_amount.is_zero() || self.collateral_token != Address::zero()
+    }
+}
+
+// long then short of every tradeable token
+fn position_keys(tokens: &[Token]) -> Vec<(Address, bool)> {
+    tokens
+        .iter()
+        .filter(|token| token.is_tradeable == Some(true))
+        .flat_map(|token| [(token.get_parsed_address(), true), (token.get_parsed_address(), false)])
+        .collect()
+}
+
+/// Multicall calls reading the positions of `account` on every tradeable token
+pub fn build_position_calls(vault: &str, tokens: &[Token], account: Address) -> Vec<(Address, Bytes)> {
+    position_keys(tokens)
+        .into_iter()
+        .map(|(index_token, is_long)| {
+            get_encode_address_and_params(
+                vault,
+                "getPositionInfo(address,address,bool)",
+                &[ethabi::Token::Address(account), ethabi::Token::Address(index_token), ethabi::Token::Bool(is_long)],
+            )
+        })
+        .collect()
+}
+
+/// Decode the return data of `build_position_calls`, closed positions are dropped
+pub fn decode_position_results(tokens: &[Token], results: &[Bytes]) -> anyhow::Result<Vec<PositionInfo>> {
+    let keys = position_keys(tokens);
+    if keys.len() != results.len() {
+        anyhow::bail!("Invalid position return data, expected {} results", keys.len());
+    }
+    let output = ParamType::Tuple(vec![ParamType::Uint(256), ParamType::Uint(128), ParamType::Address]);
+    let mut positions = vec![];
+    for ((index_token, is_long), data) in keys.into_iter().zip(results) {
+        let fields = ethabi::decode(std::slice::from_ref(&output), data)?[0]
+            .clone()
+            .into_tuple()
+            .ok_or_else(|| anyhow::anyhow!("Failed to parse position info"))?;
+        let position = match fields.as_slice() {
+            [reserved_amount, entry_borrowing_rate, collateral_token] => PositionInfo {
+                index_token,
+                is_long,
+                reserved_amount: reserved_amount.clone().into_uint().unwrap_or_default(),
+                entry_borrowing_rate: entry_borrowing_rate.clone().into_uint().unwrap_or_default(),
+                collateral_token: collateral_token.clone().into_address().unwrap_or_default(),
+            },
+            _ => anyhow::bail!("Invalid position info return data (may be invalid ABI), check vault.getPositionInfo"),
+        };
+        if position.is_open() {
+            positions.push(position);
+        }
+    }
+    Ok(positions)
+}
+
+impl VaultState {
+    pub fn update_positions(&mut self, account: Address, positions: Vec<PositionInfo>) {
+        self.positions.get_or_insert_with(HashMap::new).insert(account, positions);
+    }
+
+    pub fn get_positions(&self, account: &Address) -> Vec<PositionInfo> {
+        self.positions
+            .as_ref()
+            .and_then(|positions| positions.get(account))
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VAULT: &str = "0x792ba5e9e0cd15083ec2f58e434d875892005b91";
+    const ACCOUNT: &str = "0x1f9840a85d5af5bf1d1762f925bdaddc4201f984";
+
+    fn create_tokens() -> Vec<Token> {
+        let mut btc = Token::new(97, "0xc4900937c3222ca28cd4b300eb2575ee0868540f", "BTC", "BTC", 18, "");
+        btc.is_tradeable = Some(true);
+        let plp = Token::new(97, "0x542e4676238562b518b968a1d03626d544a7bca2", "PLP", "PLP", 18, "");
+        vec![btc, plp]
+    }
+
+    fn encode_position(reserved_amount: u64, collateral_token: Address) -> Bytes {
+        ethabi::encode(&[ethabi::Token::Tuple(vec![
+            ethabi::Token::Uint(U256::from(reserved_amount)),
+            ethabi::Token::Uint(U256::from(3)),
+            ethabi::Token::Address(collateral_token),
+        ])])
+        .into()
+    }
+
+    #[test]
+    fn positions_round_trip() {
+        let tokens = create_tokens();
+        let calls = build_position_calls(VAULT, &tokens, ACCOUNT.parse().unwrap());
+        // long and short of the tradeable token only
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[0].0, VAULT.parse::<Address>().unwrap());
+
+        let collateral = tokens[0].get_parsed_address();
+        let results = vec![encode_position(500, collateral), encode_position(0, Address::zero())];
+        let positions = decode_position_results(&tokens, &results).unwrap();
+        assert_eq!(positions.len(), 1);
+        assert!(positions[0].is_long);
+        assert_eq!(positions[0].reserved_amount, U256::from(500));
+        assert_eq!(positions[0].collateral_token, collateral);
+        assert!(decode_position_results(&tokens, &results[..1]).is_err());
+
+        let mut state = VaultState::default();
+        let account: Address = ACCOUNT.parse().unwrap();
+        state.update_positions(account, positions);
+        assert_eq!(state.get_positions(&account).len(), 1);
+        assert!(state.get_positions(&Address::zero()).is_empty());
+    }
+}
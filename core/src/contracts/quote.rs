@@ -0,0 +1,340 @@
+use ethabi::ethereum_types::U256;
+use serde::{Deserialize, Serialize};
+
+use crate::scheduler::DataClass;
+use super::token::Token;
+use super::vault::VaultState;
+use super::vault_logic::{adjust_for_decimals, expand_decimals, VaultLogic, BASIS_POINTS_DIVISOR};
+
+const USDP_DECIMALS: u32 = 18;
+const PLP_DECIMALS: u32 = 18;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum QuoteKind {
+    BuyPlp,
+    SellPlp,
+    Swap,
+}
+
+/// A priced mint, redeem or swap against the current vault state.
+/// Amounts are in the decimals of their token, USD values in 30 decimals.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Quote {
+    pub kind: QuoteKind,
+    pub token_in: String,
+    pub token_out: String,
+    pub amount_in: U256,
+    // amount received after fees
+    pub amount_out: U256,
+    pub fee_basis_points: u64,
+    pub fee_usd: U256,
+    // difference between the min and max price of the vault token
+    pub spread_basis_points: u64,
+    // fee + spread, used to compare quotes between tokens
+    pub cost_basis_points: u64,
+    pub is_available: bool,
+    // why the vault would reject the trade, eg: max USDP exceeded
+    pub unavailable_reason: Option<String>,
+    // inputs older than the freshness policy allows
+    #[serde(default)]
+    pub stale_data: Vec<DataClass>,
+}
+
+impl Quote {
+    fn new(kind: QuoteKind, token_in: String, token_out: String, amount_in: U256) -> Self {
+        Self {
+            kind,
+            token_in,
+            token_out,
+            amount_in,
+            amount_out: U256::zero(),
+            fee_basis_points: 0,
+            fee_usd: U256::zero(),
+            spread_basis_points: 0,
+            cost_basis_points: 0,
+            is_available: true,
+            unavailable_reason: None,
+            stale_data: vec![],
+        }
+    }
+
+    fn unavailable(mut self, reason: &str) -> Self {
+        self.is_available = false;
+        self.unavailable_reason = Some(reason.to_string());
+        self
+    }
+
+    /// Minimum amount out accepted by the user for the given slippage
+    pub fn min_amount_out(&self, slippage_basis_points: u64) -> U256 {
+        let slippage = U256::from(slippage_basis_points).min(*BASIS_POINTS_DIVISOR);
+        self.amount_out * (*BASIS_POINTS_DIVISOR - slippage) / *BASIS_POINTS_DIVISOR
+    }
+}
+
+impl VaultState {
+    /// Quote buying PLP with an exact amount of `token`
+    pub fn quote_buy_plp(&self, token: &Token, amount_in: U256) -> Quote {
+        self.check_freshness(self.price_buy_plp(token, amount_in))
+    }
+
+    fn price_buy_plp(&self, token: &Token, amount_in: U256) -> Quote {
+        let quote = Quote::new(QuoteKind::BuyPlp, token.address.clone(), format!("{:?}", self.plp_address), amount_in);
+        let min_price = match token.min_price {
+            Some(price) if !price.is_zero() => price.raw,
+            _ => return quote.unavailable("no price"),
+        };
+        if token.token_weight.is_none() || token.usdp_amount.is_none() {
+            return quote.unavailable("no vault info");
+        }
+        if token.token_weight == Some(0) {
+            return quote.unavailable("token not in pool");
+        }
+
+        let (amount_out, fee_basis_points) = self.get_buy_glp_to_amount(&amount_in, token);
+        let usd_in = amount_in * min_price / expand_decimals(1, token.decimals as u32);
+        let mut quote = Quote {
+            amount_out,
+            fee_basis_points,
+            fee_usd: usd_in * U256::from(fee_basis_points) / *BASIS_POINTS_DIVISOR,
+            spread_basis_points: spread_basis_points(token),
+            ..quote
+        };
+        quote.cost_basis_points = quote.fee_basis_points + quote.spread_basis_points;
+
+        let usdp_delta = usd_to_usdp(usd_in);
+        match token.max_usdp_amount {
+            Some(max_usdp_amount) if !max_usdp_amount.is_zero()
+                && token.usdp_amount.unwrap() + usdp_delta > max_usdp_amount => quote.unavailable("max USDP exceeded"),
+            _ => quote,
+        }
+    }
+
+    /// Quote selling an exact amount of PLP for `token`
+    pub fn quote_sell_plp(&self, token: &Token, plp_amount: U256) -> Quote {
+        self.check_freshness(self.price_sell_plp(token, plp_amount))
+    }
+
+    fn price_sell_plp(&self, token: &Token, plp_amount: U256) -> Quote {
+        let quote = Quote::new(QuoteKind::SellPlp, format!("{:?}", self.plp_address), token.address.clone(), plp_amount);
+        let max_price = match token.max_price {
+            Some(price) if !price.is_zero() => price.raw,
+            _ => return quote.unavailable("no price"),
+        };
+        if token.token_weight.is_none() || token.usdp_amount.is_none() {
+            return quote.unavailable("no vault info");
+        }
+        if token.token_weight == Some(0) {
+            return quote.unavailable("token not in pool");
+        }
+
+        let plp_price = self.get_plp_price(false);
+        // ^18 * ^18 * ^12 / ^18 = ^30
+        let usd_out = plp_amount * plp_price * expand_decimals(1, 12) / expand_decimals(1, PLP_DECIMALS);
+        let (_, fee_basis_points) = self.get_sell_glp_to_amount(plp_amount, token);
+        let amount_out_before_fees = usd_out * expand_decimals(1, token.decimals as u32) / max_price;
+        let amount_out = amount_out_before_fees * (*BASIS_POINTS_DIVISOR - U256::from(fee_basis_points)) / *BASIS_POINTS_DIVISOR;
+        let mut quote = Quote {
+            amount_out,
+            fee_basis_points,
+            fee_usd: usd_out * U256::from(fee_basis_points) / *BASIS_POINTS_DIVISOR,
+            spread_basis_points: spread_basis_points(token),
+            ..quote
+        };
+        quote.cost_basis_points = quote.fee_basis_points + quote.spread_basis_points;
+
+        if usd_to_usdp(usd_out) > token.usdp_amount.unwrap() {
+            return quote.unavailable("insufficient USDP amount");
+        }
+        match token.available_amount {
+            Some(available_amount) if amount_out_before_fees > available_amount => quote.unavailable("insufficient liquidity"),
+            _ => quote,
+        }
+    }
+
+    /// Quote swapping an exact amount of `token_in` for `token_out`
+    pub fn quote_swap(&self, token_in: &Token, token_out: &Token, amount_in: U256) -> Quote {
+        self.check_freshness(self.price_swap(token_in, token_out, amount_in))
+    }
+
+    fn price_swap(&self, token_in: &Token, token_out: &Token, amount_in: U256) -> Quote {
+        let quote = Quote::new(QuoteKind::Swap, token_in.address.clone(), token_out.address.clone(), amount_in);
+        if !self.is_swap_enabled {
+            return quote.unavailable("swaps disabled");
+        }
+        let price_in = match token_in.ask_price {
+            Some(price) if !price.is_zero() => price.raw,
+            _ => return quote.unavailable("no price"),
+        };
+        match token_out.bid_price {
+            Some(price) if !price.is_zero() => {}
+            _ => return quote.unavailable("no price"),
+        };
+        if token_in.token_weight.is_none() || token_in.usdp_amount.is_none() || token_in.is_stable_token.is_none()
+            || token_out.token_weight.is_none() || token_out.usdp_amount.is_none() || token_out.is_stable_token.is_none() {
+            return quote.unavailable("no vault info");
+        }
+        if token_in.token_weight == Some(0) || token_out.token_weight == Some(0) {
+            return quote.unavailable("token not in pool");
+        }
+
+        let (amount_out_before_fees, fee_amount, fee_basis_points) = self.get_swap_details(token_in, token_out, amount_in);
+        let usd_in = amount_in * price_in / expand_decimals(1, token_in.decimals as u32);
+        let mut quote = Quote {
+            amount_out: amount_out_before_fees - fee_amount,
+            fee_basis_points,
+            fee_usd: usd_in * U256::from(fee_basis_points) / *BASIS_POINTS_DIVISOR,
+            spread_basis_points: spread_basis_points(token_in).max(spread_basis_points(token_out)),
+            ..quote
+        };
+        quote.cost_basis_points = quote.fee_basis_points + quote.spread_basis_points;
+
+        let usdp_delta = usd_to_usdp(usd_in);
+        if let Some(max_usdp_amount) = token_in.max_usdp_amount {
+            if !max_usdp_amount.is_zero() && token_in.usdp_amount.unwrap() + usdp_delta > max_usdp_amount {
+                return quote.unavailable("max USDP exceeded");
+            }
+        }
+        match token_out.available_amount {
+            Some(available_amount) if amount_out_before_fees > available_amount => quote.unavailable("insufficient liquidity"),
+            _ => quote,
+        }
+    }
+}
+
+pub(crate) fn spread_basis_points(token: &Token) -> u64 {
+    match (token.min_price, token.max_price) {
+        (Some(min_price), Some(max_price)) => {
+            let (low, high) = if min_price.raw < max_price.raw { (min_price.raw, max_price.raw) } else { (max_price.raw, min_price.raw) };
+            if high.is_zero() {
+                return 0;
+            }
+            ((high - low) * *BASIS_POINTS_DIVISOR / high).low_u64()
+        }
+        _ => 0,
+    }
+}
+
+// 30 decimals usd to 18 decimals usdp
+pub(crate) fn usd_to_usdp(usd_amount: U256) -> U256 {
+    adjust_for_decimals(&usd_amount, 30, USDP_DECIMALS)
+}
+
+// 30 decimals usd to token amount at the given price
+pub(crate) fn usd_to_token_amount(usd_amount: U256, price: U256, decimals: u8) -> U256 {
+    if price.is_zero() {
+        return U256::zero();
+    }
+    usd_amount * expand_decimals(1, decimals as u32) / price
+}
+
+// 30 decimals usd to PLP amount at the sell price
+pub(crate) fn usd_to_plp_amount(state: &VaultState, usd_amount: U256) -> U256 {
+    let plp_price = state.get_plp_price(false);
+    if plp_price.is_zero() {
+        return U256::zero();
+    }
+    // ^30 * ^6 / ^18 = ^18
+    usd_amount * expand_decimals(1, 6) / plp_price
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::contracts::fixtures::{create_state, create_token, set_price, BTC, USDT};
+    use crate::contracts::token::Price;
+
+    // 50 BTC worth 10 USD each
+    fn create_btc() -> Token {
+        let mut token = create_token(BTC, "BTC", 500);
+        token.update_vault_info(expand_decimals(500, 18), U256::zero(), expand_decimals(50, 18), U256::zero());
+        set_price(&mut token, Price::new(dec!(10)));
+        token
+    }
+
+    #[test]
+    fn quote_buy_plp_works() {
+        let state = create_state();
+        let token = create_token(USDT, "USDT", 500);
+        let quote = state.quote_buy_plp(&token, expand_decimals(100, 18));
+        assert!(quote.is_available);
+        assert_eq!(quote.kind, QuoteKind::BuyPlp);
+        // token is at target weight, fee = 30 bps + tax on the 100 USDP deviation
+        assert_eq!(quote.fee_basis_points, 35);
+        assert_eq!(quote.amount_out, expand_decimals(100, 18) * 9965 / 10000);
+        assert_eq!(quote.fee_usd, expand_decimals(35, 28));
+        assert_eq!(quote.spread_basis_points, 0);
+    }
+
+    #[test]
+    fn quote_buy_plp_respects_max_usdp_amount() {
+        let state = create_state();
+        let mut token = create_token(USDT, "USDT", 500);
+        token.max_usdp_amount = Some(expand_decimals(550, 18));
+        let quote = state.quote_buy_plp(&token, expand_decimals(100, 18));
+        assert!(!quote.is_available);
+        assert_eq!(quote.unavailable_reason, Some("max USDP exceeded".to_string()));
+    }
+
+    #[test]
+    fn quote_sell_plp_works() {
+        let state = create_state();
+        let token = create_token(USDT, "USDT", 500);
+        let quote = state.quote_sell_plp(&token, expand_decimals(100, 18));
+        assert!(quote.is_available);
+        assert_eq!(quote.amount_out, expand_decimals(100, 18) * (10000 - quote.fee_basis_points) / 10000);
+
+        let quote = state.quote_sell_plp(&token, expand_decimals(600, 18));
+        assert!(!quote.is_available);
+    }
+
+    #[test]
+    fn quote_zero_weight_token_is_unavailable() {
+        let state = create_state();
+        // PLP, fPLP or a delisted token
+        let mut token = create_token(USDT, "USDT", 500);
+        token.token_weight = Some(0);
+        let other = create_btc();
+        let quotes = [
+            state.quote_buy_plp(&token, expand_decimals(100, 18)),
+            state.quote_sell_plp(&token, expand_decimals(100, 18)),
+            state.quote_swap(&other, &token, expand_decimals(1, 18)),
+        ];
+        for quote in quotes {
+            assert!(!quote.is_available);
+            assert_eq!(quote.unavailable_reason, Some("token not in pool".to_string()));
+        }
+    }
+
+    #[test]
+    fn quote_swap_works() {
+        let state = create_state();
+        let token_in = create_token(USDT, "USDT", 500);
+        let token_out = create_btc();
+        let quote = state.quote_swap(&token_in, &token_out, expand_decimals(100, 18));
+        assert!(quote.is_available);
+        assert!(quote.amount_out < expand_decimals(10, 18));
+        assert!(quote.fee_basis_points >= 30);
+    }
+
+    #[test]
+    fn spread_and_min_amount_out_works() {
+        let mut token = create_token(USDT, "USDT", 500);
+        token.min_price = Some(Price::new(dec!(99)));
+        token.max_price = Some(Price::new(dec!(100)));
+        assert_eq!(spread_basis_points(&token), 100);
+
+        let state = create_state();
+        let quote = state.quote_buy_plp(&token, expand_decimals(1, 18));
+        assert_eq!(quote.min_amount_out(50), quote.amount_out * 9950 / 10000);
+    }
+
+    #[test]
+    fn usd_conversion_works() {
+        let state = create_state();
+        assert_eq!(usd_to_usdp(expand_decimals(5, 30)), expand_decimals(5, 18));
+        assert_eq!(usd_to_token_amount(expand_decimals(100, 30), expand_decimals(10, 30), 18), expand_decimals(10, 18));
+        assert_eq!(usd_to_plp_amount(&state, expand_decimals(100, 30)), expand_decimals(100, 18));
+    }
+}
@@ -0,0 +1,347 @@
+use ethers::providers::{Middleware, MiddlewareError};
+use ethers::types::{Address, Filter, Log, ValueOrArray, H256};
+use serde::{Deserialize, Serialize};
+
+use crate::config::Chain;
+use crate::tx::http_provider;
+use super::events::{decode_log, event_topic, VaultEvent};
+
+const DEFAULT_CONFIRMATIONS: u64 = 12;
+const DEFAULT_MAX_RANGE: u64 = 5000;
+const DEFAULT_REORG_DEPTH: u64 = 12;
+
+/// Where the next scan starts, persist it to resume
+#[derive(Default, Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ScanCheckpoint {
+    // first block not scanned yet
+    pub next_block: u64,
+    // hash of next_block - 1 when it was scanned, to detect reorgs
+    pub last_block_hash: Option<H256>,
+}
+
+impl ScanCheckpoint {
+    pub fn from_block(block: u64) -> Self {
+        Self { next_block: block, last_block_hash: None }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ScannedEvent {
+    pub block_number: u64,
+    pub transaction_hash: Option<H256>,
+    pub log_index: u64,
+    pub event: VaultEvent,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ScanPage {
+    pub logs: Vec<Log>,
+    pub checkpoint: ScanCheckpoint,
+    // the last scanned block changed, events from this block on must be dropped, they are scanned again
+    pub reorg_from: Option<u64>,
+}
+
+impl ScanPage {
+    /// Logs decoded as vault events, logs of other events are skipped
+    pub fn vault_events(&self) -> Vec<ScannedEvent> {
+        self.logs
+            .iter()
+            .filter_map(|log| {
+                Some(ScannedEvent {
+                    block_number: log.block_number?.as_u64(),
+                    transaction_hash: log.transaction_hash,
+                    log_index: log.log_index.unwrap_or_default().low_u64(),
+                    event: decode_log(log)?,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Pages through eth_getLogs of an address and a set of event topics.
+/// Blocks newer than `confirmations` are left for a later scan, a range refused by the rpc is split in halves.
+/// On a reorg the scan restarts `reorg_depth` blocks before the checkpoint, whatever the confirmations.
+#[derive(Debug, Clone)]
+pub struct LogScanner {
+    address: Address,
+    topics: Vec<H256>,
+    // first indexed param, eg: the account of gateway events
+    topic1: Option<H256>,
+    // second indexed param, eg: the receiver of a transfer
+    topic2: Option<H256>,
+    confirmations: u64,
+    max_range: u64,
+    reorg_depth: u64,
+}
+
+impl LogScanner {
+    pub fn new(address: Address, topics: Vec<H256>) -> Self {
+        Self {
+            address,
+            topics,
+            topic1: None,
+            topic2: None,
+            confirmations: DEFAULT_CONFIRMATIONS,
+            max_range: DEFAULT_MAX_RANGE,
+            reorg_depth: DEFAULT_REORG_DEPTH,
+        }
+    }
+
+    /// Scanner of vault events by name, eg: ["Swap", "BuyUSDP", "SellUSDP"]
+    pub fn vault_events(vault: Address, names: &[&str]) -> anyhow::Result<Self> {
+        let topics = names
+            .iter()
+            .map(|name| event_topic(name).ok_or_else(|| anyhow::anyhow!("unknown vault event {}", name)))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(Self::new(vault, topics))
+    }
+
+    /// Only logs whose first indexed param is `account`
+    pub fn with_indexed_account(mut self, account: Address) -> Self {
+        self.topic1 = Some(H256::from(account));
+        self
+    }
+
+    /// Only logs whose second indexed param is `account`
+    pub fn with_second_indexed_account(mut self, account: Address) -> Self {
+        self.topic2 = Some(H256::from(account));
+        self
+    }
+
+    pub fn with_confirmations(mut self, confirmations: u64) -> Self {
+        self.confirmations = confirmations;
+        self
+    }
+
+    pub fn with_max_range(mut self, max_range: u64) -> Self {
+        self.max_range = max_range.max(1);
+        self
+    }
+
+    /// Blocks rescanned when the checkpoint block changed, at least 1
+    pub fn with_reorg_depth(mut self, reorg_depth: u64) -> Self {
+        self.reorg_depth = reorg_depth.max(1);
+        self
+    }
+
+    fn filter(&self, from_block: u64, to_block: u64) -> Filter {
+        let mut filter = Filter::new().address(self.address).from_block(from_block).to_block(to_block);
+        filter.topics[0] = Some(ValueOrArray::Array(self.topics.iter().map(|topic| Some(*topic)).collect()));
+        filter.topics[1] = self.topic1.map(|topic| ValueOrArray::Value(Some(topic)));
+        filter.topics[2] = self.topic2.map(|topic| ValueOrArray::Value(Some(topic)));
+        filter
+    }
+
+    async fn block_hash<M: Middleware + 'static>(provider: &M, block: u64) -> anyhow::Result<Option<H256>> {
+        Ok(provider.get_block(block).await?.and_then(|block| block.hash))
+    }
+
+    /// Logs from the checkpoint to `to_block` (or the last confirmed block) and the checkpoint to resume from
+    pub async fn scan<M: Middleware + 'static>(&self, provider: &M, checkpoint: &ScanCheckpoint, to_block: Option<u64>) -> anyhow::Result<ScanPage> {
+        let head = provider.get_block_number().await?.as_u64();
+        let mut end = head.saturating_sub(self.confirmations);
+        if let Some(to_block) = to_block {
+            end = end.min(to_block);
+        }
+
+        let mut from = checkpoint.next_block;
+        let mut reorg_from = None;
+        if let (Some(hash), true) = (checkpoint.last_block_hash, from > 0) {
+            if Self::block_hash(provider, from - 1).await? != Some(hash) {
+                from = from.saturating_sub(self.reorg_depth);
+                reorg_from = Some(from);
+            }
+        }
+        if from > end {
+            // the hash must be of the block before the rewound start, the next scan compares it
+            let last_block_hash = match (reorg_from, from) {
+                (None, _) => checkpoint.last_block_hash,
+                (Some(_), 0) => None,
+                (Some(_), from) => Self::block_hash(provider, from - 1).await?,
+            };
+            return Ok(ScanPage { logs: vec![], checkpoint: ScanCheckpoint { next_block: from, last_block_hash }, reorg_from });
+        }
+
+        let mut logs = vec![];
+        let mut range = self.max_range;
+        let mut start = from;
+        while start <= end {
+            let stop = end.min(start + range - 1);
+            match provider.get_logs(&self.filter(start, stop)).await {
+                Ok(page) => {
+                    logs.extend(page);
+                    start = stop + 1;
+                    range = (range * 2).min(self.max_range);
+                }
+                // too many results or a range too large for the rpc, retry with half
+                Err(e) if range > 1 && e.as_error_response().is_some_and(|error| is_range_error(&error.message)) => range /= 2,
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        let checkpoint = ScanCheckpoint { next_block: end + 1, last_block_hash: Self::block_hash(provider, end).await? };
+        Ok(ScanPage { logs, checkpoint, reorg_from })
+    }
+
+    /// `scan` on one of the chain rpc urls
+    pub async fn scan_chain(&self, chain: &Chain, checkpoint: &ScanCheckpoint, to_block: Option<u64>) -> anyhow::Result<ScanPage> {
+        self.scan(&http_provider(chain)?, checkpoint, to_block).await
+    }
+}
+
+// rpc refusals of a large query, eg: "query returned more than 10000 results", "exceed maximum block range: 5000".
+// Transport, auth or rate limit errors aren't retried.
+fn is_range_error(message: &str) -> bool {
+    let message = message.to_lowercase();
+    ["more than", "too many", "range", "too large", "response size"].iter().any(|pattern| message.contains(pattern))
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::providers::{JsonRpcError, MockProvider, MockResponse, Provider};
+    use ethers::types::{Block, U64};
+
+    use super::*;
+
+    const VAULT: &str = "0x1ed5051bbfa6b80f30d50d14761634ebb770f024";
+
+    fn create_block(number: u64, hash: u8) -> Block<H256> {
+        Block { number: Some(U64::from(number)), hash: Some(H256::repeat_byte(hash)), ..Block::default() }
+    }
+
+    fn create_log(block_number: u64) -> Log {
+        Log {
+            address: VAULT.parse().unwrap(),
+            topics: vec![event_topic("UpdateBorrowingRate").unwrap()],
+            data: ethabi::encode(&[ethabi::Token::Address(Address::repeat_byte(1)), ethabi::Token::Uint(100.into())]).into(),
+            block_number: Some(U64::from(block_number)),
+            ..Log::default()
+        }
+    }
+
+    fn push_error(mock: &MockProvider) {
+        mock.push_response(MockResponse::Error(JsonRpcError {
+            code: -32005,
+            message: "query returned more than 10000 results".to_string(),
+            data: None,
+        }));
+    }
+
+    fn create_scanner() -> LogScanner {
+        LogScanner::vault_events(VAULT.parse().unwrap(), &["UpdateBorrowingRate", "Swap"])
+            .unwrap()
+            .with_confirmations(10)
+            .with_max_range(20)
+            .with_reorg_depth(10)
+    }
+
+    #[test]
+    fn filter_matches_indexed_accounts() {
+        let scanner = create_scanner();
+        let filter = scanner.filter(1, 2);
+        assert!(filter.topics[1].is_none() && filter.topics[2].is_none());
+
+        let filter = scanner.with_indexed_account(Address::zero()).with_second_indexed_account(Address::repeat_byte(1)).filter(1, 2);
+        assert_eq!(filter.topics[1], Some(ValueOrArray::Value(Some(H256::zero()))));
+        assert_eq!(filter.topics[2], Some(ValueOrArray::Value(Some(H256::from(Address::repeat_byte(1))))));
+    }
+
+    #[tokio::test]
+    async fn scan_splits_refused_ranges() {
+        let (provider, mock) = Provider::mocked();
+        // popped from the back: head, [100, 119] refused, [100, 109], [110, 120], hash of 120
+        mock.push(create_block(120, 7)).unwrap();
+        mock.push::<Vec<Log>, _>(vec![create_log(115)]).unwrap();
+        mock.push::<Vec<Log>, _>(vec![create_log(101), create_log(102)]).unwrap();
+        push_error(&mock);
+        mock.push(U64::from(130)).unwrap();
+
+        let page = create_scanner().scan(&provider, &ScanCheckpoint::from_block(100), None).await.unwrap();
+        assert_eq!(page.logs.len(), 3);
+        assert_eq!(page.checkpoint, ScanCheckpoint { next_block: 121, last_block_hash: Some(H256::repeat_byte(7)) });
+        assert_eq!(page.reorg_from, None);
+
+        let events = page.vault_events();
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[2].block_number, 115);
+        assert!(matches!(events[0].event, VaultEvent::UpdateBorrowingRate(_)));
+    }
+
+    #[tokio::test]
+    async fn scan_rewinds_on_reorg() {
+        let (provider, mock) = Provider::mocked();
+        // block 120 changed, rescan from 111 to the confirmed 125
+        mock.push(create_block(125, 9)).unwrap();
+        mock.push::<Vec<Log>, _>(vec![create_log(112)]).unwrap();
+        mock.push(create_block(120, 8)).unwrap();
+        mock.push(U64::from(135)).unwrap();
+
+        let checkpoint = ScanCheckpoint { next_block: 121, last_block_hash: Some(H256::repeat_byte(7)) };
+        let page = create_scanner().scan(&provider, &checkpoint, None).await.unwrap();
+        assert_eq!(page.reorg_from, Some(111));
+        assert_eq!(page.logs.len(), 1);
+        assert_eq!(page.checkpoint.next_block, 126);
+
+        // nothing confirmed yet
+        mock.push(create_block(125, 9)).unwrap();
+        mock.push(U64::from(130)).unwrap();
+        let page = create_scanner().scan(&provider, &page.checkpoint, None).await.unwrap();
+        assert!(page.logs.is_empty());
+        assert_eq!(page.checkpoint.next_block, 126);
+    }
+
+    #[tokio::test]
+    async fn scan_does_not_retry_other_errors() {
+        let (provider, mock) = Provider::mocked();
+        // enough responses for the retries, none must be used
+        mock.push(create_block(120, 7)).unwrap();
+        mock.push::<Vec<Log>, _>(vec![]).unwrap();
+        mock.push::<Vec<Log>, _>(vec![]).unwrap();
+        mock.push_response(MockResponse::Error(JsonRpcError { code: -32000, message: "unauthorized".to_string(), data: None }));
+        mock.push(U64::from(130)).unwrap();
+
+        let result = create_scanner().scan(&provider, &ScanCheckpoint::from_block(100), None).await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn scan_rewinds_without_confirmations() {
+        let (provider, mock) = Provider::mocked();
+        let scanner = create_scanner().with_confirmations(0).with_reorg_depth(5);
+        // block 120 changed, rescan from 116 to the head
+        mock.push(create_block(125, 9)).unwrap();
+        mock.push::<Vec<Log>, _>(vec![create_log(118)]).unwrap();
+        mock.push(create_block(120, 8)).unwrap();
+        mock.push(U64::from(125)).unwrap();
+
+        let checkpoint = ScanCheckpoint { next_block: 121, last_block_hash: Some(H256::repeat_byte(7)) };
+        let page = scanner.scan(&provider, &checkpoint, None).await.unwrap();
+        assert_eq!(page.reorg_from, Some(116));
+        assert_eq!(page.logs.len(), 1);
+        assert_eq!(page.checkpoint, ScanCheckpoint { next_block: 126, last_block_hash: Some(H256::repeat_byte(9)) });
+    }
+
+    #[tokio::test]
+    async fn scan_rewind_keeps_a_matching_hash() {
+        let (provider, mock) = Provider::mocked();
+        let scanner = create_scanner().with_reorg_depth(5);
+        // block 120 changed, the rewound start 116 isn't confirmed yet: the checkpoint holds the hash of 115
+        mock.push(create_block(115, 5)).unwrap();
+        mock.push(create_block(120, 8)).unwrap();
+        mock.push(U64::from(125)).unwrap();
+
+        let checkpoint = ScanCheckpoint { next_block: 121, last_block_hash: Some(H256::repeat_byte(7)) };
+        let page = scanner.scan(&provider, &checkpoint, None).await.unwrap();
+        assert_eq!(page.reorg_from, Some(116));
+        assert_eq!(page.checkpoint, ScanCheckpoint { next_block: 116, last_block_hash: Some(H256::repeat_byte(5)) });
+
+        // 115 unchanged, no second rewind
+        mock.push(create_block(121, 6)).unwrap();
+        mock.push::<Vec<Log>, _>(vec![]).unwrap();
+        mock.push(create_block(115, 5)).unwrap();
+        mock.push(U64::from(131)).unwrap();
+        let page = scanner.scan(&provider, &page.checkpoint, None).await.unwrap();
+        assert_eq!(page.reorg_from, None);
+        assert_eq!(page.checkpoint, ScanCheckpoint { next_block: 122, last_block_hash: Some(H256::repeat_byte(6)) });
+    }
+}
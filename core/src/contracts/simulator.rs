@@ -0,0 +1,328 @@
+use anyhow::{anyhow, bail};
+use ethabi::ethereum_types::U256;
+
+use super::quote::{usd_to_token_amount, usd_to_usdp, Quote};
+use super::token::Token;
+use super::vault::VaultState;
+use super::vault_logic::{expand_decimals, VaultLogic, BASIS_POINTS_DIVISOR};
+
+/// What-if copy of the vault. Every action updates the state the way the Vault and PlpManager
+/// contracts do, so quotes can be chained: the second quote sees the result of the first one.
+#[derive(Debug, Clone)]
+pub struct Simulator {
+    pub state: VaultState,
+    pub tokens: Vec<Token>,
+}
+
+impl Simulator {
+    pub fn new(state: &VaultState, tokens: &[Token]) -> Self {
+        Self {
+            state: state.clone(),
+            tokens: tokens.to_vec(),
+        }
+    }
+
+    pub fn token(&self, address: &str) -> Option<&Token> {
+        self.tokens.iter().find(|token| token.address.eq_ignore_ascii_case(address))
+    }
+
+    fn token_index(&self, address: &str) -> anyhow::Result<usize> {
+        self.tokens
+            .iter()
+            .position(|token| token.address.eq_ignore_ascii_case(address))
+            .ok_or_else(|| anyhow!("unknown token {}", address))
+    }
+
+    /// Mint PLP with `amount_in` of `token`, Vault.buyUSDP + PlpManager.addLiquidity
+    pub fn buy_plp(&mut self, token: &str, amount_in: U256) -> anyhow::Result<Quote> {
+        let index = self.token_index(token)?;
+        let quote = self.state.quote_buy_plp(&self.tokens[index], amount_in);
+        ensure_available(&quote)?;
+
+        let token = &mut self.tokens[index];
+        let fee_amount = amount_in * U256::from(quote.fee_basis_points) / *BASIS_POINTS_DIVISOR;
+        let amount_after_fees = amount_in - fee_amount;
+        let usd_after_fees = token_to_usd(amount_after_fees, min_price(token), token.decimals);
+        let usdp_amount = usd_to_usdp(usd_after_fees);
+
+        update_token(
+            token,
+            token.usdp_amount.unwrap_or_default() + usdp_amount,
+            token.fee_reserves.unwrap_or_default() + fee_amount,
+            token.pool_amounts.unwrap_or_default() + amount_after_fees,
+            token.reserved_amounts.unwrap_or_default(),
+        );
+        self.state.usdp_supply += usdp_amount;
+        self.state.total_aum[0] += usd_after_fees;
+        self.state.total_aum[1] += usd_after_fees;
+        self.state.plp_supply += quote.amount_out;
+        Ok(quote)
+    }
+
+    /// Redeem `plp_amount` of PLP for `token`, PlpManager.removeLiquidity + Vault.sellUSDP
+    pub fn sell_plp(&mut self, token: &str, plp_amount: U256) -> anyhow::Result<Quote> {
+        let index = self.token_index(token)?;
+        let quote = self.state.quote_sell_plp(&self.tokens[index], plp_amount);
+        ensure_available(&quote)?;
+
+        // ^18 * ^18 * ^12 / ^18 = ^30
+        let usd_out = plp_amount * self.state.get_plp_price(false) * expand_decimals(1, 12) / expand_decimals(1, 18);
+        let usdp_amount = usd_to_usdp(usd_out);
+        let token = &mut self.tokens[index];
+        let redemption_amount = usd_to_token_amount(usd_out, max_price(token), token.decimals);
+        let fee_amount = redemption_amount - quote.amount_out;
+
+        update_token(
+            token,
+            token.usdp_amount.unwrap_or_default().saturating_sub(usdp_amount),
+            token.fee_reserves.unwrap_or_default() + fee_amount,
+            token.pool_amounts.unwrap_or_default().saturating_sub(redemption_amount),
+            token.reserved_amounts.unwrap_or_default(),
+        );
+        self.state.usdp_supply = self.state.usdp_supply.saturating_sub(usdp_amount);
+        self.state.total_aum[0] = self.state.total_aum[0].saturating_sub(usd_out);
+        self.state.total_aum[1] = self.state.total_aum[1].saturating_sub(usd_out);
+        self.state.plp_supply = self.state.plp_supply.saturating_sub(plp_amount);
+        Ok(quote)
+    }
+
+    /// Swap `amount_in` of `token_in` for `token_out`, Vault.swap
+    pub fn swap(&mut self, token_in: &str, token_out: &str, amount_in: U256) -> anyhow::Result<Quote> {
+        let index_in = self.token_index(token_in)?;
+        let index_out = self.token_index(token_out)?;
+        if index_in == index_out {
+            bail!("cannot swap {} for itself", token_in);
+        }
+        let quote = self.state.quote_swap(&self.tokens[index_in], &self.tokens[index_out], amount_in);
+        ensure_available(&quote)?;
+
+        let usd_in = token_to_usd(amount_in, min_price(&self.tokens[index_in]), self.tokens[index_in].decimals);
+        let usdp_amount = usd_to_usdp(usd_in);
+        let (amount_out, fee_amount, _) = self.state.get_swap_details(&self.tokens[index_in], &self.tokens[index_out], amount_in);
+        let usd_out = token_to_usd(amount_out, max_price(&self.tokens[index_out]), self.tokens[index_out].decimals);
+
+        let token = &mut self.tokens[index_in];
+        update_token(
+            token,
+            token.usdp_amount.unwrap_or_default() + usdp_amount,
+            token.fee_reserves.unwrap_or_default(),
+            token.pool_amounts.unwrap_or_default() + amount_in,
+            token.reserved_amounts.unwrap_or_default(),
+        );
+        let token = &mut self.tokens[index_out];
+        update_token(
+            token,
+            token.usdp_amount.unwrap_or_default().saturating_sub(usdp_amount),
+            token.fee_reserves.unwrap_or_default() + fee_amount,
+            token.pool_amounts.unwrap_or_default().saturating_sub(amount_out),
+            token.reserved_amounts.unwrap_or_default(),
+        );
+        // the pool keeps the value of amount_in and loses amount_out before fees
+        for aum in self.state.total_aum.iter_mut() {
+            *aum = (*aum + usd_in).saturating_sub(usd_out);
+        }
+        Ok(quote)
+    }
+
+    /// Open or increase a position of `size_delta` USD (30 decimals) with `collateral_amount` of
+    /// `collateral_token`, Vault.increasePosition. Returns the margin fee in USD.
+    pub fn increase_position(
+        &mut self,
+        collateral_token: &str,
+        index_token: &str,
+        collateral_amount: U256,
+        size_delta: U256,
+        is_long: bool,
+    ) -> anyhow::Result<U256> {
+        let collateral_index = self.token_index(collateral_token)?;
+        let index_index = self.token_index(index_token)?;
+        if is_long && collateral_index != index_index {
+            bail!("long collateral must be the index token");
+        }
+
+        let fee_usd = size_delta * self.state.margin_fee_basis_points / *BASIS_POINTS_DIVISOR;
+        let token = &mut self.tokens[collateral_index];
+        let fee_amount = usd_to_token_amount(fee_usd, min_price(token), token.decimals);
+        let reserve_delta = usd_to_token_amount(size_delta, min_price(token), token.decimals);
+        let reserved_amounts = token.reserved_amounts.unwrap_or_default() + reserve_delta;
+        let mut pool_amounts = token.pool_amounts.unwrap_or_default();
+        if is_long {
+            // collateral joins the pool, the fee goes to the fee reserves
+            pool_amounts = (pool_amounts + collateral_amount).saturating_sub(fee_amount);
+        }
+        if reserved_amounts > pool_amounts {
+            bail!("reserve exceeds pool");
+        }
+        update_token(
+            token,
+            token.usdp_amount.unwrap_or_default(),
+            token.fee_reserves.unwrap_or_default() + fee_amount,
+            pool_amounts,
+            reserved_amounts,
+        );
+
+        let token = &mut self.tokens[index_index];
+        if is_long {
+            token.global_long_size = Some(token.global_long_size.unwrap_or_default() + size_delta);
+        } else {
+            token.global_short_size = Some(token.global_short_size.unwrap_or_default() + size_delta);
+        }
+        Ok(fee_usd)
+    }
+
+    /// Decrease a position by `size_delta` USD and withdraw `collateral_delta` USD (30 decimals),
+    /// Vault.decreasePosition. Profit and loss is not simulated. Returns the margin fee in USD.
+    pub fn decrease_position(
+        &mut self,
+        collateral_token: &str,
+        index_token: &str,
+        collateral_delta: U256,
+        size_delta: U256,
+        is_long: bool,
+    ) -> anyhow::Result<U256> {
+        let collateral_index = self.token_index(collateral_token)?;
+        let index_index = self.token_index(index_token)?;
+
+        let fee_usd = size_delta * self.state.margin_fee_basis_points / *BASIS_POINTS_DIVISOR;
+        let token = &mut self.tokens[collateral_index];
+        let reserve_delta = usd_to_token_amount(size_delta, min_price(token), token.decimals);
+        let mut pool_amounts = token.pool_amounts.unwrap_or_default();
+        if is_long {
+            // collateral paid out leaves the pool
+            pool_amounts = pool_amounts.saturating_sub(usd_to_token_amount(collateral_delta, max_price(token), token.decimals));
+        }
+        update_token(
+            token,
+            token.usdp_amount.unwrap_or_default(),
+            token.fee_reserves.unwrap_or_default() + usd_to_token_amount(fee_usd, max_price(token), token.decimals),
+            pool_amounts,
+            token.reserved_amounts.unwrap_or_default().saturating_sub(reserve_delta),
+        );
+
+        let token = &mut self.tokens[index_index];
+        if is_long {
+            token.global_long_size = Some(token.global_long_size.unwrap_or_default().saturating_sub(size_delta));
+        } else {
+            token.global_short_size = Some(token.global_short_size.unwrap_or_default().saturating_sub(size_delta));
+        }
+        Ok(fee_usd)
+    }
+}
+
+fn ensure_available(quote: &Quote) -> anyhow::Result<()> {
+    if !quote.is_available {
+        bail!("{}", quote.unavailable_reason.clone().unwrap_or_default());
+    }
+    Ok(())
+}
+
+fn update_token(token: &mut Token, usdp_amount: U256, fee_reserves: U256, pool_amounts: U256, reserved_amounts: U256) {
+    token.update_vault_info(usdp_amount, fee_reserves, pool_amounts, reserved_amounts.min(pool_amounts));
+}
+
+fn min_price(token: &Token) -> U256 {
+    token.min_price.map(|price| price.raw).unwrap_or_default()
+}
+
+fn max_price(token: &Token) -> U256 {
+    token.max_price.map(|price| price.raw).unwrap_or_default()
+}
+
+pub(crate) fn token_to_usd(amount: U256, price: U256, decimals: u8) -> U256 {
+    amount * price / expand_decimals(1, decimals as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contracts::fixtures::{self, create_state, BTC, USDT};
+
+    fn create_token(address: &str, symbol: &str, usdp_amount: u32, is_stable_token: bool) -> Token {
+        let mut token = fixtures::create_token(address, symbol, usdp_amount);
+        token.is_stable_token = Some(is_stable_token);
+        token
+    }
+
+    fn create_simulator() -> Simulator {
+        let tokens = vec![create_token(USDT, "USDT", 500, true), create_token(BTC, "BTC", 500, false)];
+        Simulator::new(&create_state(), &tokens)
+    }
+
+    #[test]
+    fn buy_plp_updates_state() {
+        let mut simulator = create_simulator();
+        let quote = simulator.buy_plp(BTC, expand_decimals(100, 18)).unwrap();
+        let btc = simulator.token(BTC).unwrap();
+        // 35 bps fee stays in the fee reserves
+        assert_eq!(btc.pool_amounts, Some(expand_decimals(5_99650, 15)));
+        assert_eq!(btc.usdp_amount, btc.pool_amounts);
+        assert_eq!(btc.fee_reserves, Some(expand_decimals(350, 15)));
+        assert_eq!(simulator.state.usdp_supply, expand_decimals(1_099650, 15));
+        assert_eq!(simulator.state.total_aum[0], expand_decimals(1_099650, 27));
+        assert_eq!(simulator.state.plp_supply, expand_decimals(1000, 18) + quote.amount_out);
+    }
+
+    #[test]
+    fn chained_mints_get_more_expensive() {
+        let mut simulator = create_simulator();
+        let first = simulator.buy_plp(BTC, expand_decimals(100, 18)).unwrap();
+        let second = simulator.buy_plp(BTC, expand_decimals(100, 18)).unwrap();
+        assert!(second.fee_basis_points > first.fee_basis_points);
+        // the original state is untouched
+        let state = create_state();
+        assert_eq!(state.quote_buy_plp(&create_token(BTC, "BTC", 500, false), expand_decimals(100, 18)).fee_basis_points, first.fee_basis_points);
+    }
+
+    #[test]
+    fn sell_plp_updates_state() {
+        let mut simulator = create_simulator();
+        let quote = simulator.sell_plp(USDT, expand_decimals(100, 18)).unwrap();
+        let usdt = simulator.token(USDT).unwrap();
+        assert_eq!(usdt.pool_amounts, Some(expand_decimals(400, 18)));
+        assert_eq!(usdt.usdp_amount, Some(expand_decimals(400, 18)));
+        assert_eq!(usdt.fee_reserves, Some(expand_decimals(100, 18) - quote.amount_out));
+        assert_eq!(simulator.state.usdp_supply, expand_decimals(900, 18));
+        assert_eq!(simulator.state.total_aum[1], expand_decimals(900, 30));
+        assert_eq!(simulator.state.plp_supply, expand_decimals(900, 18));
+    }
+
+    #[test]
+    fn swap_moves_usdp_between_tokens() {
+        let mut simulator = create_simulator();
+        let quote = simulator.swap(USDT, BTC, expand_decimals(100, 18)).unwrap();
+        let usdt = simulator.token(USDT).unwrap();
+        assert_eq!(usdt.pool_amounts, Some(expand_decimals(600, 18)));
+        assert_eq!(usdt.usdp_amount, Some(expand_decimals(600, 18)));
+        let btc = simulator.token(BTC).unwrap();
+        assert_eq!(btc.pool_amounts, Some(expand_decimals(400, 18)));
+        assert_eq!(btc.usdp_amount, Some(expand_decimals(400, 18)));
+        assert_eq!(btc.fee_reserves, Some(expand_decimals(100, 18) - quote.amount_out));
+        assert_eq!(simulator.state.usdp_supply, expand_decimals(1000, 18));
+        assert!(simulator.swap(BTC, BTC, U256::one()).is_err());
+    }
+
+    #[test]
+    fn positions_reserve_liquidity() {
+        let mut simulator = create_simulator();
+        let fee = simulator.increase_position(BTC, BTC, expand_decimals(10, 18), expand_decimals(100, 30), true).unwrap();
+        assert_eq!(fee, expand_decimals(1, 29));
+        let btc = simulator.token(BTC).unwrap();
+        assert_eq!(btc.reserved_amounts, Some(expand_decimals(100, 18)));
+        assert_eq!(btc.pool_amounts, Some(expand_decimals(5099, 17)));
+        assert_eq!(btc.available_amount, Some(expand_decimals(4099, 17)));
+        assert_eq!(btc.global_long_size, Some(expand_decimals(100, 30)));
+
+        // a redeem bigger than the free liquidity is rejected
+        assert!(simulator.sell_plp(BTC, expand_decimals(450, 18)).is_err());
+
+        simulator.decrease_position(BTC, BTC, expand_decimals(9, 30), expand_decimals(100, 30), true).unwrap();
+        let btc = simulator.token(BTC).unwrap();
+        assert_eq!(btc.reserved_amounts, Some(U256::zero()));
+        assert_eq!(btc.global_long_size, Some(U256::zero()));
+
+        simulator.increase_position(USDT, BTC, expand_decimals(10, 18), expand_decimals(50, 30), false).unwrap();
+        assert_eq!(simulator.token(USDT).unwrap().reserved_amounts, Some(expand_decimals(50, 18)));
+        assert_eq!(simulator.token(BTC).unwrap().global_short_size, Some(expand_decimals(50, 30)));
+        assert!(simulator.increase_position(USDT, BTC, U256::zero(), expand_decimals(10, 30), true).is_err());
+    }
+}
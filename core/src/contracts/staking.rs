@@ -0,0 +1,241 @@
+use std::collections::HashMap;
+
+use ethabi::ParamType;
+use ethers::types::{Address, Bytes, U256};
+use serde::{Deserialize, Serialize};
+
+use crate::config::Chain;
+use crate::utils::get_encode_address_and_params;
+use super::multicall::ChainMulticallTrait;
+use super::vault::VaultState;
+
+/// Pool wide figures of a reward tracker, eg: fPLP
+#[derive(Default, Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct RewardTrackerInfo {
+    pub address: Address,
+    pub reward_token: Address,
+    // rewards distributed per second, reward token decimals
+    pub tokens_per_interval: U256,
+    // total staked in the tracker, 18 decimals
+    pub total_supply: U256,
+}
+
+/// Staking position of one account in one reward tracker
+#[derive(Default, Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct StakingInfo {
+    pub reward_tracker: Address,
+    pub staked_amount: U256,
+    // deposit token in the tracker: PLP in fPLP, fPLP in fsPLP
+    pub deposit_balance: U256,
+    pub claimable: U256,
+    pub cumulative_rewards: U256,
+    pub average_staked_amount: U256,
+}
+
+/// Pool figures of each reward tracker with the staking position of the account when read
+pub type StakingResults = Vec<(RewardTrackerInfo, Option<StakingInfo>)>;
+
+// rewardToken, tokensPerInterval, totalSupply
+const POOL_CALLS: usize = 3;
+// stakedAmounts, depositBalances, claimable, cumulativeRewards, averageStakedAmounts
+const ACCOUNT_CALLS: usize = 5;
+
+/// Multicall calls reading `reward_tracker`, the account calls are skipped when `account` is None.
+/// `deposit_token` is the token staked in the tracker, read by depositBalances.
+pub fn build_staking_calls(reward_tracker: &str, deposit_token: Address, account: Option<Address>) -> Vec<(Address, Bytes)> {
+    let mut calls = vec![
+        get_encode_address_and_params(reward_tracker, "rewardToken()", &[]),
+        get_encode_address_and_params(reward_tracker, "tokensPerInterval()", &[]),
+        get_encode_address_and_params(reward_tracker, "totalSupply()", &[]),
+    ];
+    if let Some(account) = account {
+        let param = [ethabi::Token::Address(account)];
+        calls.extend(vec![
+            get_encode_address_and_params(reward_tracker, "stakedAmounts(address)", &param),
+            get_encode_address_and_params(
+                reward_tracker,
+                "depositBalances(address,address)",
+                &[ethabi::Token::Address(account), ethabi::Token::Address(deposit_token)],
+            ),
+            get_encode_address_and_params(reward_tracker, "claimable(address)", &param),
+            get_encode_address_and_params(reward_tracker, "cumulativeRewards(address)", &param),
+            get_encode_address_and_params(reward_tracker, "averageStakedAmounts(address)", &param),
+        ]);
+    }
+    calls
+}
+
+/// Decode the return data of `build_staking_calls`
+pub fn decode_staking_results(reward_tracker: &str, results: &[Bytes]) -> anyhow::Result<(RewardTrackerInfo, Option<StakingInfo>)> {
+    if results.len() != POOL_CALLS && results.len() != POOL_CALLS + ACCOUNT_CALLS {
+        anyhow::bail!("Invalid reward tracker return data, expected {} or {} results", POOL_CALLS, POOL_CALLS + ACCOUNT_CALLS);
+    }
+    let reward_tracker: Address = reward_tracker.parse()?;
+    let decode_uint = |data: &Bytes| -> anyhow::Result<U256> {
+        ethabi::decode(&[ParamType::Uint(256)], data)?[0]
+            .clone()
+            .into_uint()
+            .ok_or_else(|| anyhow::anyhow!("Failed to parse uint"))
+    };
+    let reward_token = ethabi::decode(&[ParamType::Address], &results[0])?[0]
+        .clone()
+        .into_address()
+        .ok_or_else(|| anyhow::anyhow!("Failed to parse reward token"))?;
+
+    let tracker = RewardTrackerInfo {
+        address: reward_tracker,
+        reward_token,
+        tokens_per_interval: decode_uint(&results[1])?,
+        total_supply: decode_uint(&results[2])?,
+    };
+    if results.len() == POOL_CALLS {
+        return Ok((tracker, None));
+    }
+    let staking = StakingInfo {
+        reward_tracker,
+        staked_amount: decode_uint(&results[3])?,
+        deposit_balance: decode_uint(&results[4])?,
+        claimable: decode_uint(&results[5])?,
+        cumulative_rewards: decode_uint(&results[6])?,
+        average_staked_amount: decode_uint(&results[7])?,
+    };
+    Ok((tracker, Some(staking)))
+}
+
+/// Read every `reward_trackers`, with their deposit token, in one multicall
+pub async fn fetch_staking(
+    chain: &Chain,
+    reward_trackers: &[(String, Address)],
+    account: Option<&str>,
+) -> anyhow::Result<StakingResults> {
+    let account: Option<Address> = account
+        .map(|account| account.parse().map_err(|_| anyhow::anyhow!("Invalid account {}", account)))
+        .transpose()?;
+    let calls: Vec<_> = reward_trackers
+        .iter()
+        .flat_map(|(reward_tracker, deposit_token)| build_staking_calls(reward_tracker, *deposit_token, account))
+        .collect();
+    if calls.is_empty() {
+        return Ok(vec![]);
+    }
+    let results = chain.execute_multicall_raw(calls).await.map_err(|e| anyhow::anyhow!(e))?;
+    let per_tracker = results.len() / reward_trackers.len();
+    reward_trackers
+        .iter()
+        .zip(results.chunks(per_tracker))
+        .map(|((reward_tracker, _), results)| decode_staking_results(reward_tracker, results))
+        .collect()
+}
+
+impl VaultState {
+    /// Store the reward tracker figures and the staking positions of `account`.
+    /// `staked_plp` holds the PLP staked by each account in the first (fPLP) tracker.
+    pub fn update_staking(&mut self, account: Option<Address>, results: StakingResults) {
+        let mut positions = vec![];
+        self.reward_trackers = vec![];
+        for (tracker, staking) in results {
+            self.reward_trackers.push(tracker);
+            if let Some(staking) = staking {
+                positions.push(staking);
+            }
+        }
+        let account = match account {
+            Some(account) => account,
+            None => return,
+        };
+        if let Some(staking) = positions.first() {
+            self.staked_plp.get_or_insert_with(HashMap::new).insert(account, staking.staked_amount);
+        }
+        self.staking_info.get_or_insert_with(HashMap::new).insert(account, positions);
+    }
+
+    pub fn get_staking_info(&self, account: &Address) -> Vec<StakingInfo> {
+        self.staking_info
+            .as_ref()
+            .and_then(|staking_info| staking_info.get(account))
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::utils::hex;
+
+    use super::*;
+
+    const REWARD_TRACKER: &str = "0x50c121da29cbd8bd96bdb6c6965f93f09c2fc598";
+    const PLP_TOKEN: &str = "0x542e4676238562b518b968a1d03626d544a7bca2";
+    const ACCOUNT: &str = "0x1f9840a85d5af5bf1d1762f925bdaddc4201f984";
+
+    fn encode_uint(value: u64) -> Bytes {
+        ethabi::encode(&[ethabi::Token::Uint(U256::from(value))]).into()
+    }
+
+    fn create_results() -> Vec<Bytes> {
+        let reward_token: Address = PLP_TOKEN.parse().unwrap();
+        vec![
+            ethabi::encode(&[ethabi::Token::Address(reward_token)]).into(),
+            encode_uint(100),
+            encode_uint(5000),
+            encode_uint(1000),
+            encode_uint(900),
+            encode_uint(7),
+            encode_uint(20),
+            encode_uint(800),
+        ]
+    }
+
+    #[test]
+    fn build_staking_calls_works() {
+        let plp_token: Address = PLP_TOKEN.parse().unwrap();
+        let calls = build_staking_calls(REWARD_TRACKER, plp_token, None);
+        assert_eq!(calls.len(), POOL_CALLS);
+        let calls = build_staking_calls(REWARD_TRACKER, plp_token, Some(ACCOUNT.parse().unwrap()));
+        assert_eq!(calls.len(), POOL_CALLS + ACCOUNT_CALLS);
+        assert_eq!(calls[3].0, REWARD_TRACKER.parse::<Address>().unwrap());
+        // stakedAmounts(address)
+        assert_eq!(
+            hex::encode(&calls[3].1),
+            "10c1c1030000000000000000000000001f9840a85d5af5bf1d1762f925bdaddc4201f984"
+        );
+    }
+
+    #[tokio::test]
+    async fn fetch_staking_rejects_invalid_account() {
+        let trackers = vec![(REWARD_TRACKER.to_string(), PLP_TOKEN.parse().unwrap())];
+        // fails before any request
+        let result = fetch_staking(&Chain::default(), &trackers, Some("not an account")).await;
+        assert!(result.unwrap_err().to_string().contains("Invalid account"));
+    }
+
+    #[test]
+    fn decode_staking_results_works() {
+        let (tracker, staking) = decode_staking_results(REWARD_TRACKER, &create_results()).unwrap();
+        assert_eq!(tracker.reward_token, PLP_TOKEN.parse::<Address>().unwrap());
+        assert_eq!(tracker.tokens_per_interval, U256::from(100));
+        assert_eq!(tracker.total_supply, U256::from(5000));
+        let staking = staking.expect("no staking info");
+        assert_eq!(staking.staked_amount, U256::from(1000));
+        assert_eq!(staking.deposit_balance, U256::from(900));
+        assert_eq!(staking.claimable, U256::from(7));
+        assert_eq!(staking.cumulative_rewards, U256::from(20));
+        assert_eq!(staking.average_staked_amount, U256::from(800));
+
+        let (_, staking) = decode_staking_results(REWARD_TRACKER, &create_results()[..POOL_CALLS]).unwrap();
+        assert!(staking.is_none());
+        assert!(decode_staking_results(REWARD_TRACKER, &create_results()[..4]).is_err());
+    }
+
+    #[test]
+    fn update_staking_populates_state() {
+        let mut state = VaultState::default();
+        let account: Address = ACCOUNT.parse().unwrap();
+        let results = vec![decode_staking_results(REWARD_TRACKER, &create_results()).unwrap()];
+        state.update_staking(Some(account), results);
+        assert_eq!(state.reward_trackers.len(), 1);
+        assert_eq!(state.staked_plp.as_ref().unwrap().get(&account), Some(&U256::from(1000)));
+        assert_eq!(state.get_staking_info(&account).len(), 1);
+        assert!(state.get_staking_info(&Address::zero()).is_empty());
+    }
+}
@@ -164,7 +164,7 @@ impl Token {
     pub fn build_get_staked_amount(&self, account: &String, reward_tracker : &String) -> (Address, Bytes) {
         let account: Address = account.parse().expect("Invalid account");
         let token: Address = reward_tracker.parse().expect("Invalid account");;
-        let function_name = "pairAmounts";
+        let function_name = "stakedAmounts";
         let reward_tracker_abi = include_str!("../../abi/reward_tracker.json");
         let contract = Contract::load(reward_tracker_abi.as_bytes()).unwrap();
         let function = contract.function(function_name).unwrap();
@@ -325,6 +325,14 @@ impl Token {
             .get(&addr);
         return balance.unwrap_or_else(|| &Decimal::ZERO).to_string();
     }
+
+    /// Balance of the account in token decimals
+    pub fn get_balance_raw(&self, account: &String) -> U256 {
+        let balance = self.get_balance(account);
+        ethers::utils::parse_units(balance, self.decimals as u32)
+            .map(|value| value.into())
+            .unwrap_or(U256::zero())
+    }
     pub fn get_allowance(&self, account: &String, spender : &String) -> String {
         let addr: Address = account.parse().unwrap();
         let spender: Address = spender.parse().unwrap();
@@ -349,13 +357,13 @@ impl Token {
         }
     }
 
-    fn get_parsed_address(&self) -> Address {
+    pub(crate) fn get_parsed_address(&self) -> Address {
         return self.address.parse().expect("Token address parse error");
     }
 
 }
 
-fn format_units(value: U256, decimals: u32) -> anyhow::Result<Decimal> {
+pub(crate) fn format_units(value: U256, decimals: u32) -> anyhow::Result<Decimal> {
     let value = ethers::utils::format_units(value, decimals)?;
     let value = Decimal::from_str(&value)?;
     Ok(value)
@@ -434,6 +442,17 @@ mod tests {
         test_build_vault_fn_call!("1f3567170000000000000000000000001f9840a85d5af5bf1d1762f925bdaddc4201f984", build_get_ask_price_call);
     }
 
+    #[test]
+    fn build_get_staked_amount_works() {
+        let token = create_mock_token();
+        let (address, data) = token.build_get_staked_amount(
+            &"0x1f9840a85d5af5bf1d1762f925bdaddc4201f984".to_string(),
+            &"0x50c121da29cbd8bd96bdb6c6965f93f09c2fc598".to_string()
+        );
+        assert_eq!(address, Address::from_str("0x50c121da29cbd8bd96bdb6c6965f93f09c2fc598").unwrap());
+        assert_eq!(hex::encode(data), "10c1c1030000000000000000000000001f9840a85d5af5bf1d1762f925bdaddc4201f984".to_string());
+    }
+
     // test for update functions
     #[test]
     fn update_token_configuration_works() {
@@ -460,6 +479,8 @@ mod tests {
         assert_eq!(token.get_balance(&user1).parse::<f64>().unwrap(), 1000.0);
         assert_eq!(token.get_balance(&user2).parse::<f64>().unwrap(), 2000.0);
         assert_eq!(token.get_balance(&user3).parse::<f64>().unwrap(), 0.0);
+        assert_eq!(token.get_balance_raw(&user1), ethers::utils::parse_ether(1000).unwrap());
+        assert_eq!(token.get_balance_raw(&user3), U256::zero());
     }
 
     #[test]
@@ -1,25 +1,27 @@
-use std::cell::RefCell;
-use std::rc::Rc;
 use std::sync::Arc;
 use std::{time::Duration};
 use std::collections::HashMap;
 
-use ethers::types::{Address, Bytes, U256};
+use ethers::types::{Address, U256};
 use instant::Instant;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use tokio::sync::Mutex;
-use tokio::sync::Notify;
-use crate::config::{Chain, ContractAddress};
-use crate::log;
+use crate::config::{Chain, Config, ContractAddress};
 use crate::utils::*;
-use super::token::{Token, Price};
 use super::multicall::*;
 use super::types::TokensArc;
+use super::staking::{RewardTrackerInfo, StakingInfo};
+use super::vester::VestingInfo;
+use super::positions::PositionInfo;
+use super::freshness::{FetchedAt, FreshnessPolicy};
+use crate::scheduler::DataClass;
+use crate::fetch_plan::{FetchComponent, FetchPlan};
 use ethabi::Token as AbiToken;
 
-#[derive(Default, Debug, Serialize, Clone)]
+#[derive(Default, Debug, Serialize, Deserialize, Clone)]
 pub struct VaultState {
     pub usdp_address: Address,
+    pub plp_address: Address,
     pub fee_basis_points: u32,
     pub tax_basis_points: u32,
     pub usdp_supply: U256,
@@ -43,9 +45,24 @@ pub struct VaultState {
     pub borrowing_rate_interval: Duration,
     pub borrowing_rate_factor: U256,
     pub stable_borrowing_rate_factor: U256,
+    // wei, zero when the vault doesn't limit the gas price
+    pub max_gas_price: U256,
 
     pub staked_plp: Option<HashMap<Address, U256>>,
-    pub reserved_amount : Option<HashMap<Address, U256>>
+    pub reserved_amount : Option<HashMap<Address, U256>>,
+
+    // reward trackers and staking positions per account
+    pub reward_trackers: Vec<RewardTrackerInfo>,
+    pub staking_info: Option<HashMap<Address, Vec<StakingInfo>>>,
+    pub vesting_info: Option<HashMap<Address, VestingInfo>>,
+    #[serde(default)]
+    pub positions: Option<HashMap<Address, Vec<PositionInfo>>>,
+
+    // when each data class was fetched, see freshness
+    #[serde(default)]
+    pub fetched_at: HashMap<DataClass, FetchedAt>,
+    #[serde(default)]
+    pub freshness_policy: FreshnessPolicy,
 }
 
 #[derive(Default, Debug, Clone)]
@@ -54,27 +71,26 @@ pub struct Vault {
     pub plp_manager: String,
     pub plp_token: String,
     pub chain: Chain,
-    chain_arc: Arc<Mutex<Chain>>,
     pub state: VaultState,
     contract_address: Arc<Mutex<ContractAddress>>,
-    vault_info_updated_notify: Arc<Notify>,
 }
 
 impl Vault {
-    pub fn new(vault_addr: &String, plp_manager: &String, plp_token: &String, chain: &Chain, contract_address: Rc<RefCell<ContractAddress>>) -> Self {
-        // convert contract address to arc mutex
-        let contract_address = Arc::new(Mutex::new(contract_address.borrow().clone()));
-        Self { vault_addr: vault_addr.to_string(), plp_token: plp_token.to_string(), plp_manager: plp_manager.to_string(), chain: chain.clone(), state: VaultState::default(), contract_address, chain_arc: Arc::new(Mutex::new(chain.clone())), vault_info_updated_notify: Arc::new(Notify::new()) }
+    pub fn new(vault_addr: &String, plp_manager: &String, plp_token: &String, chain: &Chain, contract_address: &ContractAddress) -> Self {
+        let contract_address = Arc::new(Mutex::new(contract_address.clone()));
+        Self { vault_addr: vault_addr.to_string(), plp_token: plp_token.to_string(), plp_manager: plp_manager.to_string(), chain: chain.clone(), state: VaultState { plp_address: plp_token.parse().unwrap_or_default(), ..VaultState::default() }, contract_address }
     }
 
     pub async fn init_vault_state(&mut self) -> anyhow::Result<()> {
         // Note: Need to call init address first to initialize the addresses
-        let _ = &self.init_address_state().await;
+        self.init_address_state().await?;
         // TODO move to join all?
         println!("addr {:?}", self.state.usdp_address);
-        &self.init_vault_state_data().await;
+        self.init_vault_state_data().await?;
         println!("done init_vault_state_data");
-        &self.init_plp_manager_state().await;
+        let block_number = self.init_plp_manager_state().await?;
+        // only a complete fetch is fresh
+        self.state.record_fetch(&[DataClass::VaultState], FetchedAt::now(Some(block_number)));
         Ok(())
     }
 
@@ -84,10 +100,11 @@ impl Vault {
         let calls = vec![
             get_encode_address_and_params(&self.vault_addr, &"usdp()".to_string(), &vec![]),
         ];
-        let results = self.chain.execute_multicall_raw(calls).await.unwrap();
-        let formated_results: Vec<_> = results.into_iter().map(
-            |x| ethabi::decode(&[ethabi::ParamType::Address], &x).unwrap()
-        ).collect();
+        let results = self.chain.execute_multicall_raw(calls).await.map_err(|e| anyhow::anyhow!(e))?;
+        let formated_results = results
+            .into_iter()
+            .map(|x| ethabi::decode(&[ethabi::ParamType::Address], &x))
+            .collect::<Result<Vec<_>, _>>()?;
         if let [usdp_addr] = &formated_results[..] {
             self.state.usdp_address = usdp_addr[0].clone().into_address().expect("Failed to parse usdp_addr");
             println!("init address state {:?}", self.state.usdp_address);
@@ -98,7 +115,8 @@ impl Vault {
     }
 
 
-    async fn init_plp_manager_state(&mut self) -> anyhow::Result<()> {
+    /// Aum and supplies, returns the block they were read at
+    async fn init_plp_manager_state(&mut self) -> anyhow::Result<u64> {
         let calls = vec![
             // get aum
             get_encode_address_and_params(&self.plp_manager, &"getAum(bool)".to_string(), &vec![AbiToken::Bool(true)]),
@@ -106,12 +124,13 @@ impl Vault {
             get_encode_address_and_params(&self.plp_token, &"totalSupply()".to_string(), &vec![]),
             (self.state.usdp_address, encode_selector_and_params(&"totalSupply()".to_string(), &vec![])),
         ];
-        let results = self.chain.execute_multicall_raw(calls).await.unwrap();
+        let (block_number, results) = self.chain.execute_multicall_at_block(calls).await.map_err(|e| anyhow::anyhow!(e))?;
         println!("usdp_address {:?}", self.state.usdp_address);
 
-        let formated_results: Vec<_> = results.into_iter().map(
-            |x| ethabi::decode(&[ethabi::ParamType::Uint(256)], &x).unwrap()
-        ).collect();
+        let formated_results = results
+            .into_iter()
+            .map(|x| ethabi::decode(&[ethabi::ParamType::Uint(256)], &x))
+            .collect::<Result<Vec<_>, _>>()?;
         if let [aum1, aum2, plp_supply, usdp_supply] = &formated_results[..] {
             self.state.total_aum[0] = aum1[0].clone().into_uint().expect("Failed to parse aum1");
             self.state.total_aum[1] = aum2[0].clone().into_uint().expect("Failed to parse aum2");
@@ -121,7 +140,7 @@ impl Vault {
             anyhow::bail!("Failed to fetch plp manager state. Maybe invalid contract ABI");
         }
 
-        Ok(())
+        Ok(block_number)
     }
 
     async fn init_vault_state_data(&mut self) -> anyhow::Result<()> {
@@ -139,8 +158,17 @@ impl Vault {
             get_vault_variable_selector(&self.vault_addr.clone(), &"borrowingRateFactor".to_string()),
             get_vault_variable_selector(&self.vault_addr.clone(), &"stableBorrowingRateFactor".to_string()),
             get_vault_variable_selector(&self.vault_addr.clone(), &"totalTokenWeight".to_string()),
+            get_vault_variable_selector(&self.vault_addr.clone(), &"maxGasPrice".to_string()),
         ];
-        let results = self.chain.execute_multicall(calls, include_str!("../../abi/vault.json").to_string(), "mintBurnFeeBasisPoints").await.expect("Failed to fetch vault state");
+        // every variable is read as an uint, booleans included
+        let results = self
+            .chain
+            .execute_multicall_raw(calls)
+            .await
+            .map_err(|e| anyhow::anyhow!(e))?
+            .into_iter()
+            .map(|x| ethabi::decode(&[ethabi::ParamType::Uint(256)], &x))
+            .collect::<Result<Vec<_>, _>>()?;
         if let [
         mint_burn_fee_basis_points,
         swap_fee_basis_points,
@@ -154,7 +182,8 @@ impl Vault {
         borrowing_rate_interval,
         borrowing_rate_factor,
         stable_borrowing_rate_factor,
-        total_token_weights
+        total_token_weights,
+        max_gas_price
         ] = results.as_slice() {
             self.state.mint_burn_fee_basis_points = mint_burn_fee_basis_points[0].clone().into_uint().expect("Failed to parse mint_burn_fee_basis_points");
             self.state.swap_fee_basis_points = swap_fee_basis_points[0].clone().into_uint().expect("Failed to parse swap_fee_basis_points");
@@ -169,6 +198,7 @@ impl Vault {
             self.state.borrowing_rate_factor = borrowing_rate_factor[0].clone().into_uint().expect("Failed to parse borrowing_rate_factor");
             self.state.stable_borrowing_rate_factor = stable_borrowing_rate_factor[0].clone().into_uint().expect("Failed to parse stable_borrowing_rate_factor");
             self.state.total_token_weights = total_token_weights[0].clone().into_uint().expect("Failed to parse total_token_weights");
+            self.state.max_gas_price = max_gas_price[0].clone().into_uint().expect("Failed to parse max_gas_price");
 
             // println!("total_token_weights[0].clone().into_uint(): {}", total_token_weights[0].clone().into_uint().unwrap());
         } else {
@@ -181,191 +211,42 @@ impl Vault {
         Ok(())
     }
 
-    pub async fn fetch_token_configuration(&self, tokens: TokensArc) -> anyhow::Result<()> {
-        log::print("srtart fetch_token_configuration");
-        let mut calls: Vec<(Address, Bytes)> = vec![];
-        // let mut tokens = tokens.lock().await;
-        {
-            let _tokens = tokens.read().await;
-            calls = _tokens.iter().map(|token| {
-                let (vault_addr, data) = token.build_get_vault_token_configuration_call(&self.vault_addr);
-                (vault_addr, data)
-            }).collect();
-            // unlock locked
-        }
-        
-        log::print(format!("calls: {:?}", calls).as_str());
-        let results = self.chain_arc.lock().await.execute_multicall(calls, include_str!("../../abi/vault.json").to_string(), "tokenConfigurations").await.expect("[Vault] Failed to fetch token configurations");
-        log::print(format!("results before lock: {:?}", results).as_str());
-        let mut tokens = tokens.write().await;
-        println!("after lock");
-        for (token, result) in tokens.iter_mut().zip(results) {
-            if let [is_whitelisted, _token_decimals, is_stable_token, is_shortable_token, min_profit_basis_points, token_weight, max_usdp_amount] = result.as_slice() {
-                token.update_token_configuration(
-                    token_weight.clone().into_uint().expect("Failed to parse token weight").as_u64(),
-                    is_whitelisted.clone().into_bool().expect("Failed to parse is_whitelisted"),
-                    is_stable_token.clone().into_bool().expect("Failed to parse is_stable_token"),
-                    is_shortable_token.clone().into_bool().expect("Failed to parse is_shortable_token"),
-                    min_profit_basis_points.clone().into_uint().expect("Failed to parse min_profit_basis_points").as_u64(),
-                    max_usdp_amount.clone().into_uint().expect("Failed to parse max_usdp_amount"),
-                );
-            } else {
-                anyhow::bail!("Invalid token configuration return data (may be invalid ABI), check vault.tokenConfigurations(address token) sm function");
-            }
-        }
+    // reads `plan` on the tokens in one multicall, see `FetchPlan`
+    async fn fetch_components(&self, tokens: TokensArc, plan: FetchPlan) -> anyhow::Result<()> {
+        let mut contract_address = self.contract_address.lock().await.clone();
+        contract_address.vault = self.vault_addr.clone();
+        let config = Config {
+            selected_account: None,
+            chain: self.chain.clone(),
+            tokens: tokens.read().await.to_vec(),
+            contract_address,
+            contract_spender: vec![],
+            watched_accounts: vec![],
+        };
+        let output = plan.execute(&config).await?;
+        *tokens.write().await = output.tokens;
         Ok(())
     }
 
-    pub async fn fetch_vault_info(&self, tokens: TokensArc) -> anyhow::Result<()> {
-        // Lock vault info
-        let mut calls: Vec<(Address, Bytes)> = vec![];
-
-        {
-            let tokens = tokens.read().await;
-            calls = tokens.iter().map(|token| {
-                let (vault_addr, data) = token.build_get_vault_info(&self.vault_addr);
-                (vault_addr, data)
-            }).collect();
-        }
+    pub async fn fetch_token_configuration(&self, tokens: TokensArc) -> anyhow::Result<()> {
+        self.fetch_components(tokens, FetchPlan::new(&[FetchComponent::Config])).await
+    }
 
-        let results = self.chain.execute_multicall(calls, include_str!("../../abi/vault.json").to_string(), "vaultInfo").await.expect("[Vault] Failed to fetch vault info");
-
-        let mut tokens = tokens.write().await;
-        for (token, result) in tokens.iter_mut().zip(results) {
-            if let [
-                feeReserves,
-                usdpAmounts,
-                poolAmounts,
-                reservedAmounts
-            ] = result.as_slice() {
-                token.update_vault_info(
-                    usdpAmounts.clone().into_uint().expect("Fail to parse usdp amount"),
-                    feeReserves.clone().into_uint().expect("Fail to parse feeReserves"),
-                    poolAmounts.clone().into_uint().expect("Fail to parse poolAmounts"),
-                    reservedAmounts.clone().into_uint().expect("Fail to parse reservedAmounts"),
-                );
-            } else {
-                anyhow::bail!("Invalid token configuration return data (may be invalid ABI), check vault.tokenConfigurations(address token) sm function");
-            }
-        }
-        self.vault_info_updated_notify.notify_one();
-        Ok(())
+    /// Vault info and the available long/short sizes, with the configuration and prices they derive from
+    pub async fn fetch_vault_info(&self, tokens: TokensArc) -> anyhow::Result<()> {
+        self.fetch_components(tokens, FetchPlan::vault()).await
     }
 
+    /// Same as `fetch_vault_info`, the token variables are read in the same multicall
     pub async fn fetch_multi_vault_token_variables(&self, tokens: TokensArc) -> anyhow::Result<()> {
-        let vault_calls_fns = ["guaranteedUsd", "globalShortSizes"];
-        let user_gateway_calls_fns = ["maxGlobalLongSizes", "maxGlobalShortSizes"];
-        let user_gatway_addr = self.contract_address.lock().await.futurx_gateway.clone();
-        println!("user_gatway_addr: {:?}", user_gatway_addr);
-        let mut calls: Vec<Vec<(Address, Bytes)>> = vec![];
-        {
-            let tokens = tokens.read().await;
-            calls = tokens.iter().map(|token| {
-                let mut calls: Vec<(Address, Bytes)> = Vec::new();
-                let vault_calls: Vec<(Address, Bytes)> = vault_calls_fns.iter().map(|call_fn| token.build_get_token_variable(&self.vault_addr, call_fn)).collect();
-                calls.extend(vault_calls);
-                let user_gateway_calls: Vec<(Address, Bytes)> = user_gateway_calls_fns.iter().map(|call_fn| token.build_get_token_variable(&user_gatway_addr, call_fn)).collect();
-                calls.extend(user_gateway_calls);
-                calls
-            }).collect();
-        }
-        let flatten_calls: Vec<(Address, Bytes)> = calls.into_iter().flatten().collect();
-        let results = self.chain.execute_multicall_raw(flatten_calls).await.expect("[Vault] Failed to fetch multi vault token variables");
-        let decode_results: Vec<_> = results.into_iter().map(
-            |raw| ethabi::decode(&[ethabi::ParamType::Uint(256)], &raw).unwrap()
-        ).collect();
-        // chunk decode results into call_fns.len
-        let chunked_decode_results = decode_results.chunks(vault_calls_fns.len() + user_gateway_calls_fns.len());
-        // println!("chunks {:?}", chunked_decode_results);
-        // Wait for vault info updated
-        self.vault_info_updated_notify.notified().await;
-        let mut tokens = tokens.write().await;
-        for (token, chunked_decode_result) in tokens.iter_mut().zip(chunked_decode_results) {
-            if let [guaranteed_usd, global_short_sizes, max_global_long_sizes, max_global_short_sizes] = chunked_decode_result {
-                let guaranteed_usd = guaranteed_usd[0].clone().into_uint().expect("Fail to parse guaranteed_usd");
-                let global_short_sizes = global_short_sizes[0].clone().into_uint().expect("Fail to parse global_short_sizes");
-                let max_global_long_sizes = max_global_long_sizes[0].clone().into_uint().expect("Fail to parse global_short_sizes");
-                let max_global_short_sizes = max_global_short_sizes[0].clone().into_uint().expect("Fail to parse global_short_sizes");
-
-                token.update_available_long_short_amounts(max_global_long_sizes, max_global_short_sizes, guaranteed_usd, global_short_sizes)
-            } else {
-                anyhow::bail!("Invalid token configuration return data (may be invalid ABI), check vault.tokenConfigurations(address token) sm function");
-            }
-        }
-        Ok(())
+        self.fetch_vault_info(tokens).await
     }
 
     pub async fn fetch_token_prices(&self, tokens: TokensArc) -> anyhow::Result<()> {
-        // let mut tokens = tokens.lock().await;
-        // fetch ask price
-        let mut fetch_ask_price_calls: Vec<(Address, Bytes)> = vec![];
-        {
-            let tokens = tokens.read().await;
-            fetch_ask_price_calls = tokens.iter()
-                .filter(|token| token.is_tradeable == Some(true))
-                .map(|token| {
-                    let (vault_addr, data) = token.build_get_ask_price_call(&self.vault_addr);
-                    (vault_addr, data)
-                }).collect();
-        }
-
-        let results_ask_price = self.chain.execute_multicall(fetch_ask_price_calls, include_str!("../../abi/vault.json").to_string(), "getAskPrice").await.expect("[Vault] Failed to fetch ask prices");
-
-
-        let mut tokens = tokens.write().await;
-        // fetch bid price
-        let fetch_bid_price_calls: Vec<(Address, Bytes)> = tokens.iter()
-            .filter(|token| token.is_tradeable == Some(true))
-            .map(|token| {
-                let (vault_addr, data) = token.build_get_bid_price_call(&self.vault_addr);
-                (vault_addr, data)
-            }).collect();
-        let results_bid_price = self.chain.execute_multicall(fetch_bid_price_calls, include_str!("../../abi/vault.json").to_string(), "getBidPrice").await.expect("[Vault] Failed to fetch ask prices");
-
-        //
-        // let call_len = fetch_ask_price_calls.len();
-        // let mut merged_calls = fetch_bid_price_calls.clone();
-        // merged_calls.extend(fetch_ask_price_calls);
-        // let results = self.chain.execute_multicall(merged_calls, include_str!("../../abi/vault.json").to_string(), "getAskPrice").await.expect("[Vault] Failed to fetch ask prices");
-        // let chunk_reulsts = results.chunks(call_len);
-        // let ask_prices = chunk_reulsts.clone().next().expect("Failed to get ask prices")
-        //     .into_iter().map(_format_price).collect::<Vec<_>>();
-        // let bid_prices = chunk_reulsts.clone().next().expect("Failed to get bid prices")
-        //     .into_iter().map(_format_price).collect::<Vec<_>>();
-        // for (token, ask_price) in tokens.iter_mut().zip(ask_prices) {
-        //     token.ask_price = Some(ask_price);
-        //     token.min_price = Some(ask_price);
-        // }
-        // for (token, bid_price) in tokens.iter_mut().zip(bid_prices) {
-        //     token.bid_price = Some(bid_price);
-        //     token.max_price = Some(bid_price);
-        // }
-
-        for (token, ask_price) in tokens.iter_mut().zip(results_ask_price) {
-
-            let ask_price_formatted = _format_price(&ask_price);
-            token.ask_price = Some(ask_price_formatted);
-            token.min_price = Some(ask_price_formatted);
-        }
-        for (token, bid_price) in tokens.iter_mut().zip(results_bid_price) {
-            let bid_price_formatted = _format_price(&bid_price);
-
-            println!("**********done fetch_token_prices********");
-            token.bid_price = Some(bid_price_formatted);
-            token.max_price = Some(bid_price_formatted);
-        }
-
-        println!("**********done fetch_token_prices********");
-
-
-        Ok(())
+        self.fetch_components(tokens, FetchPlan::new(&[FetchComponent::Prices])).await
     }
 }
 
-fn _format_price(x: &Vec<ethabi::Token>) -> Price {
-    return Price::new_from_eth_token(&x[0]);
-}
-
 
 #[cfg(test)]
 mod tests {
@@ -375,6 +256,7 @@ mod tests {
     use crate::contracts::vault_logic::VaultLogic;
 
     use super::*;
+    use crate::contracts::token::Token;
     use crate::contracts::types::to_tokens_arc;
 
     fn create_tokens() -> Vec<Token> {
@@ -391,19 +273,18 @@ mod tests {
             rpc_urls: vec!["https://data-seed-prebsc-1-s1.binance.org:8545/".to_string()],
             multicall_address: "0x6e5bb1a5ad6f68a8d7d6a5e47750ec15773d6042".to_string(),
         };
-        let address = Rc::new(RefCell::new(
-            ContractAddress {
+        let address = ContractAddress {
                 vault: "0x792bA5e9E0Cd15083Ec2f58E434d875892005b91".to_string(),
                 plp_manager: "".to_string(),
                 plp_token: "".to_string(),
                 reward_router:  "".to_string(),
                 futurx_gateway :  "0x7f8cd121aedd5249a03328ce792c6fc5a7f224ce".to_string(),
                 reward_tracker_fee_plp :  "".to_string(),
+                reward_tracker_fee_staked_plp: "".to_string(),
                 vester_plp : "".to_string(),
-            }
-        ));
+        };
         
-        return Vault::new(&"0xb79391ad9614f72a737db3e3df38e05e5fc185eb".to_string(), &"0xDF49C2d458892B681331F4EEC0d09A88b283f444".to_string(), &"0x792bA5e9E0Cd15083Ec2f58E434d875892005b91".to_string(), &chain, address);
+        return Vault::new(&"0xb79391ad9614f72a737db3e3df38e05e5fc185eb".to_string(), &"0xDF49C2d458892B681331F4EEC0d09A88b283f444".to_string(), &"0x792bA5e9E0Cd15083Ec2f58E434d875892005b91".to_string(), &chain, &address);
     }
 
 
@@ -11,8 +11,8 @@ const MINT_BURN_FEE_BASIS_POINTS: u32 = 0;
 const TAX_BASIS_POINTS: u32 = 0;
 
 lazy_static! {
-    static ref PRECISION: U256 = U256::from(1) * U256::from(10u32).pow(30.into());
-    static ref BASIS_POINTS_DIVISOR: U256 = U256::from(10000);
+    pub(crate) static ref PRECISION: U256 = U256::from(1) * U256::from(10u32).pow(30.into());
+    pub(crate) static ref BASIS_POINTS_DIVISOR: U256 = U256::from(10000);
 }
 
 pub trait VaultLogic {
@@ -262,7 +262,7 @@ impl VaultLogic for VaultState {
 
 }
 
-fn adjust_for_decimals(amount: &U256, div_decimals: u32, mul_decimals: u32) -> U256 {
+pub(crate) fn adjust_for_decimals(amount: &U256, div_decimals: u32, mul_decimals: u32) -> U256 {
     amount * expand_decimals(1, mul_decimals) / expand_decimals(1, div_decimals)
 }
 
@@ -358,7 +358,7 @@ fn get_fee_basis_points(
     }
 }
 
-fn expand_decimals(value: u32, decimals: u32) -> U256 {
+pub(crate) fn expand_decimals(value: u32, decimals: u32) -> U256 {
     U256::from(value) * U256::from(10u32).pow(decimals.into())
 }
 
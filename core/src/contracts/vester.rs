@@ -0,0 +1,247 @@
+use std::collections::HashMap;
+
+use ethabi::{Contract, ParamType};
+use ethers::types::{Address, Bytes, U256};
+use serde::{Deserialize, Serialize};
+
+use crate::config::Chain;
+use super::multicall::ChainMulticallTrait;
+use super::vault::VaultState;
+
+const VESTER_ABI: &str = include_str!("../../abi/vester.json");
+
+// functions read for an account, in call order, vestingDuration is appended
+const ACCOUNT_FUNCTIONS: [&str; 8] = [
+    "balances",
+    "getVestedAmount",
+    "claimable",
+    "claimedAmounts",
+    "cumulativeClaimAmounts",
+    "pairAmounts",
+    "getMaxVestableAmount",
+    "lastVestingTimes",
+];
+
+/// Vesting position of an account, amounts in esToken decimals
+#[derive(Default, Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct VestingInfo {
+    pub vester: Address,
+    // deposited and not vested yet
+    pub balance: U256,
+    // total deposited, balance + cumulative_claim_amount
+    pub vested_amount: U256,
+    pub claimable: U256,
+    pub claimed_amount: U256,
+    // vested so far, claimed or not
+    pub cumulative_claim_amount: U256,
+    // staked tokens locked by the deposit
+    pub pair_amount: U256,
+    pub max_vestable_amount: U256,
+    // unix seconds
+    pub last_vesting_time: u64,
+    pub vesting_duration: u64,
+}
+
+#[derive(Default, Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct VestingSchedule {
+    // vested since the last contract update, claimable on top of `claimable`
+    pub next_claimable: U256,
+    pub total_claimable: U256,
+    // still to vest after next_claimable
+    pub remaining: U256,
+    pub vesting_per_second: U256,
+    pub remaining_seconds: u64,
+    // unix seconds
+    pub end_time: u64,
+}
+
+impl VestingInfo {
+    /// Remaining schedule at `now` (unix seconds), mirrors Vester._getNextClaimableAmount
+    pub fn schedule(&self, now: u64) -> VestingSchedule {
+        if self.vesting_duration == 0 || self.balance.is_zero() || self.vested_amount.is_zero() {
+            return VestingSchedule {
+                total_claimable: self.claimable,
+                end_time: now,
+                ..VestingSchedule::default()
+            };
+        }
+        let duration = U256::from(self.vesting_duration);
+        let elapsed = U256::from(now.saturating_sub(self.last_vesting_time));
+        let next_claimable = (self.vested_amount * elapsed / duration).min(self.balance);
+        let remaining = self.balance - next_claimable;
+        let vesting_per_second = self.vested_amount / duration;
+        // round up, the last second vests the remainder
+        let remaining_seconds = ((remaining * duration + self.vested_amount - 1) / self.vested_amount).low_u64();
+        VestingSchedule {
+            next_claimable,
+            total_claimable: self.claimable + next_claimable,
+            remaining,
+            vesting_per_second,
+            remaining_seconds,
+            end_time: now + remaining_seconds,
+        }
+    }
+}
+
+fn encode_call(vester: &str, fn_name: &str, params: &[ethabi::Token]) -> (Address, Bytes) {
+    let address: Address = vester.parse().expect("Invalid vester address");
+    let contract = Contract::load(VESTER_ABI.as_bytes()).unwrap();
+    let data: Bytes = contract.function(fn_name).unwrap().encode_input(params).unwrap().into();
+    (address, data)
+}
+
+pub fn build_vesting_calls(vester: &str, account: Address) -> Vec<(Address, Bytes)> {
+    let mut calls: Vec<_> = ACCOUNT_FUNCTIONS
+        .iter()
+        .map(|fn_name| encode_call(vester, fn_name, &[ethabi::Token::Address(account)]))
+        .collect();
+    calls.push(encode_call(vester, "vestingDuration", &[]));
+    calls
+}
+
+/// Decode the return data of `build_vesting_calls`
+pub fn decode_vesting_results(vester: &str, results: &[Bytes]) -> anyhow::Result<VestingInfo> {
+    let values = results
+        .iter()
+        .map(|data| {
+            ethabi::decode(&[ParamType::Uint(256)], data)?[0]
+                .clone()
+                .into_uint()
+                .ok_or_else(|| anyhow::anyhow!("Failed to parse uint"))
+        })
+        .collect::<anyhow::Result<Vec<U256>>>()?;
+    if let [balance, vested_amount, claimable, claimed_amount, cumulative_claim_amount, pair_amount, max_vestable_amount, last_vesting_time, vesting_duration] = values[..] {
+        Ok(VestingInfo {
+            vester: vester.parse()?,
+            balance,
+            vested_amount,
+            claimable,
+            claimed_amount,
+            cumulative_claim_amount,
+            pair_amount,
+            max_vestable_amount,
+            last_vesting_time: last_vesting_time.low_u64(),
+            vesting_duration: vesting_duration.low_u64(),
+        })
+    } else {
+        anyhow::bail!("Invalid vester return data, check Vester smart contract");
+    }
+}
+
+pub async fn fetch_vesting(chain: &Chain, vester: &str, account: &str) -> anyhow::Result<VestingInfo> {
+    let account: Address = account.parse().map_err(|_| anyhow::anyhow!("Invalid account {}", account))?;
+    let results = chain
+        .execute_multicall_raw(build_vesting_calls(vester, account))
+        .await
+        .map_err(|e| anyhow::anyhow!(e))?;
+    decode_vesting_results(vester, &results)
+}
+
+/// Vester.deposit(amount), vests `amount` of esToken
+pub fn build_deposit_call(vester: &str, amount: U256) -> (Address, Bytes) {
+    encode_call(vester, "deposit", &[ethabi::Token::Uint(amount)])
+}
+
+/// Vester.withdraw(), returns the unvested esToken and the pair tokens, claims the vested ones
+pub fn build_withdraw_call(vester: &str) -> (Address, Bytes) {
+    encode_call(vester, "withdraw", &[])
+}
+
+/// Vester.claim()
+pub fn build_claim_call(vester: &str) -> (Address, Bytes) {
+    encode_call(vester, "claim", &[])
+}
+
+impl VaultState {
+    pub fn update_vesting(&mut self, account: Address, vesting: VestingInfo) {
+        self.vesting_info.get_or_insert_with(HashMap::new).insert(account, vesting);
+    }
+
+    pub fn get_vesting_info(&self, account: &Address) -> Option<VestingInfo> {
+        self.vesting_info.as_ref().and_then(|vesting_info| vesting_info.get(account)).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::utils::hex;
+
+    use super::*;
+
+    const VESTER: &str = "0x1ed5051bbfa6b80f30d50d14761634ebb770f024";
+    const ACCOUNT: &str = "0x1f9840a85d5af5bf1d1762f925bdaddc4201f984";
+    const YEAR: u64 = 365 * 24 * 60 * 60;
+
+    fn create_vesting() -> VestingInfo {
+        VestingInfo {
+            vester: VESTER.parse().unwrap(),
+            balance: U256::from(750),
+            vested_amount: U256::from(1000),
+            claimable: U256::from(50),
+            cumulative_claim_amount: U256::from(250),
+            last_vesting_time: 1_000_000,
+            vesting_duration: YEAR,
+            ..VestingInfo::default()
+        }
+    }
+
+    #[test]
+    fn build_calls_works() {
+        let calls = build_vesting_calls(VESTER, ACCOUNT.parse().unwrap());
+        assert_eq!(calls.len(), ACCOUNT_FUNCTIONS.len() + 1);
+        assert_eq!(calls[0].0, VESTER.parse::<Address>().unwrap());
+
+        let (address, data) = build_deposit_call(VESTER, U256::from(1));
+        assert_eq!(address, VESTER.parse::<Address>().unwrap());
+        assert_eq!(hex::encode(data), "b6b55f250000000000000000000000000000000000000000000000000000000000000001");
+        assert_eq!(hex::encode(build_withdraw_call(VESTER).1), "3ccfd60b");
+        assert_eq!(hex::encode(build_claim_call(VESTER).1), "4e71d92d");
+    }
+
+    #[test]
+    fn decode_vesting_results_works() {
+        let results: Vec<Bytes> = (1..=9u64)
+            .map(|value| ethabi::encode(&[ethabi::Token::Uint(U256::from(value))]).into())
+            .collect();
+        let vesting = decode_vesting_results(VESTER, &results).unwrap();
+        assert_eq!(vesting.balance, U256::from(1));
+        assert_eq!(vesting.max_vestable_amount, U256::from(7));
+        assert_eq!(vesting.last_vesting_time, 8);
+        assert_eq!(vesting.vesting_duration, 9);
+        assert!(decode_vesting_results(VESTER, &results[..8]).is_err());
+    }
+
+    #[test]
+    fn schedule_works() {
+        let vesting = create_vesting();
+        // a quarter of the year later, a quarter of the deposit vests
+        let schedule = vesting.schedule(1_000_000 + YEAR / 4);
+        assert_eq!(schedule.next_claimable, U256::from(250));
+        assert_eq!(schedule.total_claimable, U256::from(300));
+        assert_eq!(schedule.remaining, U256::from(500));
+        assert_eq!(schedule.vesting_per_second, U256::zero());
+        assert_eq!(schedule.end_time, 1_000_000 + YEAR / 4 + YEAR / 2);
+
+        // vesting never exceeds the balance
+        let schedule = vesting.schedule(1_000_000 + YEAR * 2);
+        assert_eq!(schedule.next_claimable, U256::from(750));
+        assert_eq!(schedule.remaining, U256::zero());
+        assert_eq!(schedule.remaining_seconds, 0);
+    }
+
+    #[test]
+    fn schedule_with_rate_works() {
+        let vesting = VestingInfo {
+            balance: U256::from(YEAR) * 3,
+            vested_amount: U256::from(YEAR) * 4,
+            last_vesting_time: 100,
+            vesting_duration: YEAR,
+            ..VestingInfo::default()
+        };
+        let schedule = vesting.schedule(100 + YEAR / 2);
+        assert_eq!(schedule.vesting_per_second, U256::from(4));
+        assert_eq!(schedule.next_claimable, U256::from(YEAR) * 2);
+        assert_eq!(schedule.remaining_seconds, YEAR / 4);
+        assert_eq!(schedule.end_time, 100 + YEAR / 2 + YEAR / 4);
+    }
+}
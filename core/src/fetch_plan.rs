@@ -0,0 +1,534 @@
+use std::collections::BTreeSet;
+
+use ethabi::{Contract, ParamType};
+use ethers::types::{Address, Bytes, U256};
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::contracts::freshness::FetchedAt;
+use crate::contracts::multicall::ChainMulticallTrait;
+use crate::contracts::positions::{build_position_calls, decode_position_results, PositionInfo};
+use crate::contracts::staking::{build_staking_calls, decode_staking_results, StakingResults};
+use crate::contracts::token::{Price, Token};
+use crate::scheduler::DataClass;
+use crate::utils::get_encode_address_and_params;
+use crate::{p, Router};
+
+const VAULT_ABI: &str = include_str!("../abi/vault.json");
+// token variables read after vaultInfo, on the vault then on the gateway
+const VAULT_VARIABLES: [&str; 2] = ["guaranteedUsd", "globalShortSizes"];
+const GATEWAY_VARIABLES: [&str; 2] = ["maxGlobalLongSizes", "maxGlobalShortSizes"];
+
+/// Data read by a `FetchPlan`, in the order the results are applied
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum FetchComponent {
+    // Vault.tokenConfigurations
+    Config,
+    // ask and bid prices of the tradeable tokens
+    Prices,
+    // pool amounts, reserves and the available long/short sizes
+    VaultInfo,
+    // the components below read the selected and watched accounts and are skipped without any,
+    // but for the pool figures of the reward trackers
+    UserBalances,
+    Allowances,
+    Positions,
+    Staking,
+}
+
+impl FetchComponent {
+    pub const ALL: [FetchComponent; 7] = [
+        FetchComponent::Config,
+        FetchComponent::Prices,
+        FetchComponent::VaultInfo,
+        FetchComponent::UserBalances,
+        FetchComponent::Allowances,
+        FetchComponent::Positions,
+        FetchComponent::Staking,
+    ];
+
+    /// Components whose results are read when applying this one, fetched along
+    pub fn requires(self) -> &'static [FetchComponent] {
+        match self {
+            // the available sizes derive from is_stable_token, the pool amounts and the bid price
+            FetchComponent::VaultInfo => &[FetchComponent::Config, FetchComponent::Prices],
+            _ => &[],
+        }
+    }
+
+    fn needs_account(self) -> bool {
+        matches!(self, FetchComponent::UserBalances | FetchComponent::Allowances | FetchComponent::Positions)
+    }
+
+    /// Freshness class of the component, see `contracts::freshness`
+    pub fn data_class(self) -> Option<DataClass> {
+        match self {
+            FetchComponent::Config => Some(DataClass::TokenConfiguration),
+            FetchComponent::Prices => Some(DataClass::Prices),
+            FetchComponent::VaultInfo => Some(DataClass::VaultInfo),
+            FetchComponent::UserBalances | FetchComponent::Allowances => Some(DataClass::Balances),
+            FetchComponent::Positions | FetchComponent::Staking => None,
+        }
+    }
+}
+
+// calls of each component, in apply order
+type Batches = Vec<(FetchComponent, Vec<(Address, Bytes)>)>;
+
+/// Components fetched together in one multicall, see `Router::execute_plan`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FetchPlan {
+    components: BTreeSet<FetchComponent>,
+}
+
+/// Results of a `FetchPlan` on copies of the config tokens, applied with `Router::apply_fetch`
+#[derive(Debug, Clone)]
+pub struct FetchOutput {
+    pub chain_id: u64,
+    // components read, without the account ones when no account is tracked
+    pub components: Vec<FetchComponent>,
+    pub fetched_at: FetchedAt,
+    pub tokens: Vec<Token>,
+    // selected then watched accounts, see `Config::tracked_accounts`
+    pub accounts: Vec<Address>,
+    pub positions: Vec<(Address, Vec<PositionInfo>)>,
+    // per account, None for the pool figures only
+    pub staking: Vec<(Option<Address>, StakingResults)>,
+}
+
+impl FetchPlan {
+    pub fn new(components: &[FetchComponent]) -> Self {
+        components.iter().fold(Self::default(), |plan, component| plan.with(*component))
+    }
+
+    /// Adds `component` and the components it requires
+    pub fn with(mut self, component: FetchComponent) -> Self {
+        for required in component.requires() {
+            self = self.with(*required);
+        }
+        self.components.insert(component);
+        self
+    }
+
+    /// Components in apply order, requirements included
+    pub fn components(&self) -> Vec<FetchComponent> {
+        self.components.iter().copied().collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.components.is_empty()
+    }
+
+    /// Token configurations, prices and vault info
+    pub fn vault() -> Self {
+        Self::new(&[FetchComponent::VaultInfo])
+    }
+
+    /// Balances and allowances of the tracked accounts
+    pub fn balances() -> Self {
+        Self::new(&[FetchComponent::UserBalances, FetchComponent::Allowances])
+    }
+
+    /// Balances, allowances and staking of the tracked accounts
+    pub fn accounts() -> Self {
+        Self::balances().with(FetchComponent::Staking)
+    }
+
+    pub fn data() -> Self {
+        Self::vault().with(FetchComponent::UserBalances).with(FetchComponent::Allowances)
+    }
+
+    /// Plan refreshing `class`, None for the vault state which isn't read per token
+    pub fn for_class(class: DataClass) -> Option<Self> {
+        match class {
+            DataClass::TokenConfiguration => Some(Self::new(&[FetchComponent::Config])),
+            DataClass::VaultInfo => Some(Self::vault()),
+            DataClass::Prices => Some(Self::new(&[FetchComponent::Prices])),
+            DataClass::Balances => Some(Self::balances()),
+            DataClass::VaultState => None,
+        }
+    }
+
+    /// Multicall calls of each component, account after account for the account ones,
+    /// which are skipped when no account is tracked
+    pub fn build_calls(&self, config: &Config) -> anyhow::Result<Batches> {
+        let accounts = config.tracked_accounts()?;
+        self.components
+            .iter()
+            .filter(|component| !accounts.is_empty() || !component.needs_account())
+            .map(|component| Ok((*component, build_component_calls(*component, config, &accounts)?)))
+            .collect()
+    }
+
+    /// Applies the return data of the `build_calls` calls, in the same order, to copies of the config tokens
+    pub fn decode(&self, config: &Config, block_number: Option<u64>, results: &[Bytes]) -> anyhow::Result<FetchOutput> {
+        decode_batches(&self.build_calls(config)?, config, block_number, results)
+    }
+
+    /// Reads every component in a single multicall
+    pub async fn execute(&self, config: &Config) -> anyhow::Result<FetchOutput> {
+        let batches = self.build_calls(config)?;
+        let calls: Vec<(Address, Bytes)> = batches.iter().flat_map(|(_, calls)| calls.iter().cloned()).collect();
+        if calls.is_empty() {
+            return decode_batches(&batches, config, None, &[]);
+        }
+        p!("fetch plan {:?}, {} calls", self.components(), calls.len());
+        let (block_number, results) = config.chain.execute_multicall_at_block(calls).await.map_err(|e| anyhow::anyhow!(e))?;
+        decode_batches(&batches, config, Some(block_number), &results)
+    }
+}
+
+// staking reads the pool figures once without accounts
+fn staking_accounts(accounts: &[Address]) -> Vec<Option<Address>> {
+    match accounts.is_empty() {
+        true => vec![None],
+        false => accounts.iter().copied().map(Some).collect(),
+    }
+}
+
+// reward trackers with their deposit token: PLP in fPLP, fPLP in fsPLP
+fn reward_trackers(config: &Config) -> anyhow::Result<Vec<(String, Address)>> {
+    let contract_address = &config.contract_address;
+    [
+        (&contract_address.reward_tracker_fee_plp, &contract_address.plp_token),
+        (&contract_address.reward_tracker_fee_staked_plp, &contract_address.reward_tracker_fee_plp),
+    ]
+    .into_iter()
+    .filter(|(reward_tracker, _)| !reward_tracker.is_empty())
+    .map(|(reward_tracker, deposit_token)| {
+        let deposit_token = deposit_token.parse().map_err(|_| anyhow::anyhow!("Invalid deposit token {} of {}", deposit_token, reward_tracker))?;
+        Ok((reward_tracker.to_lowercase(), deposit_token))
+    })
+    .collect()
+}
+
+fn tradeable_tokens(tokens: &[Token]) -> impl Iterator<Item = &Token> {
+    tokens.iter().filter(|token| token.is_tradeable == Some(true))
+}
+
+fn build_component_calls(component: FetchComponent, config: &Config, accounts: &[Address]) -> anyhow::Result<Vec<(Address, Bytes)>> {
+    let vault = &config.contract_address.vault;
+    let tokens = &config.tokens;
+    Ok(match component {
+        FetchComponent::Config => tokens.iter().map(|token| token.build_get_vault_token_configuration_call(vault)).collect(),
+        FetchComponent::Prices => tradeable_tokens(tokens)
+            .map(|token| token.build_get_ask_price_call(vault))
+            .chain(tradeable_tokens(tokens).map(|token| token.build_get_bid_price_call(vault)))
+            .collect(),
+        FetchComponent::VaultInfo => tokens
+            .iter()
+            .flat_map(|token| {
+                let mut calls = vec![token.build_get_vault_info(vault)];
+                calls.extend(VAULT_VARIABLES.iter().map(|call_fn| token.build_get_token_variable(vault, call_fn)));
+                calls.extend(GATEWAY_VARIABLES.iter().map(|call_fn| token.build_get_token_variable(&config.contract_address.futurx_gateway, call_fn)));
+                calls
+            })
+            .collect(),
+        FetchComponent::UserBalances => accounts
+            .iter()
+            .flat_map(|account| {
+                tokens.iter().map(move |token| match token.is_native_token {
+                    Some(true) => get_encode_address_and_params(&config.chain.multicall_address, "getEthBalance(address)", &[ethabi::Token::Address(*account)]),
+                    _ => token.build_balance_of_call(&format!("{:?}", account)),
+                })
+            })
+            .collect(),
+        FetchComponent::Allowances => accounts
+            .iter()
+            .flat_map(|account| {
+                tokens.iter().flat_map(move |token| {
+                    config.contract_spender.iter().map(move |spender| token.build_allowance_call(&format!("{:?}", account), &spender.address))
+                })
+            })
+            .collect(),
+        FetchComponent::Positions => accounts
+            .iter()
+            .flat_map(|account| build_position_calls(vault, tokens, *account))
+            .collect(),
+        FetchComponent::Staking => {
+            let reward_trackers = reward_trackers(config)?;
+            staking_accounts(accounts)
+                .into_iter()
+                .flat_map(|account| {
+                    reward_trackers
+                        .iter()
+                        .flat_map(move |(reward_tracker, deposit_token)| build_staking_calls(reward_tracker, *deposit_token, account))
+                })
+                .collect()
+        }
+    })
+}
+
+fn decode_uint(data: &Bytes) -> anyhow::Result<U256> {
+    ethabi::decode(&[ParamType::Uint(256)], data)?[0]
+        .clone()
+        .into_uint()
+        .ok_or_else(|| anyhow::anyhow!("Failed to parse uint"))
+}
+
+fn decode_batches(
+    batches: &Batches,
+    config: &Config,
+    block_number: Option<u64>,
+    results: &[Bytes],
+) -> anyhow::Result<FetchOutput> {
+    let expected: usize = batches.iter().map(|(_, calls)| calls.len()).sum();
+    if results.len() != expected {
+        anyhow::bail!("Invalid fetch plan return data, expected {} results, got {}", expected, results.len());
+    }
+    let accounts = config.tracked_accounts()?;
+    let mut output = FetchOutput {
+        chain_id: config.chain.chain_id,
+        components: batches.iter().map(|(component, _)| *component).collect(),
+        fetched_at: FetchedAt::now(block_number),
+        tokens: config.tokens.clone(),
+        accounts: accounts.clone(),
+        positions: vec![],
+        staking: vec![],
+    };
+    let vault = Contract::load(VAULT_ABI.as_bytes())?;
+    let mut offset = 0;
+    // batches are in apply order, a component reads the results of the ones it requires
+    for (component, calls) in batches {
+        let results = &results[offset..offset + calls.len()];
+        offset += calls.len();
+        // the results of the account components, one chunk per account
+        let per_account = results.chunks((results.len() / accounts.len().max(1)).max(1));
+        match component {
+            FetchComponent::Config => {
+                let function = vault.function("tokenConfigurations")?;
+                for (token, data) in output.tokens.iter_mut().zip(results) {
+                    if let [is_whitelisted, _token_decimals, is_stable_token, is_shortable_token, min_profit_basis_points, token_weight, max_usdp_amount] = function.decode_output(data)?.as_slice() {
+                        token.update_token_configuration(
+                            token_weight.clone().into_uint().unwrap_or_default().as_u64(),
+                            is_whitelisted.clone().into_bool().unwrap_or_default(),
+                            is_stable_token.clone().into_bool().unwrap_or_default(),
+                            is_shortable_token.clone().into_bool().unwrap_or_default(),
+                            min_profit_basis_points.clone().into_uint().unwrap_or_default().as_u64(),
+                            max_usdp_amount.clone().into_uint().unwrap_or_default(),
+                        );
+                    } else {
+                        anyhow::bail!("Invalid token configuration return data (may be invalid ABI), check vault.tokenConfigurations(address token) sm function");
+                    }
+                }
+            }
+            FetchComponent::Prices => {
+                let (asks, bids) = results.split_at(results.len() / 2);
+                let tradeable = output.tokens.iter_mut().filter(|token| token.is_tradeable == Some(true));
+                for ((token, ask), bid) in tradeable.zip(asks).zip(bids) {
+                    let (ask_price, bid_price) = (Price::new_from_eth_token(&ethabi::Token::Uint(decode_uint(ask)?)), Price::new_from_eth_token(&ethabi::Token::Uint(decode_uint(bid)?)));
+                    token.ask_price = Some(ask_price);
+                    token.min_price = Some(ask_price);
+                    token.bid_price = Some(bid_price);
+                    token.max_price = Some(bid_price);
+                }
+            }
+            FetchComponent::VaultInfo => {
+                let function = vault.function("vaultInfo")?;
+                for (token, results) in output.tokens.iter_mut().zip(results.chunks(1 + VAULT_VARIABLES.len() + GATEWAY_VARIABLES.len())) {
+                    if let [fee_reserves, usdp_amounts, pool_amounts, reserved_amounts] = function.decode_output(&results[0])?.as_slice() {
+                        token.update_vault_info(
+                            usdp_amounts.clone().into_uint().unwrap_or_default(),
+                            fee_reserves.clone().into_uint().unwrap_or_default(),
+                            pool_amounts.clone().into_uint().unwrap_or_default(),
+                            reserved_amounts.clone().into_uint().unwrap_or_default(),
+                        );
+                    } else {
+                        anyhow::bail!("Invalid vault info return data (may be invalid ABI), check vault.vaultInfo(address token) sm function");
+                    }
+                    let (guaranteed_usd, global_short_sizes) = (decode_uint(&results[1])?, decode_uint(&results[2])?);
+                    let (max_global_long_sizes, max_global_short_sizes) = (decode_uint(&results[3])?, decode_uint(&results[4])?);
+                    token.update_available_long_short_amounts(max_global_long_sizes, max_global_short_sizes, guaranteed_usd, global_short_sizes);
+                    token.calculate_available_liquidity();
+                }
+            }
+            FetchComponent::UserBalances => {
+                for (account, results) in accounts.iter().zip(per_account) {
+                    let account = format!("{:?}", account);
+                    for (token, data) in output.tokens.iter_mut().zip(results) {
+                        token.update_balance(&account, decode_uint(data)?);
+                    }
+                }
+            }
+            FetchComponent::Allowances => {
+                let spenders = &config.contract_spender;
+                for (account, results) in accounts.iter().zip(per_account) {
+                    let account = format!("{:?}", account);
+                    for (token, results) in output.tokens.iter_mut().zip(results.chunks(spenders.len().max(1))) {
+                        for (spender, data) in spenders.iter().zip(results) {
+                            token.update_allowance(&account, decode_uint(data)?, &spender.address);
+                        }
+                    }
+                }
+            }
+            FetchComponent::Positions => {
+                for (account, results) in accounts.iter().zip(per_account) {
+                    output.positions.push((*account, decode_position_results(&output.tokens, results)?));
+                }
+            }
+            FetchComponent::Staking => {
+                let reward_trackers = reward_trackers(config)?;
+                let staking_accounts = staking_accounts(&accounts);
+                let per_staking_account = results.chunks((results.len() / staking_accounts.len()).max(1));
+                for (account, results) in staking_accounts.into_iter().zip(per_staking_account) {
+                    let per_tracker = (results.len() / reward_trackers.len().max(1)).max(1);
+                    let staking = reward_trackers
+                        .iter()
+                        .zip(results.chunks(per_tracker))
+                        .map(|((reward_tracker, _), results)| decode_staking_results(reward_tracker, results))
+                        .collect::<anyhow::Result<_>>()?;
+                    output.staking.push((account, staking));
+                }
+            }
+        }
+    }
+    Ok(output)
+}
+
+impl Router {
+    /// Reads `plan` in one multicall and writes the results
+    pub async fn execute_plan(&mut self, plan: &FetchPlan) -> anyhow::Result<()> {
+        let output = plan.execute(&self.config).await?;
+        self.apply_fetch(output);
+        Ok(())
+    }
+
+    /// Writes a `FetchPlan` result and notifies the subscribers, dropped when the chain was switched meanwhile
+    pub fn apply_fetch(&mut self, output: FetchOutput) {
+        if output.chain_id != self.config.chain.chain_id {
+            return;
+        }
+        let before = self.change_baseline();
+        let mut classes: Vec<DataClass> = output.components.iter().filter_map(|component| component.data_class()).collect();
+        classes.dedup();
+        self.config.tokens = output.tokens;
+        for (account, positions) in output.positions {
+            self.vault.state.update_positions(account, positions);
+        }
+        for (account, staking) in output.staking {
+            self.vault.state.update_staking(account, staking);
+        }
+        self.mark_fetched(&classes, output.fetched_at);
+        self.emit_changes(before);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RouterTrait;
+
+    const ACCOUNT: &str = "0x1f9840a85d5af5bf1d1762f925bdaddc4201f984";
+
+    fn encode_uints(values: &[u64]) -> Bytes {
+        ethabi::encode(&values.iter().map(|value| ethabi::Token::Uint(U256::from(*value))).collect::<Vec<_>>()).into()
+    }
+
+    fn create_router() -> Router {
+        let mut router = Router::new();
+        router.initilize(97).unwrap();
+        router
+    }
+
+    #[test]
+    fn plan_resolves_requirements() {
+        assert_eq!(FetchPlan::vault().components(), vec![FetchComponent::Config, FetchComponent::Prices, FetchComponent::VaultInfo]);
+        assert_eq!(FetchPlan::new(&[FetchComponent::Staking, FetchComponent::Prices]).components(), vec![FetchComponent::Prices, FetchComponent::Staking]);
+        assert!(FetchPlan::for_class(DataClass::VaultState).is_none());
+        assert_eq!(FetchPlan::for_class(DataClass::Balances), Some(FetchPlan::balances()));
+    }
+
+    #[test]
+    fn build_calls_skip_account_components() {
+        let mut router = create_router();
+        let plan = FetchPlan::data().with(FetchComponent::Positions);
+        let components = |router: &Router| plan.build_calls(&router.config).unwrap().into_iter().map(|(component, _)| component).collect::<Vec<_>>();
+        assert_eq!(components(&router), FetchPlan::vault().components());
+
+        router.set_account(ACCOUNT.to_string());
+        let batches = plan.build_calls(&router.config).unwrap();
+        let (tokens, spenders) = (router.config.tokens.len(), router.config.contract_spender.len());
+        let calls: Vec<usize> = batches.iter().map(|(_, calls)| calls.len()).collect();
+        assert_eq!(calls[0], tokens);
+        assert_eq!(calls[2], tokens * 5);
+        assert_eq!(calls[3], tokens);
+        assert_eq!(calls[4], tokens * spenders);
+    }
+
+    #[test]
+    fn accounts_plan_reads_every_tracked_account() {
+        let mut router = create_router();
+        router.set_account(ACCOUNT.to_string());
+        router.watch_accounts(vec![Address::repeat_byte(1)]);
+        let batches = FetchPlan::accounts().build_calls(&router.config).unwrap();
+        let components: Vec<FetchComponent> = batches.iter().map(|(component, _)| *component).collect();
+        assert_eq!(components, vec![FetchComponent::UserBalances, FetchComponent::Allowances, FetchComponent::Staking]);
+
+        let (tokens, spenders, trackers) = (router.config.tokens.len(), router.config.contract_spender.len(), reward_trackers(&router.config).unwrap().len());
+        assert_eq!(batches[0].1.len(), tokens * 2);
+        assert_eq!(batches[1].1.len(), tokens * spenders * 2);
+        // pool figures and 5 account reads of each tracker
+        assert!(trackers > 0);
+        assert_eq!(batches[2].1.len(), trackers * 8 * 2);
+    }
+
+    #[test]
+    fn staking_reads_both_plp_trackers() {
+        let mut router = create_router();
+        router.set_account(ACCOUNT.to_string());
+        let fee_plp: Address = router.config.contract_address.reward_tracker_fee_plp.parse().unwrap();
+        router.config.contract_address.reward_tracker_fee_staked_plp = format!("{:?}", Address::repeat_byte(1));
+        let batches = FetchPlan::new(&[FetchComponent::Staking]).build_calls(&router.config).unwrap();
+        let calls = &batches[0].1;
+        assert_eq!(calls.len(), 16);
+        assert_eq!(calls[8].0, Address::repeat_byte(1));
+        // fsPLP holds fPLP, depositBalances(account, fPLP)
+        assert!(calls[12].1.ends_with(fee_plp.as_bytes()));
+        assert!(!calls[4].1.ends_with(fee_plp.as_bytes()));
+    }
+
+    #[test]
+    fn decode_applies_in_order() {
+        let mut router = create_router();
+        router.config.tokens.truncate(1);
+        router.config.tokens[0].is_tradeable = Some(true);
+        router.config.tokens[0].is_native_token = None;
+        router.config.contract_spender.truncate(1);
+        router.set_account(ACCOUNT.to_string());
+        let plan = FetchPlan::data();
+
+        let price = 10_u64.pow(18);
+        let results = vec![
+            // whitelisted, decimals, stable, shortable, min profit bps, weight, max usdp
+            encode_uints(&[1, 18, 1, 0, 0, 50, 5000]),
+            // ask, bid
+            encode_uints(&[price]),
+            encode_uints(&[price]),
+            // fee reserves, usdp amounts, pool amounts, reserved amounts
+            encode_uints(&[0, 1000, 500, 100]),
+            encode_uints(&[0]),
+            encode_uints(&[0]),
+            encode_uints(&[0]),
+            encode_uints(&[0]),
+            // balance, allowance
+            encode_uints(&[2 * price]),
+            encode_uints(&[7]),
+        ];
+        assert!(plan.decode(&router.config, Some(42), &results[1..]).is_err());
+        let output = plan.decode(&router.config, Some(42), &results).unwrap();
+
+        let token = &output.tokens[0];
+        assert_eq!(token.is_stable_token, Some(true));
+        assert_eq!(token.bid_price, Some(Price::new_from_eth_token(&ethabi::Token::Uint(U256::from(price)))));
+        assert_eq!(token.pool_amounts, Some(U256::from(500)));
+        assert!(token.available_long_size.is_some());
+        let account: Address = ACCOUNT.parse().unwrap();
+        assert_eq!(token.get_balance_raw(&ACCOUNT.to_string()), U256::from(2 * price));
+        assert_eq!(token.allowances.as_ref().unwrap()[&account].values().next(), Some(&U256::from(7)));
+
+        router.apply_fetch(output);
+        assert_eq!(router.block_number, Some(42));
+        assert_eq!(router.vault.state.fetched_at.len(), 4);
+        assert_eq!(router.config.tokens[0].pool_amounts, Some(U256::from(500)));
+    }
+}
@@ -1,8 +1,16 @@
 pub mod config;
 pub mod contracts;
+pub mod tx;
+pub mod scheduler;
+pub mod subscription;
+pub mod persistence;
+pub mod fetch_plan;
+pub mod shared;
+pub mod accounts;
+pub mod portfolio;
 mod log;
 mod utils;
-use std::{cell::RefCell, rc::Rc, sync::Arc};
+use std::sync::Arc;
 
 use async_trait::async_trait;
 use contracts::vault::Vault;
@@ -13,9 +21,8 @@ use instant::Instant;
 // use tokio::{task::futures};
 
 use crate::contracts::token::Token;
-use crate::contracts::types::{VaultArc, TokensArc};
 use crate::contracts::vault_logic::VaultLogic;
-use contracts::global_fetch::*;
+use crate::fetch_plan::{FetchComponent, FetchPlan};
 use ethabi::ethereum_types::U256;
 use log::*;
 
@@ -27,9 +34,15 @@ pub struct Router {
     pub vault: Vault,
     pub price_plp_buy: U256,
     pub price_plp_sell: U256,
+    // change callbacks and streams, notified after each fetch
+    pub subscriptions: subscription::Subscriptions,
+    // head block and unix millis of the last fetch, per data class in `vault.state.fetched_at`
+    pub block_number: Option<u64>,
+    pub fetched_at: Option<u64>,
 }
 
-#[async_trait(?Send)]
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
 pub trait RouterTrait {
     fn new() -> Self;
     fn initilize(&mut self, chain_id: u64) -> Result<&config::Config, &'static str>;
@@ -40,9 +53,18 @@ pub trait RouterTrait {
     async fn fetch_balance(&mut self) -> anyhow::Result<()>;
     async fn fetch_vault(&mut self) -> anyhow::Result<()>;
     async fn fetch_data(&mut self) -> anyhow::Result<()>;
+    async fn fetch_staking(&mut self) -> anyhow::Result<()>;
+    async fn fetch_vesting(&mut self) -> anyhow::Result<()>;
+    /// PLP mints/redeems, swaps and position changes of `account` since `from_block`, oldest first
+    async fn account_history(&self, account: &str, from_block: u64) -> anyhow::Result<Vec<contracts::account_history::HistoryEntry>>;
+    /// `account_history` entries as CSV or JSON, with the symbols and decimals of the config tokens
+    fn export_history(&self, entries: &[contracts::account_history::HistoryEntry], format: contracts::history_export::ExportFormat) -> anyhow::Result<String>;
+    /// Fetches one data class only, see `scheduler::RefreshScheduler` to refresh them on intervals
+    async fn refresh(&mut self, class: scheduler::DataClass) -> anyhow::Result<()>;
 }
 
-#[async_trait(?Send)]
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
 impl RouterTrait for Router {
     fn new() -> Self {
         Self {
@@ -56,10 +78,14 @@ impl RouterTrait for Router {
                 tokens: vec![],
                 contract_address: config::ContractAddress::default(),
                 contract_spender: vec![],
+                watched_accounts: vec![],
             },
             vault: Vault::default(),
             price_plp_buy: U256::zero(),
             price_plp_sell: U256::zero(),
+            subscriptions: subscription::Subscriptions::default(),
+            block_number: None,
+            fetched_at: None,
         }
     }
 
@@ -71,7 +97,7 @@ impl RouterTrait for Router {
             &contract_address.plp_manager.to_lowercase(),
             &contract_address.plp_token.to_lowercase(),
             &self.config.chain,
-            Rc::new(RefCell::new(contract_address)),
+            &contract_address,
         );
         for token in self.config.tokens.iter_mut() {
             token.address = token.address.to_lowercase();
@@ -93,139 +119,15 @@ impl RouterTrait for Router {
     }
 
     async fn fetch_balance(&mut self) -> anyhow::Result<()>{
-
-        let tokens = self.load_tokens();
-        let tokens = Arc::new(tokio::sync::RwLock::new(tokens));
-
-        async fn fetch_user_info(
-            tokens: TokensArc,
-            config: Arc<tokio::sync::RwLock<config::Config>>,
-        ) -> anyhow::Result<()> {
-            tokio::join![
-                async {
-                    let _config = config.read().await;
-                    p!("task 5 start fetch balances");
-                    _config.fetch_balances(&tokens).await;
-                },
-                async {
-                    let _config = config.read().await;
-                    p!("task 5 start fetch allowance");
-                    _config.fetch_allowance(&tokens).await;
-                },
-            ];
-            Ok(())
-        }
-        let config = Arc::new(tokio::sync::RwLock::new(self.config.clone()));
-
-        let _tasks = tokio::try_join![
-            fetch_user_info(Arc::clone(&tokens), config),
-        ];
-
-        print("all done");
-
-        let tokens = tokens.read().await;
-
-        print(format!("tokens full: {:?}", tokens).as_str());
-        // re assign new tokens
-        self.config.tokens = tokens.to_vec();
-        print("all done");
-        Ok(())
-
+        self.execute_plan(&FetchPlan::balances()).await
     }
 
     async fn fetch_vault(&mut self) -> anyhow::Result<()> {
-        let tokens = self.load_tokens();
-        // p!("tokens: ", tokens);
-        let tokens = Arc::new(tokio::sync::RwLock::new(tokens));
-        let vault = Arc::new(tokio::sync::RwLock::new(self.vault.clone()));
-        // let tokens1 = Arc::clone(&tokens);
-        // let tokens2 = Arc::clone(&tokens);
-        let startTime = Instant::now();
-
-        print(
-            format!(
-                "RUST:: start fetch data, chain id: {:?}",
-                self.config.chain.chain_id
-            )
-                .as_str(),
-        );
-
-        async fn fetch_token_configuration(
-            tokens: TokensArc,
-            vault: VaultArc,
-        ) -> anyhow::Result<()> {
-            vault.read().await.fetch_token_configuration(tokens)
-                .await;
-
-            Ok(())
-        }
-        async fn fetch_vault_info(
-            tokens: TokensArc,
-            vault: VaultArc,
-        ) -> anyhow::Result<()> {
-            vault.read().await.fetch_vault_info(tokens).await;
-            print("task 2 done");
-            Ok(())
-        }
-        async fn fetch_token_prices(
-            tokens: TokensArc,
-            vault: VaultArc
-        ) -> anyhow::Result<()> {
-            vault.read().await.fetch_token_prices(tokens).await;
-            print("task 3 done");
-            Ok(())
-        }
-
-        async fn fetch_multi_vault_token_variables(
-            tokens: TokensArc,
-            vault: VaultArc,
-        ) -> anyhow::Result<()> {
-            let vault = vault.read().await;
-            vault
-                .fetch_multi_vault_token_variables(tokens)
-                .await;
-            print("task 4 done");
-                Ok(())
-        }
-        let config = Arc::new(tokio::sync::RwLock::new(self.config.clone()));
-
-        let _tasks = tokio::try_join![
-            fetch_token_configuration(Arc::clone(&tokens), Arc::clone(&vault)),
-            fetch_vault_info(Arc::clone(&tokens), Arc::clone(&vault)),
-            fetch_token_prices(Arc::clone(&tokens), Arc::clone(&vault)),
-            fetch_multi_vault_token_variables(Arc::clone(&tokens), Arc::clone(&vault)),
-        ];
-
-        print("all done");
-        p!("all done, time: {}", startTime.elapsed().as_millis());
-
-        let tokens = tokens.read().await;
-        // self.vault
-        //     .fetch_multi_vault_token_variables(&mut tokens)
-        //     .await;
-        print(format!("tokens full: {:?}", tokens).as_str());
-        // re assign new tokens
-        self.config.tokens = tokens.to_vec();
-
-        for token in self.config.tokens.iter_mut() {
-            token.calculate_available_liquidity();
-        }
-
-        print("all done");
-
-        Ok(())
+        self.execute_plan(&FetchPlan::vault()).await
     }
 
-
     async fn fetch_data(&mut self) -> anyhow::Result<()> {
-        let tokens = self.load_tokens();
-        // p!("tokens: ", tokens);
-        let tokens = Arc::new(tokio::sync::RwLock::new(tokens));
-        let vault = Arc::new(tokio::sync::RwLock::new(self.vault.clone()));
-        // let tokens1 = Arc::clone(&tokens);
-        // let tokens2 = Arc::clone(&tokens);
         let startTime = Instant::now();
-
         print(
             format!(
                 "RUST:: start fetch data, chain id: {:?}",
@@ -233,114 +135,37 @@ impl RouterTrait for Router {
             )
             .as_str(),
         );
-
-        async fn fetch_token_configuration(
-            tokens: TokensArc,
-            vault: VaultArc,
-            startTime: Instant,
-        ) -> anyhow::Result<()> {
-            p!("task 1 start after lock, elapsed: {:?}", startTime.elapsed());
-            vault.read().await.fetch_token_configuration(tokens)
-                .await;
-            // if res.is_err() {
-            //     print("task 1 error");
-            //     return;
-            // }
-            p!("task 1 done, time {}", startTime.elapsed().as_millis());
-            Ok(())
-        }
-        async fn fetch_vault_info(
-            tokens: TokensArc,
-            vault: VaultArc,
-            startTime: Instant,
-        ) -> anyhow::Result<()> {
-            p!("task 2 start after lock, elapsed: {:?}", startTime.elapsed());
-            vault.read().await.fetch_vault_info(tokens).await;
-            print("task 2 done");
-            p!("task 2 done, time: {}", startTime.elapsed().as_millis());
-            Ok(())
-        }
-        async fn fetch_token_prices(
-            tokens: TokensArc,
-            vault: VaultArc,
-            startTime: Instant,
-        ) -> anyhow::Result<()> {
-            p!("task 3 start after lock, elapsed: {:?}", startTime.elapsed());
-            vault.read().await.fetch_token_prices(tokens).await.expect("fetch token prices");
-            print("task 3 done");
-            p!("task 3 done, time: {}", startTime.elapsed().as_millis());
-            Ok(())
-        }
-
-        async fn fetch_multi_vault_token_variables(
-            tokens: TokensArc,
-            vault: VaultArc,
-            startTime: Instant,
-        ) -> anyhow::Result<()> {
-            let vault = vault.read().await;
-            p!("task 4 start after lock, elapsed: {:?}", startTime.elapsed());
-            vault
-                .fetch_multi_vault_token_variables(tokens)
-                .await;
-            print("task 4 done");
-            p!("task 4 done, time: {}", startTime.elapsed().as_millis());
-            Ok(())
-        }
-
-        async fn fetch_user_info(
-            tokens: TokensArc,
-            config: Arc<tokio::sync::RwLock<config::Config>>,
-            startTime: Instant,
-        ) -> anyhow::Result<()> {
-            p!("task 5 start after lock, elapsed: {:?}", startTime.elapsed());
-            tokio::join![
-                async {
-                    
-                    let _config = config.read().await;
-                    p!("task 5 start fetch balances");
-                    _config.fetch_balances(&tokens).await;
-                },
-                async {
-                    let _config = config.read().await;
-                    p!("task 5 start fetch allowance");
-                    _config.fetch_allowance(&tokens).await;
-                },
-            ];
-            // config.fetch_balances(&tokens).await;
-            // config.fetch_allowance(&tokens).await;
-            p!("task 5 done, time: {}", startTime.elapsed().as_millis());
-            Ok(())
-        }
-        let config = Arc::new(tokio::sync::RwLock::new(self.config.clone()));
-
-        let _tasks = tokio::try_join![
-            fetch_token_configuration(Arc::clone(&tokens), Arc::clone(&vault), startTime),
-            fetch_vault_info(Arc::clone(&tokens), Arc::clone(&vault), startTime),
-            fetch_token_prices(Arc::clone(&tokens), Arc::clone(&vault), startTime),
-            fetch_user_info(Arc::clone(&tokens), config, startTime),
-            fetch_multi_vault_token_variables(Arc::clone(&tokens), Arc::clone(&vault), startTime),
-        ];
-
-        print("all done");
+        self.execute_plan(&FetchPlan::accounts().with(FetchComponent::VaultInfo)).await?;
         p!("all done, time: {}", startTime.elapsed().as_millis());
+        Ok(())
+    }
+
+    async fn fetch_staking(&mut self) -> anyhow::Result<()> {
+        self.execute_plan(&FetchPlan::new(&[FetchComponent::Staking])).await
+    }
 
-        let tokens = tokens.read().await;
-        // self.vault
-        //     .fetch_multi_vault_token_variables(&mut tokens)
-        //     .await;
-        print(format!("tokens full: {:?}", tokens).as_str());
-        // re assign new tokens
-        self.config.tokens = tokens.to_vec();
+    async fn fetch_vesting(&mut self) -> anyhow::Result<()> {
+        let vester = self.config.contract_address.vester_plp.to_lowercase();
+        let account = match self.config.selected_account.clone() {
+            Some(account) if !vester.is_empty() => account,
+            _ => return Ok(()),
+        };
+        let vesting = contracts::vester::fetch_vesting(&self.config.chain, &vester, &account).await?;
+        self.vault.state.update_vesting(account.parse()?, vesting);
+        Ok(())
+    }
 
-        for token in self.config.tokens.iter_mut() {
-            token.calculate_available_liquidity();
-        }
+    async fn account_history(&self, account: &str, from_block: u64) -> anyhow::Result<Vec<contracts::account_history::HistoryEntry>> {
+        contracts::account_history::fetch_config_history(&self.config, self.vault.state.usdp_address, account, from_block).await
+    }
 
-        print("all done");
+    fn export_history(&self, entries: &[contracts::account_history::HistoryEntry], format: contracts::history_export::ExportFormat) -> anyhow::Result<String> {
+        contracts::history_export::export(entries, &self.config.tokens, self.vault.state.usdp_address, format)
+    }
 
-        // let fetch_token_task = tokio::spawn(vault.fetch_token_configuration(&mut tokens));
-        // let fetch_token_price_task = tokio::spawn(vault.fetch_token_prices(&mut tokens));
-        // tokio::try_join!(fetch_token_task, fetch_token_price_task, fetch_account_balance)?;
+    async fn refresh(&mut self, class: scheduler::DataClass) -> anyhow::Result<()> {
+        let refreshed = scheduler::fetch(class, self.vault.clone(), self.config.clone()).await?;
+        self.apply_refresh(refreshed);
         Ok(())
     }
 }
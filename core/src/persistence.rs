@@ -0,0 +1,245 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use ethers::types::U256;
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::contracts::freshness::FetchedAt;
+use crate::contracts::vault::VaultState;
+use crate::scheduler::DataClass;
+use crate::shared::{MaybeSend, Shared};
+use crate::utils::now_millis;
+use crate::Router;
+
+/// Bumped on breaking changes of `RouterSnapshot`, older snapshots are refused
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// Router state to render cached data before the first fetch, see `Router::snapshot`
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RouterSnapshot {
+    pub version: u32,
+    pub chain_id: u64,
+    pub config: Config,
+    pub vault_state: VaultState,
+    pub price_plp_buy: U256,
+    pub price_plp_sell: U256,
+    // head block and unix millis of the fetch the data comes from
+    pub block_number: Option<u64>,
+    pub fetched_at: Option<u64>,
+    // unix millis
+    pub saved_at: u64,
+}
+
+impl RouterSnapshot {
+    pub fn to_json(&self) -> anyhow::Result<String> {
+        Ok(serde_json::to_string(self)?)
+    }
+
+    pub fn from_json(json: &str) -> anyhow::Result<Self> {
+        #[derive(Deserialize)]
+        struct Versioned {
+            version: u32,
+        }
+        let versioned: Versioned = serde_json::from_str(json)?;
+        if versioned.version != SNAPSHOT_VERSION {
+            return Err(anyhow::anyhow!("unsupported snapshot version {}, expected {}", versioned.version, SNAPSHOT_VERSION));
+        }
+        Ok(serde_json::from_str(json)?)
+    }
+}
+
+/// Key of the snapshot of a chain in a `SnapshotStorage`
+pub fn snapshot_key(chain_id: u64) -> String {
+    format!("dptp-router-snapshot-{}", chain_id)
+}
+
+/// Key value store of snapshots, eg: localStorage or IndexedDB in the browser
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+pub trait SnapshotStorage: MaybeSend {
+    async fn load(&self, key: &str) -> anyhow::Result<Option<String>>;
+    async fn save(&self, key: &str, value: &str) -> anyhow::Result<()>;
+}
+
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    values: Shared<HashMap<String, String>>,
+}
+
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+impl SnapshotStorage for MemoryStorage {
+    async fn load(&self, key: &str) -> anyhow::Result<Option<String>> {
+        Ok(self.values.borrow().get(key).cloned())
+    }
+
+    async fn save(&self, key: &str, value: &str) -> anyhow::Result<()> {
+        self.values.borrow_mut().insert(key.to_string(), value.to_string());
+        Ok(())
+    }
+}
+
+/// One `<key>.json` file per snapshot in `dir`
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug, Clone)]
+pub struct FileStorage {
+    dir: std::path::PathBuf,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl FileStorage {
+    pub fn new(dir: impl Into<std::path::PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    fn path(&self, key: &str) -> std::path::PathBuf {
+        self.dir.join(format!("{}.json", key))
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[cfg_attr(feature = "send", async_trait)]
+#[cfg_attr(not(feature = "send"), async_trait(?Send))]
+impl SnapshotStorage for FileStorage {
+    async fn load(&self, key: &str) -> anyhow::Result<Option<String>> {
+        match std::fs::read_to_string(self.path(key)) {
+            Ok(value) => Ok(Some(value)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn save(&self, key: &str, value: &str) -> anyhow::Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+        // written aside then renamed, a crash never leaves half a snapshot
+        let tmp = self.path(&format!("{}.tmp", key));
+        std::fs::write(&tmp, value)?;
+        std::fs::rename(tmp, self.path(key))?;
+        Ok(())
+    }
+}
+
+impl Router {
+    pub(crate) fn mark_fetched(&mut self, classes: &[DataClass], fetched_at: FetchedAt) {
+        self.vault.state.record_fetch(classes, fetched_at);
+        self.block_number = fetched_at.block_number.or(self.block_number);
+        self.fetched_at = Some(fetched_at.timestamp);
+    }
+
+    pub fn snapshot(&self) -> RouterSnapshot {
+        RouterSnapshot {
+            version: SNAPSHOT_VERSION,
+            chain_id: self.config.chain.chain_id,
+            config: self.config.clone(),
+            vault_state: self.vault.state.clone(),
+            price_plp_buy: self.price_plp_buy,
+            price_plp_sell: self.price_plp_sell,
+            block_number: self.block_number,
+            fetched_at: self.fetched_at,
+            saved_at: now_millis(),
+        }
+    }
+
+    /// Cached state of the initialized chain. Tokens are restored only when still in the config,
+    /// the other config fields come from the bundled config and the selected and watched accounts are kept when set.
+    pub fn restore(&mut self, snapshot: RouterSnapshot) -> anyhow::Result<()> {
+        if snapshot.version != SNAPSHOT_VERSION {
+            return Err(anyhow::anyhow!("unsupported snapshot version {}, expected {}", snapshot.version, SNAPSHOT_VERSION));
+        }
+        if snapshot.chain_id != self.config.chain.chain_id {
+            return Err(anyhow::anyhow!("snapshot of chain {}, router initialized for {}", snapshot.chain_id, self.config.chain.chain_id));
+        }
+        let before = self.change_baseline();
+        for token in self.config.tokens.iter_mut() {
+            if let Some(cached) = snapshot.config.tokens.iter().find(|cached| cached.address.to_lowercase() == token.address) {
+                *token = cached.clone();
+                token.address = token.address.to_lowercase();
+            }
+        }
+        if self.config.selected_account.is_none() {
+            self.config.selected_account = snapshot.config.selected_account;
+        }
+        if self.config.watched_accounts.is_empty() {
+            self.config.watched_accounts = snapshot.config.watched_accounts;
+        }
+        // the policy is a setting of this session, not cached data
+        let freshness_policy = self.vault.state.freshness_policy;
+        self.vault.state = VaultState { freshness_policy, ..snapshot.vault_state };
+        self.price_plp_buy = snapshot.price_plp_buy;
+        self.price_plp_sell = snapshot.price_plp_sell;
+        self.block_number = snapshot.block_number;
+        self.fetched_at = snapshot.fetched_at;
+        self.emit_changes(before);
+        Ok(())
+    }
+
+    pub async fn save_snapshot(&self, storage: &dyn SnapshotStorage) -> anyhow::Result<()> {
+        storage.save(&snapshot_key(self.config.chain.chain_id), &self.snapshot().to_json()?).await
+    }
+
+    /// Restores the stored snapshot of the chain, false when there is none
+    pub async fn load_snapshot(&mut self, storage: &dyn SnapshotStorage) -> anyhow::Result<bool> {
+        match storage.load(&snapshot_key(self.config.chain.chain_id)).await? {
+            Some(json) => {
+                self.restore(RouterSnapshot::from_json(&json)?)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use super::*;
+    use crate::contracts::token::Price;
+    use crate::RouterTrait;
+
+    fn create_router() -> Router {
+        let mut router = Router::new();
+        router.initilize(97).unwrap();
+        router
+    }
+
+    #[tokio::test]
+    async fn snapshot_round_trip() {
+        let mut router = create_router();
+        router.config.tokens[0].min_price = Some(Price::new(Decimal::from(2000)));
+        router.vault.state.plp_supply = U256::exp10(21);
+        router.price_plp_buy = U256::exp10(30);
+        router.mark_fetched(&[DataClass::Prices], FetchedAt::now(Some(100)));
+
+        let storage = MemoryStorage::default();
+        router.save_snapshot(&storage).await.unwrap();
+
+        let mut restored = create_router();
+        assert!(restored.load_snapshot(&storage).await.unwrap());
+        assert_eq!(restored.config.tokens[0].min_price, Some(Price::new(Decimal::from(2000))));
+        assert_eq!(restored.vault.state.plp_supply, U256::exp10(21));
+        assert_eq!(restored.price_plp_buy, U256::exp10(30));
+        assert_eq!(restored.block_number, Some(100));
+        assert_eq!(restored.fetched_at, router.fetched_at);
+        assert_eq!(restored.vault.state.fetched_at[&DataClass::Prices].block_number, Some(100));
+
+        let mut other_chain = Router::new();
+        assert!(!other_chain.load_snapshot(&storage).await.unwrap());
+        assert!(other_chain.restore(router.snapshot()).is_err());
+    }
+
+    #[tokio::test]
+    async fn snapshot_versions_and_files() {
+        let router = create_router();
+        let json = router.snapshot().to_json().unwrap().replacen(&format!("\"version\":{}", SNAPSHOT_VERSION), "\"version\":0", 1);
+        assert!(RouterSnapshot::from_json(&json).is_err());
+
+        let dir = std::env::temp_dir().join(format!("dptp-snapshot-test-{}", now_millis()));
+        let storage = FileStorage::new(&dir);
+        assert_eq!(storage.load("missing").await.unwrap(), None);
+        router.save_snapshot(&storage).await.unwrap();
+        assert!(create_router().load_snapshot(&storage).await.unwrap());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,220 @@
+use ethers::types::{Address, U256};
+use serde::{Deserialize, Serialize};
+
+use crate::contracts::token::Token;
+use crate::contracts::vault_logic::{expand_decimals, VaultLogic};
+use crate::Router;
+
+const PLP_DECIMALS: u32 = 18;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum AssetKind {
+    Token,
+    // PLP and the reward tracker tokens holding it 1:1, eg: fPLP
+    Plp,
+    Rewards,
+    Position,
+}
+
+/// One line of the portfolio breakdown
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct PortfolioAsset {
+    pub kind: AssetKind,
+    // held token, reward token or index token of the position
+    pub token: Address,
+    pub symbol: String,
+    // token decimals, reserved amount for positions
+    pub amount: U256,
+    // 30 decimals, None when the price or the equity isn't known
+    pub usd_value: Option<U256>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Portfolio {
+    pub account: Address,
+    pub assets: Vec<PortfolioAsset>,
+    // sum of the valued assets, 30 decimals
+    pub total_usd: U256,
+    // false when some assets are left out of the total
+    pub is_complete: bool,
+}
+
+impl Router {
+    /// Net worth of `account` from the last fetched data: wallet tokens at bid price, PLP and fPLP
+    /// at the sell price of PLP and claimable rewards at the bid price of the reward token.
+    /// Positions are listed without equity, the vault only exposes their reserved amount.
+    pub fn portfolio(&self, account: &Address) -> Portfolio {
+        let plp_price = self.vault.state.get_plp_price(false);
+        let mut assets = vec![];
+
+        for token in self.config.tokens.iter() {
+            let amount = token.get_balance_raw(&format!("{:?}", account));
+            if amount.is_zero() {
+                continue;
+            }
+            let (kind, usd_value) = match self.is_plp(token) {
+                true => (AssetKind::Plp, plp_usd(amount, plp_price)),
+                false => (AssetKind::Token, token_usd(token, amount)),
+            };
+            assets.push(PortfolioAsset { kind, token: token.get_parsed_address(), symbol: token.symbol.clone(), amount, usd_value });
+        }
+
+        for staking in self.vault.state.get_staking_info(account) {
+            if staking.claimable.is_zero() {
+                continue;
+            }
+            let reward_token = self
+                .vault
+                .state
+                .reward_trackers
+                .iter()
+                .find(|tracker| tracker.address == staking.reward_tracker)
+                .map(|tracker| tracker.reward_token)
+                .unwrap_or_default();
+            let token = self.find_token(&reward_token);
+            assets.push(PortfolioAsset {
+                kind: AssetKind::Rewards,
+                token: reward_token,
+                symbol: token.map(|token| token.symbol.clone()).unwrap_or_default(),
+                amount: staking.claimable,
+                usd_value: token.and_then(|token| token_usd(token, staking.claimable)),
+            });
+        }
+
+        for position in self.vault.state.get_positions(account) {
+            assets.push(PortfolioAsset {
+                kind: AssetKind::Position,
+                token: position.index_token,
+                symbol: self.find_token(&position.index_token).map(|token| token.symbol.clone()).unwrap_or_default(),
+                amount: position.reserved_amount,
+                usd_value: None,
+            });
+        }
+
+        Portfolio {
+            account: *account,
+            total_usd: assets.iter().filter_map(|asset| asset.usd_value).fold(U256::zero(), |sum, value| sum + value),
+            is_complete: assets.iter().all(|asset| asset.usd_value.is_some()),
+            assets,
+        }
+    }
+
+    fn find_token(&self, address: &Address) -> Option<&Token> {
+        self.config.tokens.iter().find(|token| token.get_parsed_address() == *address)
+    }
+
+    // PLP and its reward trackers, also known from the config before any staking fetch
+    fn is_plp(&self, token: &Token) -> bool {
+        let address = token.get_parsed_address();
+        let contract_address = &self.config.contract_address;
+        [&contract_address.plp_token, &contract_address.reward_tracker_fee_plp, &contract_address.reward_tracker_fee_staked_plp]
+            .iter()
+            .any(|plp| plp.parse::<Address>().map(|plp| plp == address).unwrap_or(false))
+            || self.vault.state.reward_trackers.iter().any(|tracker| tracker.address == address)
+    }
+}
+
+// ^18 * ^18 * ^12 / ^18 = ^30
+fn plp_usd(amount: U256, plp_price: U256) -> Option<U256> {
+    if plp_price.is_zero() {
+        return None;
+    }
+    Some(amount * plp_price * expand_decimals(1, 12) / expand_decimals(1, PLP_DECIMALS))
+}
+
+fn token_usd(token: &Token, amount: U256) -> Option<U256> {
+    match token.bid_price {
+        Some(price) if !price.is_zero() => Some(amount * price.raw / expand_decimals(1, token.decimals as u32)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contracts::positions::PositionInfo;
+    use crate::contracts::staking::{RewardTrackerInfo, StakingInfo};
+    use crate::contracts::token::Price;
+    use crate::RouterTrait;
+    use rust_decimal::Decimal;
+
+    const ACCOUNT: &str = "0x1f9840a85d5af5bf1d1762f925bdaddc4201f984";
+
+    fn create_router() -> Router {
+        let mut router = Router::new();
+        router.initilize(97).unwrap();
+        router.set_account(ACCOUNT.to_string());
+        // PLP at 1.5$
+        router.vault.state.total_aum = [U256::exp10(33) * 3; 2];
+        router.vault.state.plp_supply = U256::exp10(21) * 2;
+        router.vault.state.usdp_supply = U256::exp10(21) * 2;
+        router
+    }
+
+    fn symbol_index(router: &Router, symbol: &str) -> usize {
+        router.config.tokens.iter().position(|token| token.symbol == symbol).unwrap()
+    }
+
+    #[test]
+    fn portfolio_values_every_asset() {
+        let mut router = create_router();
+        let account: Address = ACCOUNT.parse().unwrap();
+        let (eth, plp, fplp) = (symbol_index(&router, "ETH"), symbol_index(&router, "PLP"), symbol_index(&router, "fPLP"));
+        router.config.tokens[eth].bid_price = Some(Price::new(Decimal::from(2000)));
+        router.config.tokens[eth].update_balance(&ACCOUNT.to_string(), U256::exp10(18));
+        router.config.tokens[plp].update_balance(&ACCOUNT.to_string(), U256::exp10(18) * 10);
+        router.config.tokens[fplp].update_balance(&ACCOUNT.to_string(), U256::exp10(18) * 20);
+
+        let eth_address = router.config.tokens[eth].get_parsed_address();
+        let reward_tracker = router.config.tokens[fplp].get_parsed_address();
+        let tracker = RewardTrackerInfo { address: reward_tracker, reward_token: eth_address, ..RewardTrackerInfo::default() };
+        let staking = StakingInfo { reward_tracker, claimable: U256::exp10(17), ..StakingInfo::default() };
+        router.vault.state.update_staking(Some(account), vec![(tracker, Some(staking))]);
+
+        let portfolio = router.portfolio(&account);
+        let kinds: Vec<AssetKind> = portfolio.assets.iter().map(|asset| asset.kind).collect();
+        assert_eq!(kinds, vec![AssetKind::Token, AssetKind::Plp, AssetKind::Plp, AssetKind::Rewards]);
+        assert_eq!(portfolio.assets[0].usd_value, Some(U256::exp10(30) * 2000));
+        assert_eq!(portfolio.assets[2].usd_value, Some(U256::exp10(30) * 30));
+        assert_eq!(portfolio.assets[3].symbol, "ETH");
+        // 2000 + 15 + 30 + 200
+        assert_eq!(portfolio.total_usd, U256::exp10(30) * 2245);
+        assert!(portfolio.is_complete);
+    }
+
+    #[test]
+    fn portfolio_flags_unvalued_assets() {
+        let mut router = create_router();
+        let account: Address = ACCOUNT.parse().unwrap();
+        let btc = symbol_index(&router, "BTC");
+        // no price fetched
+        router.config.tokens[btc].update_balance(&ACCOUNT.to_string(), U256::exp10(18));
+        let index_token = router.config.tokens[btc].get_parsed_address();
+        router.vault.state.update_positions(account, vec![PositionInfo { index_token, is_long: true, reserved_amount: U256::from(5), ..PositionInfo::default() }]);
+
+        let portfolio = router.portfolio(&account);
+        assert_eq!(portfolio.assets.len(), 2);
+        assert_eq!(portfolio.assets[1].kind, AssetKind::Position);
+        assert_eq!(portfolio.assets[1].symbol, "BTC");
+        assert_eq!(portfolio.total_usd, U256::zero());
+        assert!(!portfolio.is_complete);
+        assert!(router.portfolio(&Address::zero()).assets.is_empty());
+    }
+
+    #[test]
+    fn portfolio_values_configured_plp_only() {
+        let mut router = create_router();
+        let account: Address = ACCOUNT.parse().unwrap();
+        let btc = symbol_index(&router, "BTC");
+        router.config.tokens[btc].symbol = "fsPLP".to_string();
+        router.config.tokens[btc].update_balance(&ACCOUNT.to_string(), U256::exp10(18));
+        // not a PLP contract of the config, no price
+        assert_eq!(router.portfolio(&account).assets[0].kind, AssetKind::Token);
+        assert!(!router.portfolio(&account).is_complete);
+
+        router.config.contract_address.reward_tracker_fee_staked_plp = router.config.tokens[btc].address.clone();
+        let portfolio = router.portfolio(&account);
+        assert_eq!(portfolio.assets[0].kind, AssetKind::Plp);
+        assert_eq!(portfolio.total_usd, U256::exp10(29) * 15);
+    }
+}
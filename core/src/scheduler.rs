@@ -0,0 +1,391 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::future::Future;
+use std::rc::Rc;
+use std::time::Duration;
+
+use instant::Instant;
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+use crate::contracts::freshness::FetchedAt;
+use crate::contracts::vault::{Vault, VaultState};
+use crate::fetch_plan::{FetchOutput, FetchPlan};
+use crate::subscription::RouterMut;
+use crate::{p, Router, RouterTrait};
+
+// longest sleep of the loop, so pause, stop and new intervals apply within a second
+const MAX_SLEEP: Duration = Duration::from_secs(1);
+
+/// Data refreshed on its own interval, in refresh order when several are due
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum DataClass {
+    // Vault.tokenConfigurations, changes on governance actions only
+    TokenConfiguration,
+    // aum, PLP and USDP supply, fees
+    VaultState,
+    // pool amounts, reserves and the available long/short liquidity
+    VaultInfo,
+    Prices,
+    // balances and allowances of the selected account
+    Balances,
+}
+
+impl DataClass {
+    pub const ALL: [DataClass; 5] = [
+        DataClass::TokenConfiguration,
+        DataClass::VaultState,
+        DataClass::VaultInfo,
+        DataClass::Prices,
+        DataClass::Balances,
+    ];
+}
+
+/// Refresh interval of each data class in milliseconds, 0 disables the class
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct RefreshIntervals {
+    pub token_configuration_ms: u64,
+    pub vault_state_ms: u64,
+    pub vault_info_ms: u64,
+    pub prices_ms: u64,
+    pub balances_ms: u64,
+}
+
+impl Default for RefreshIntervals {
+    fn default() -> Self {
+        Self {
+            token_configuration_ms: 300_000,
+            vault_state_ms: 30_000,
+            vault_info_ms: 30_000,
+            prices_ms: 5_000,
+            balances_ms: 15_000,
+        }
+    }
+}
+
+impl RefreshIntervals {
+    pub fn interval(&self, class: DataClass) -> Option<Duration> {
+        let ms = match class {
+            DataClass::TokenConfiguration => self.token_configuration_ms,
+            DataClass::VaultState => self.vault_state_ms,
+            DataClass::VaultInfo => self.vault_info_ms,
+            DataClass::Prices => self.prices_ms,
+            DataClass::Balances => self.balances_ms,
+        };
+        (ms > 0).then(|| Duration::from_millis(ms))
+    }
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct SchedulerStatus {
+    pub is_running: bool,
+    pub is_paused: bool,
+    pub intervals: RefreshIntervals,
+    // last error of each class, cleared by the next successful refresh
+    pub errors: HashMap<DataClass, String>,
+}
+
+#[derive(Debug, Default)]
+struct SchedulerState {
+    intervals: RefreshIntervals,
+    last_refresh: HashMap<DataClass, Instant>,
+    errors: HashMap<DataClass, String>,
+    paused: bool,
+    // bumped by start and stop, a loop exits once its generation is stale
+    generation: u64,
+    running: bool,
+}
+
+impl SchedulerState {
+    // classes never refreshed or whose interval elapsed
+    fn due(&self, now: Instant) -> Vec<DataClass> {
+        if self.paused {
+            return vec![];
+        }
+        DataClass::ALL
+            .into_iter()
+            .filter(|class| match (self.intervals.interval(*class), self.last_refresh.get(class)) {
+                (None, _) => false,
+                (Some(_), None) => true,
+                (Some(interval), Some(last)) => now.duration_since(*last) >= interval,
+            })
+            .collect()
+    }
+
+    fn sleep_duration(&self, now: Instant) -> Duration {
+        if self.paused {
+            return MAX_SLEEP;
+        }
+        DataClass::ALL
+            .into_iter()
+            .filter_map(|class| {
+                let interval = self.intervals.interval(class)?;
+                let elapsed = self.last_refresh.get(&class).map(|last| now.duration_since(*last)).unwrap_or(interval);
+                Some(interval.saturating_sub(elapsed))
+            })
+            .min()
+            .unwrap_or(MAX_SLEEP)
+            .min(MAX_SLEEP)
+    }
+}
+
+/// Refreshes the router data classes on their own intervals, see `run`.
+/// Clones share the same state, keep one to pause, resume or stop the loop.
+#[derive(Debug, Clone, Default)]
+pub struct RefreshScheduler {
+    state: Rc<RefCell<SchedulerState>>,
+}
+
+impl RefreshScheduler {
+    pub fn new(intervals: RefreshIntervals) -> Self {
+        Self { state: Rc::new(RefCell::new(SchedulerState { intervals, ..SchedulerState::default() })) }
+    }
+
+    pub fn set_intervals(&self, intervals: RefreshIntervals) {
+        self.state.borrow_mut().intervals = intervals;
+    }
+
+    /// No refresh until `resume`, the loop keeps running
+    pub fn pause(&self) {
+        self.state.borrow_mut().paused = true;
+    }
+
+    pub fn resume(&self) {
+        self.state.borrow_mut().paused = false;
+    }
+
+    /// Refresh `class` on the next tick, eg: after a trade of the account
+    pub fn invalidate(&self, class: DataClass) {
+        self.state.borrow_mut().last_refresh.remove(&class);
+    }
+
+    /// Ends the running loop at its next tick
+    pub fn stop(&self) {
+        let mut state = self.state.borrow_mut();
+        state.generation += 1;
+        state.running = false;
+    }
+
+    pub fn status(&self) -> SchedulerStatus {
+        let state = self.state.borrow();
+        SchedulerStatus {
+            is_running: state.running,
+            is_paused: state.paused,
+            intervals: state.intervals,
+            errors: state.errors.clone(),
+        }
+    }
+
+    /// Refresh loop of `router` until `stop`, spawn it on a local executor:
+    /// `tokio::task::spawn_local` natively, `wasm_bindgen_futures::spawn_local` in the browser.
+    /// `sleep` is the timer of the runtime, eg: `tokio::time::sleep`.
+    /// The router is only borrowed to copy its state and to write the results, never across a fetch.
+    pub async fn run<S, F>(&self, router: Rc<RefCell<Router>>, sleep: S)
+    where
+        S: Fn(Duration) -> F,
+        F: Future<Output = ()>,
+    {
+        let generation = {
+            let mut state = self.state.borrow_mut();
+            state.generation += 1;
+            state.running = true;
+            state.generation
+        };
+        loop {
+            let due = self.state.borrow().due(Instant::now());
+            for class in due {
+                if self.state.borrow().generation != generation {
+                    return;
+                }
+                let snapshot = match router.try_borrow() {
+                    Ok(router) => (router.vault.clone(), router.config.clone()),
+                    // busy with a fetch of the app, retry on the next tick
+                    Err(_) => break,
+                };
+                let result = fetch(class, snapshot.0, snapshot.1).await;
+                if !self.record(&router, generation, class, result) {
+                    return;
+                }
+            }
+            let duration = self.state.borrow().sleep_duration(Instant::now());
+            sleep(duration).await;
+            if self.state.borrow().generation != generation {
+                return;
+            }
+        }
+    }
+}
+
+impl RefreshScheduler {
+    // applies a fetch result, false when the loop was stopped or restarted during the fetch.
+    // The state isn't borrowed while applying, the subscribers may pause or stop the scheduler.
+    fn record(&self, router: &RefCell<Router>, generation: u64, class: DataClass, result: anyhow::Result<Refreshed>) -> bool {
+        {
+            let mut state = self.state.borrow_mut();
+            if state.generation != generation {
+                return false;
+            }
+            // mark failed refreshes too, to retry on the interval instead of every tick
+            state.last_refresh.insert(class, Instant::now());
+        }
+        let applied = result.and_then(|refreshed| apply(router, refreshed));
+        let mut state = self.state.borrow_mut();
+        match applied {
+            Ok(_) => {
+                state.errors.remove(&class);
+            }
+            Err(e) => {
+                p!("refresh {:?} failed: {}", class, e);
+                state.errors.insert(class, e.to_string());
+            }
+        }
+        true
+    }
+}
+
+// the subscribers are called once the router is released, they may read it
+fn apply(router: &RefCell<Router>, refreshed: Refreshed) -> anyhow::Result<()> {
+    RouterMut::try_borrow(router)?.apply_refresh(refreshed);
+    Ok(())
+}
+
+/// Result of a data class fetch, applied with `Router::apply_refresh`
+#[derive(Debug, Clone)]
+pub enum Refreshed {
+    Fetched(Box<FetchOutput>),
+    VaultState { chain_id: u64, fetched_at: FetchedAt, state: Box<VaultState> },
+}
+
+/// Fetches `class` on copies of the router vault and config
+pub async fn fetch(class: DataClass, mut vault: Vault, config: Config) -> anyhow::Result<Refreshed> {
+    if let Some(plan) = FetchPlan::for_class(class) {
+        return Ok(Refreshed::Fetched(Box::new(plan.execute(&config).await?)));
+    }
+    vault.init_vault_state().await?;
+    let fetched_at = vault.state.fetched_at.get(&DataClass::VaultState).copied().unwrap_or_else(|| FetchedAt::now(None));
+    Ok(Refreshed::VaultState { chain_id: config.chain.chain_id, fetched_at, state: Box::new(vault.state) })
+}
+
+impl Router {
+    /// Writes a `fetch` result and notifies the subscribers, dropped when the chain was switched meanwhile
+    pub fn apply_refresh(&mut self, refreshed: Refreshed) {
+        match refreshed {
+            Refreshed::Fetched(output) => self.apply_fetch(*output),
+            Refreshed::VaultState { chain_id, fetched_at, state } if chain_id == self.config.chain.chain_id => {
+                let before = self.change_baseline();
+                // the metadata of the other classes and the policy may have changed during the fetch
+                let (mut metadata, freshness_policy) = (std::mem::take(&mut self.vault.state.fetched_at), self.vault.state.freshness_policy);
+                metadata.insert(DataClass::VaultState, fetched_at);
+                self.vault.state = VaultState { fetched_at: metadata, freshness_policy, ..*state };
+                self.calculate_price_plp();
+                self.emit_changes(before);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// `RefreshScheduler::run` on the tokio timer, spawn it with `tokio::task::spawn_local` in a `LocalSet`
+#[cfg(not(target_arch = "wasm32"))]
+pub async fn run_tokio(scheduler: RefreshScheduler, router: Rc<RefCell<Router>>) {
+    scheduler.run(router, tokio::time::sleep).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_state() -> SchedulerState {
+        SchedulerState {
+            intervals: RefreshIntervals { token_configuration_ms: 0, ..RefreshIntervals::default() },
+            ..SchedulerState::default()
+        }
+    }
+
+    #[test]
+    fn due_follows_intervals() {
+        let mut state = create_state();
+        let start = Instant::now();
+        // everything but the disabled token configuration on the first tick
+        assert_eq!(state.due(start), vec![DataClass::VaultState, DataClass::VaultInfo, DataClass::Prices, DataClass::Balances]);
+        assert_eq!(state.sleep_duration(start), Duration::ZERO);
+
+        for class in DataClass::ALL {
+            state.last_refresh.insert(class, start);
+        }
+        assert!(state.due(start + Duration::from_millis(4_999)).is_empty());
+        assert_eq!(state.sleep_duration(start + Duration::from_millis(4_500)), Duration::from_millis(500));
+        assert_eq!(state.due(start + Duration::from_secs(5)), vec![DataClass::Prices]);
+        assert_eq!(state.due(start + Duration::from_secs(30)), vec![DataClass::VaultState, DataClass::VaultInfo, DataClass::Prices, DataClass::Balances]);
+    }
+
+    #[test]
+    fn pause_and_stop() {
+        let scheduler = RefreshScheduler::new(RefreshIntervals::default());
+        let handle = scheduler.clone();
+        handle.pause();
+        assert!(scheduler.status().is_paused);
+        assert!(scheduler.state.borrow().due(Instant::now()).is_empty());
+        assert_eq!(scheduler.state.borrow().sleep_duration(Instant::now()), MAX_SLEEP);
+
+        handle.resume();
+        assert_eq!(scheduler.state.borrow().due(Instant::now()).len(), DataClass::ALL.len());
+
+        let router = Rc::new(RefCell::new(Router::new()));
+        // the loop ends once stopped, with nothing fetched while paused
+        scheduler.pause();
+        let sleep = |_| {
+            handle.stop();
+            async {}
+        };
+        futures::executor::block_on(scheduler.run(router, sleep));
+        assert!(!scheduler.status().is_running);
+    }
+
+    #[tokio::test]
+    async fn failed_vault_state_is_recorded() {
+        let mut router = Router::new();
+        router.initilize(97).unwrap();
+        // nothing listens there
+        router.vault.chain.rpc_urls = vec!["http://127.0.0.1:1".to_string()];
+        let result = fetch(DataClass::VaultState, router.vault.clone(), router.config.clone()).await;
+        assert!(result.is_err());
+
+        let scheduler = RefreshScheduler::new(RefreshIntervals::default());
+        assert!(scheduler.record(&RefCell::new(router), 0, DataClass::VaultState, result));
+        assert!(scheduler.status().errors.contains_key(&DataClass::VaultState));
+    }
+
+    // the scheduler handles aren't Send, callbacks must be with the `send` feature
+    #[cfg(not(feature = "send"))]
+    #[test]
+    fn subscribers_can_pause_the_scheduler() {
+        let scheduler = RefreshScheduler::new(RefreshIntervals::default());
+        let router = RefCell::new(Router::new());
+        router.borrow_mut().initilize(97).unwrap();
+        let router = Rc::new(router);
+        let (handle, callback_router) = (scheduler.clone(), Rc::downgrade(&router));
+        let statuses = Rc::new(RefCell::new(vec![]));
+        let callback_statuses = statuses.clone();
+        router.borrow().subscriptions.subscribe(move |_| {
+            handle.pause();
+            callback_statuses.borrow_mut().push(handle.status());
+            // the router is released before the subscribers are called
+            assert!(callback_router.upgrade().unwrap().try_borrow_mut().is_ok());
+        });
+
+        let mut state = router.borrow().vault.state.clone();
+        state.plp_supply = ethers::types::U256::exp10(18);
+        let refreshed = Refreshed::VaultState { chain_id: 97, fetched_at: FetchedAt::now(None), state: Box::new(state) };
+        assert!(scheduler.record(&router, 0, DataClass::VaultState, Ok(refreshed)));
+        assert!(statuses.borrow().iter().all(|status| status.is_paused && status.errors.is_empty()));
+        assert!(!statuses.borrow().is_empty());
+        assert!(scheduler.status().is_paused);
+        assert!(scheduler.state.borrow().last_refresh.contains_key(&DataClass::VaultState));
+
+        // a stale loop doesn't write its result
+        scheduler.stop();
+        assert!(!scheduler.record(&router, 0, DataClass::Prices, Err(anyhow::anyhow!("stale"))));
+        assert!(scheduler.status().errors.is_empty());
+    }
+}
@@ -0,0 +1,187 @@
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+
+#[cfg(not(feature = "send"))]
+use std::{cell::RefCell, rc::Rc};
+#[cfg(feature = "send")]
+use std::sync::{Arc, Mutex};
+
+#[cfg(feature = "send")]
+use tokio::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+#[cfg(feature = "send")]
+use crate::fetch_plan::{FetchComponent, FetchPlan};
+#[cfg(feature = "send")]
+use crate::scheduler::{self, DataClass};
+#[cfg(feature = "send")]
+use crate::{Router, RouterTrait};
+
+/// Send + Sync with the `send` feature, implemented by every type otherwise
+#[cfg(feature = "send")]
+pub trait MaybeSend: Send + Sync {}
+#[cfg(feature = "send")]
+impl<T: Send + Sync + ?Sized> MaybeSend for T {}
+
+#[cfg(not(feature = "send"))]
+pub trait MaybeSend {}
+#[cfg(not(feature = "send"))]
+impl<T: ?Sized> MaybeSend for T {}
+
+/// Shared mutable state: `Rc<RefCell>` for wasm, `Arc<Mutex>` with the `send` feature.
+/// Never borrow it across an await.
+pub struct Shared<T> {
+    #[cfg(not(feature = "send"))]
+    inner: Rc<RefCell<T>>,
+    #[cfg(feature = "send")]
+    inner: Arc<Mutex<T>>,
+}
+
+#[cfg(not(feature = "send"))]
+impl<T> Shared<T> {
+    pub fn new(value: T) -> Self {
+        Self { inner: Rc::new(RefCell::new(value)) }
+    }
+
+    pub fn borrow(&self) -> impl Deref<Target = T> + '_ {
+        self.inner.borrow()
+    }
+
+    pub fn borrow_mut(&self) -> impl DerefMut<Target = T> + '_ {
+        self.inner.borrow_mut()
+    }
+}
+
+#[cfg(feature = "send")]
+impl<T> Shared<T> {
+    pub fn new(value: T) -> Self {
+        Self { inner: Arc::new(Mutex::new(value)) }
+    }
+
+    // a panic while borrowed doesn't make the state unusable, same as RefCell
+    pub fn borrow(&self) -> impl Deref<Target = T> + '_ {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn borrow_mut(&self) -> impl DerefMut<Target = T> + '_ {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<T> Clone for Shared<T> {
+    fn clone(&self) -> Self {
+        Self { inner: self.inner.clone() }
+    }
+}
+
+impl<T: Default> Default for Shared<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Shared<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.borrow().fmt(f)
+    }
+}
+
+/// Router shared by the tasks of a multi-threaded runtime, eg: the state of an axum service.
+/// Fetches copy the config under a read lock and write their results under a short write lock,
+/// the readers are never blocked by the network.
+#[cfg(feature = "send")]
+#[derive(Debug, Clone)]
+pub struct SharedRouter {
+    router: Arc<RwLock<Router>>,
+}
+
+#[cfg(feature = "send")]
+impl From<Router> for SharedRouter {
+    fn from(router: Router) -> Self {
+        Self { router: Arc::new(RwLock::new(router)) }
+    }
+}
+
+#[cfg(feature = "send")]
+impl SharedRouter {
+    pub fn new(chain_id: u64) -> anyhow::Result<Self> {
+        let mut router = Router::new();
+        router.initilize(chain_id).map_err(|e| anyhow::anyhow!(e))?;
+        Ok(Self::from(router))
+    }
+
+    pub async fn read(&self) -> RwLockReadGuard<'_, Router> {
+        self.router.read().await
+    }
+
+    pub async fn write(&self) -> RwLockWriteGuard<'_, Router> {
+        self.router.write().await
+    }
+
+    pub async fn set_account(&self, account: String) {
+        self.router.write().await.set_account(account);
+    }
+
+    pub async fn execute_plan(&self, plan: &FetchPlan) -> anyhow::Result<()> {
+        let config = self.router.read().await.config.clone();
+        let output = plan.execute(&config).await?;
+        self.apply(|router| router.apply_fetch(output)).await;
+        Ok(())
+    }
+
+    pub async fn fetch_data(&self) -> anyhow::Result<()> {
+        self.execute_plan(&FetchPlan::accounts().with(FetchComponent::VaultInfo)).await
+    }
+
+    /// Fetches one data class, the vault state included
+    pub async fn refresh(&self, class: DataClass) -> anyhow::Result<()> {
+        let (vault, config) = {
+            let router = self.router.read().await;
+            (router.vault.clone(), router.config.clone())
+        };
+        let refreshed = scheduler::fetch(class, vault, config).await?;
+        self.apply(|router| router.apply_refresh(refreshed)).await;
+        Ok(())
+    }
+
+    // the subscribers are called once the write lock is released, they may read the router
+    async fn apply(&self, write: impl FnOnce(&mut Router)) {
+        let subscriptions = {
+            let mut router = self.router.write().await;
+            router.subscriptions.hold();
+            write(&mut router);
+            router.subscriptions.clone()
+        };
+        subscriptions.release();
+    }
+}
+
+#[cfg(all(test, feature = "send"))]
+mod tests {
+    use super::*;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    fn assert_send<T: Send>(_: &T) {}
+
+    #[test]
+    fn router_is_send_sync() {
+        assert_send_sync::<Router>();
+        assert_send_sync::<SharedRouter>();
+        let mut router = Router::new();
+        assert_send(&router.fetch_data());
+    }
+
+    #[tokio::test]
+    async fn shared_router_across_tasks() {
+        let router = SharedRouter::new(97).unwrap();
+        let task_router = router.clone();
+        // nothing to read, the task only writes the fetch metadata
+        tokio::spawn(async move { task_router.execute_plan(&FetchPlan::default()).await }).await.unwrap().unwrap();
+        assert!(router.read().await.fetched_at.is_some());
+
+        let received = Arc::new(Mutex::new(0));
+        let callback_received = Arc::clone(&received);
+        router.read().await.subscriptions.subscribe(move |_| *callback_received.lock().unwrap() += 1);
+        assert!(!router.read().await.subscriptions.is_empty());
+    }
+}
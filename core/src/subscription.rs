@@ -0,0 +1,367 @@
+use std::cell::{BorrowMutError, RefCell, RefMut};
+use std::collections::HashSet;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+#[cfg(not(feature = "send"))]
+use std::rc::Rc;
+#[cfg(feature = "send")]
+use std::sync::Arc;
+
+use ethers::types::{Address, U256};
+use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::contracts::token::{Price, Token};
+use crate::shared::{MaybeSend, Shared};
+use crate::Router;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct TokenPrices {
+    pub min_price: Option<Price>,
+    pub max_price: Option<Price>,
+    pub ask_price: Option<Price>,
+    pub bid_price: Option<Price>,
+}
+
+impl From<&Token> for TokenPrices {
+    fn from(token: &Token) -> Self {
+        Self { min_price: token.min_price, max_price: token.max_price, ask_price: token.ask_price, bid_price: token.bid_price }
+    }
+}
+
+/// Vault values watched for changes, USD in 30 decimals
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct VaultValues {
+    // min aum, max aum
+    pub total_aum: [U256; 2],
+    pub plp_supply: U256,
+    pub usdp_supply: U256,
+    pub price_plp_buy: U256,
+    pub price_plp_sell: U256,
+}
+
+/// A value that changed between two fetches, tokens are their lowercase address
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "type")]
+pub enum ChangeEvent {
+    PriceChanged { token: String, old: TokenPrices, new: TokenPrices },
+    BalanceChanged { token: String, account: Address, old: Option<Decimal>, new: Option<Decimal> },
+    AllowanceChanged { token: String, account: Address, spender: Address, old: Option<U256>, new: Option<U256> },
+    PoolAmountChanged { token: String, old: Option<U256>, new: Option<U256> },
+    VaultStateChanged { old: VaultValues, new: VaultValues },
+}
+
+/// Router values before a fetch, see `Router::change_baseline`
+#[derive(Debug, Clone)]
+pub struct ChangeBaseline {
+    pub tokens: Vec<Token>,
+    pub vault: VaultValues,
+}
+
+// keys of both maps, in order and once
+fn union<'a, K: std::hash::Hash + Eq + 'a>(a: impl Iterator<Item = &'a K>, b: impl Iterator<Item = &'a K>) -> Vec<&'a K> {
+    let mut seen = HashSet::new();
+    a.chain(b).filter(|key| seen.insert(*key)).collect()
+}
+
+/// Changes of the tokens in both lists, matched by address
+pub fn diff_tokens(old: &[Token], new: &[Token]) -> Vec<ChangeEvent> {
+    let mut events = vec![];
+    for new_token in new {
+        let old_token = match old.iter().find(|token| token.address == new_token.address) {
+            Some(token) => token,
+            None => continue,
+        };
+        let token = new_token.address.clone();
+
+        let (old_prices, new_prices) = (TokenPrices::from(old_token), TokenPrices::from(new_token));
+        if old_prices != new_prices {
+            events.push(ChangeEvent::PriceChanged { token: token.clone(), old: old_prices, new: new_prices });
+        }
+
+        let balance = |token: &Token, account: &Address| token.balances.as_ref().and_then(|balances| balances.get(account)).copied();
+        for account in union(old_token.balances.iter().flat_map(|b| b.keys()), new_token.balances.iter().flat_map(|b| b.keys())) {
+            let (old, new) = (balance(old_token, account), balance(new_token, account));
+            if old != new {
+                events.push(ChangeEvent::BalanceChanged { token: token.clone(), account: *account, old, new });
+            }
+        }
+
+        let allowances = |token: &Token, account: &Address| token.allowances.as_ref().and_then(|allowances| allowances.get(account)).cloned().unwrap_or_default();
+        for account in union(old_token.allowances.iter().flat_map(|a| a.keys()), new_token.allowances.iter().flat_map(|a| a.keys())) {
+            let (old_allowances, new_allowances) = (allowances(old_token, account), allowances(new_token, account));
+            for spender in union(old_allowances.keys(), new_allowances.keys()) {
+                let (old, new) = (old_allowances.get(spender).copied(), new_allowances.get(spender).copied());
+                if old != new {
+                    events.push(ChangeEvent::AllowanceChanged { token: token.clone(), account: *account, spender: *spender, old, new });
+                }
+            }
+        }
+
+        if old_token.pool_amounts != new_token.pool_amounts {
+            events.push(ChangeEvent::PoolAmountChanged { token, old: old_token.pool_amounts, new: new_token.pool_amounts });
+        }
+    }
+    events
+}
+
+#[cfg(not(feature = "send"))]
+type Callback = Rc<dyn Fn(&ChangeEvent)>;
+#[cfg(feature = "send")]
+type Callback = Arc<dyn Fn(&ChangeEvent) + Send + Sync>;
+
+#[derive(Default)]
+struct Registry {
+    next_id: u32,
+    callbacks: Vec<(u32, Callback)>,
+    streams: Vec<UnboundedSender<ChangeEvent>>,
+    // events emitted between `hold` and `release`
+    held: Option<Vec<ChangeEvent>>,
+}
+
+/// Callbacks and streams notified of the router changes after each fetch
+#[derive(Clone, Default)]
+pub struct Subscriptions {
+    registry: Shared<Registry>,
+}
+
+impl fmt::Debug for Subscriptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let registry = self.registry.borrow();
+        f.debug_struct("Subscriptions")
+            .field("callbacks", &registry.callbacks.len())
+            .field("streams", &registry.streams.len())
+            .finish()
+    }
+}
+
+impl Subscriptions {
+    /// Calls `callback` with every change, returns the id to unsubscribe
+    pub fn subscribe(&self, callback: impl Fn(&ChangeEvent) + MaybeSend + 'static) -> u32 {
+        let mut registry = self.registry.borrow_mut();
+        registry.next_id += 1;
+        let id = registry.next_id;
+        #[cfg(not(feature = "send"))]
+        registry.callbacks.push((id, Rc::new(callback)));
+        #[cfg(feature = "send")]
+        registry.callbacks.push((id, Arc::new(callback)));
+        id
+    }
+
+    pub fn unsubscribe(&self, id: u32) -> bool {
+        let mut registry = self.registry.borrow_mut();
+        let len = registry.callbacks.len();
+        registry.callbacks.retain(|(callback_id, _)| *callback_id != id);
+        registry.callbacks.len() != len
+    }
+
+    /// Stream of every change, ends when the router is dropped. Drop the receiver to unsubscribe.
+    pub fn stream(&self) -> UnboundedReceiver<ChangeEvent> {
+        let (sender, receiver) = unbounded();
+        self.registry.borrow_mut().streams.push(sender);
+        receiver
+    }
+
+    pub fn is_empty(&self) -> bool {
+        let registry = self.registry.borrow();
+        registry.callbacks.is_empty() && registry.streams.iter().all(|stream| stream.is_closed())
+    }
+
+    /// Queues the emitted events until `release`, to notify once the router is no longer borrowed
+    pub fn hold(&self) {
+        self.registry.borrow_mut().held.get_or_insert_with(Vec::new);
+    }
+
+    /// Emits the events queued since `hold`
+    pub fn release(&self) {
+        let held = self.registry.borrow_mut().held.take();
+        self.emit(&held.unwrap_or_default());
+    }
+
+    pub fn emit(&self, events: &[ChangeEvent]) {
+        if events.is_empty() {
+            return;
+        }
+        // released before the calls, a callback may subscribe or unsubscribe
+        let callbacks: Vec<Callback> = {
+            let mut registry = self.registry.borrow_mut();
+            if let Some(held) = registry.held.as_mut() {
+                held.extend_from_slice(events);
+                return;
+            }
+            registry.streams.retain(|stream| events.iter().all(|event| stream.unbounded_send(event.clone()).is_ok()));
+            registry.callbacks.iter().map(|(_, callback)| callback.clone()).collect()
+        };
+        for event in events {
+            for callback in &callbacks {
+                callback(event);
+            }
+        }
+    }
+}
+
+/// Mutable borrow of the router holding its change events until dropped, so the subscribers
+/// can borrow the router again. Use it for every fetch through a `RefCell`.
+pub struct RouterMut<'a> {
+    // only taken on drop
+    router: Option<RefMut<'a, Router>>,
+    subscriptions: Subscriptions,
+}
+
+impl<'a> RouterMut<'a> {
+    pub fn try_borrow(router: &'a RefCell<Router>) -> Result<Self, BorrowMutError> {
+        let router = router.try_borrow_mut()?;
+        router.subscriptions.hold();
+        let subscriptions = router.subscriptions.clone();
+        Ok(Self { router: Some(router), subscriptions })
+    }
+}
+
+impl Deref for RouterMut<'_> {
+    type Target = Router;
+
+    fn deref(&self) -> &Router {
+        self.router.as_deref().unwrap()
+    }
+}
+
+impl DerefMut for RouterMut<'_> {
+    fn deref_mut(&mut self) -> &mut Router {
+        self.router.as_deref_mut().unwrap()
+    }
+}
+
+impl Drop for RouterMut<'_> {
+    fn drop(&mut self) {
+        self.router.take();
+        self.subscriptions.release();
+    }
+}
+
+impl Router {
+    fn vault_values(&self) -> VaultValues {
+        VaultValues {
+            total_aum: self.vault.state.total_aum,
+            plp_supply: self.vault.state.plp_supply,
+            usdp_supply: self.vault.state.usdp_supply,
+            price_plp_buy: self.price_plp_buy,
+            price_plp_sell: self.price_plp_sell,
+        }
+    }
+
+    /// Values to diff after a fetch, None without subscribers
+    pub fn change_baseline(&self) -> Option<ChangeBaseline> {
+        if self.subscriptions.is_empty() {
+            return None;
+        }
+        Some(ChangeBaseline { tokens: self.config.tokens.clone(), vault: self.vault_values() })
+    }
+
+    /// Emits the changes since `before` to the subscribers
+    pub fn emit_changes(&self, before: Option<ChangeBaseline>) {
+        let before = match before {
+            Some(before) => before,
+            None => return,
+        };
+        let mut events = diff_tokens(&before.tokens, &self.config.tokens);
+        let vault = self.vault_values();
+        if vault != before.vault {
+            events.push(ChangeEvent::VaultStateChanged { old: before.vault, new: vault });
+        }
+        self.subscriptions.emit(&events);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+
+    use super::*;
+    use crate::RouterTrait;
+
+    const USDT: &str = "0x542e4676238562b518b968a1d03626d544a7bca2";
+    const ACCOUNT: &str = "0x1f9840a85d5af5bf1d1762f925bdaddc4201f984";
+
+    fn create_token() -> Token {
+        let mut token = Token::new(97, USDT, "Tether USD", "USDT", 18, "");
+        token.min_price = Some(Price::new(Decimal::ONE));
+        token.pool_amounts = Some(U256::from(100));
+        token.update_balance(&ACCOUNT.to_string(), U256::exp10(18));
+        token
+    }
+
+    #[test]
+    fn diff_tokens_works() {
+        let old = create_token();
+        assert!(diff_tokens(std::slice::from_ref(&old), std::slice::from_ref(&old)).is_empty());
+
+        let mut new = old.clone();
+        new.min_price = Some(Price::new(Decimal::TWO));
+        new.update_balance(&ACCOUNT.to_string(), U256::exp10(18) * 2);
+        new.update_allowance(&ACCOUNT.to_string(), U256::from(5), &ACCOUNT.to_string());
+        let events = diff_tokens(std::slice::from_ref(&old), &[new]);
+        assert_eq!(events.len(), 3);
+        assert!(matches!(&events[0], ChangeEvent::PriceChanged { new, .. } if new.min_price == Some(Price::new(Decimal::TWO))));
+        assert!(matches!(&events[1], ChangeEvent::BalanceChanged { old: Some(old), new: Some(new), .. } if *old == Decimal::ONE && *new == Decimal::TWO));
+        assert!(matches!(&events[2], ChangeEvent::AllowanceChanged { old: None, new: Some(new), .. } if *new == U256::from(5)));
+    }
+
+    #[test]
+    fn router_emits_to_subscribers() {
+        let mut router = Router::new();
+        router.config.tokens = vec![create_token()];
+        assert!(router.change_baseline().is_none());
+
+        let received = Shared::new(vec![]);
+        let callback_received = received.clone();
+        let id = router.subscriptions.subscribe(move |event| callback_received.borrow_mut().push(event.clone()));
+        let mut stream = router.subscriptions.stream();
+
+        let before = router.change_baseline();
+        router.config.tokens[0].pool_amounts = Some(U256::from(150));
+        router.price_plp_buy = U256::one();
+        router.emit_changes(before);
+
+        assert_eq!(received.borrow().len(), 2);
+        assert!(matches!(received.borrow()[0], ChangeEvent::PoolAmountChanged { .. }));
+        assert!(matches!(received.borrow()[1], ChangeEvent::VaultStateChanged { .. }));
+        let event = futures::executor::block_on(stream.next()).unwrap();
+        assert_eq!(event, received.borrow()[0]);
+
+        // held until released
+        router.subscriptions.hold();
+        let before = router.change_baseline();
+        router.price_plp_buy = U256::from(2);
+        router.emit_changes(before);
+        assert_eq!(received.borrow().len(), 2);
+        router.subscriptions.release();
+        assert_eq!(received.borrow().len(), 3);
+
+        assert!(router.subscriptions.unsubscribe(id));
+        drop(stream);
+        assert!(router.change_baseline().is_none());
+    }
+
+    // callbacks must be Send with the `send` feature
+    #[cfg(not(feature = "send"))]
+    #[test]
+    fn router_mut_notifies_once_released() {
+        let router = Rc::new(RefCell::new(Router::new()));
+        let (callback_router, reads) = (Rc::downgrade(&router), Rc::new(RefCell::new(0)));
+        let callback_reads = reads.clone();
+        router.borrow().subscriptions.subscribe(move |_| {
+            // getters work from the callback
+            assert!(callback_router.upgrade().unwrap().try_borrow().is_ok());
+            *callback_reads.borrow_mut() += 1;
+        });
+        {
+            let mut router = RouterMut::try_borrow(&router).unwrap();
+            let before = router.change_baseline();
+            router.price_plp_buy = U256::one();
+            router.emit_changes(before);
+            assert_eq!(*reads.borrow(), 0);
+        }
+        assert_eq!(*reads.borrow(), 1);
+    }
+}
@@ -0,0 +1,184 @@
+use ethers::providers::Middleware;
+use ethers::types::transaction::eip2718::TypedTransaction;
+use ethers::types::{BlockNumber, Eip1559TransactionRequest, FeeHistory, U256};
+use serde::{Deserialize, Serialize};
+
+use crate::contracts::simulator::token_to_usd;
+use crate::contracts::token::Token;
+use super::UnsignedTransaction;
+
+// blocks of fee history and the reward percentiles of slow, normal and fast
+const FEE_HISTORY_BLOCKS: u64 = 10;
+const REWARD_PERCENTILES: [f64; 3] = [10.0, 50.0, 90.0];
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum GasSpeed {
+    Slow,
+    Normal,
+    Fast,
+}
+
+/// EIP-1559 fees for one speed, wei
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct FeeSuggestion {
+    pub speed: GasSpeed,
+    pub max_fee_per_gas: U256,
+    pub max_priority_fee_per_gas: U256,
+    // base fee + tip, what the transaction is expected to pay per gas
+    pub gas_price: U256,
+    // the fees were lowered to the vault maxGasPrice
+    pub is_clamped: bool,
+    // gas_limit * gas_price, native token
+    pub network_fee: U256,
+    // USD, 30 decimals
+    pub network_fee_usd: U256,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct GasEstimate {
+    pub gas_limit: U256,
+    pub next_base_fee: U256,
+    pub suggestions: Vec<FeeSuggestion>,
+}
+
+impl GasEstimate {
+    pub fn suggestion(&self, speed: GasSpeed) -> Option<&FeeSuggestion> {
+        self.suggestions.iter().find(|suggestion| suggestion.speed == speed)
+    }
+}
+
+/// Slow/normal/fast fees from `eth_feeHistory` with REWARD_PERCENTILES.
+/// The tip is the median reward of the blocks at the speed percentile, the max fee allows the base fee to double.
+/// A non zero `max_gas_price` (Vault.maxGasPrice) caps both fees, the vault reverts above it.
+pub fn suggest_fees(history: &FeeHistory, max_gas_price: U256) -> Vec<FeeSuggestion> {
+    // the history returns one more base fee, the one of the next block
+    let next_base_fee = history.base_fee_per_gas.last().copied().unwrap_or_default();
+    [GasSpeed::Slow, GasSpeed::Normal, GasSpeed::Fast]
+        .iter()
+        .enumerate()
+        .map(|(index, speed)| {
+            let mut rewards: Vec<U256> = history.reward.iter().filter_map(|block| block.get(index).copied()).collect();
+            rewards.sort();
+            let tip = rewards.get(rewards.len() / 2).copied().unwrap_or_default();
+            let mut max_fee_per_gas = next_base_fee * 2 + tip;
+            let mut max_priority_fee_per_gas = tip;
+            let is_clamped = !max_gas_price.is_zero() && max_fee_per_gas > max_gas_price;
+            if is_clamped {
+                max_fee_per_gas = max_gas_price;
+                max_priority_fee_per_gas = max_priority_fee_per_gas.min(max_gas_price);
+            }
+            FeeSuggestion {
+                speed: *speed,
+                max_fee_per_gas,
+                max_priority_fee_per_gas,
+                gas_price: (next_base_fee + max_priority_fee_per_gas).min(max_fee_per_gas),
+                is_clamped,
+                network_fee: U256::zero(),
+                network_fee_usd: U256::zero(),
+            }
+        })
+        .collect()
+}
+
+/// Network fee of every suggestion for `gas_limit`, in native token and in USD with the native token max price
+pub fn with_network_fees(gas_limit: U256, next_base_fee: U256, suggestions: Vec<FeeSuggestion>, native_token: &Token) -> GasEstimate {
+    let price = native_token.max_price.as_ref().map(|price| price.raw).unwrap_or_default();
+    let suggestions = suggestions
+        .into_iter()
+        .map(|suggestion| {
+            let network_fee = gas_limit * suggestion.gas_price;
+            FeeSuggestion {
+                network_fee,
+                network_fee_usd: token_to_usd(network_fee, price, native_token.decimals),
+                ..suggestion
+            }
+        })
+        .collect();
+    GasEstimate { gas_limit, next_base_fee, suggestions }
+}
+
+/// eth_estimateGas of a built transaction
+pub async fn estimate_gas<M: Middleware + 'static>(provider: &M, tx: &UnsignedTransaction) -> anyhow::Result<U256> {
+    let request: TypedTransaction = Eip1559TransactionRequest::new()
+        .from(tx.from)
+        .to(tx.to)
+        .data(tx.data.clone())
+        .value(tx.value)
+        .into();
+    Ok(provider.estimate_gas(&request, None).await?)
+}
+
+/// Gas limit and slow/normal/fast fees of `tx`, capped by `max_gas_price` (zero for no cap)
+pub async fn estimate<M: Middleware + 'static>(provider: &M, tx: &UnsignedTransaction, max_gas_price: U256, native_token: &Token) -> anyhow::Result<GasEstimate> {
+    let gas_limit = estimate_gas(provider, tx).await?;
+    let history = provider.fee_history(FEE_HISTORY_BLOCKS, BlockNumber::Latest, &REWARD_PERCENTILES).await?;
+    let next_base_fee = history.base_fee_per_gas.last().copied().unwrap_or_default();
+    Ok(with_network_fees(gas_limit, next_base_fee, suggest_fees(&history, max_gas_price), native_token))
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::providers::Provider;
+    use ethers::types::{Address, Bytes};
+    use rust_decimal::Decimal;
+
+    use super::*;
+    use crate::contracts::token::Price;
+
+    fn gwei(value: u64) -> U256 {
+        U256::from(value) * U256::exp10(9)
+    }
+
+    fn create_history() -> FeeHistory {
+        FeeHistory {
+            base_fee_per_gas: vec![gwei(8), gwei(9), gwei(10)],
+            gas_used_ratio: vec![0.5, 0.6],
+            oldest_block: U256::from(100),
+            reward: vec![vec![gwei(1), gwei(2), gwei(5)], vec![gwei(1), gwei(3), gwei(4)]],
+        }
+    }
+
+    fn create_native_token() -> Token {
+        let mut token = Token::new(97, "0x0000000000000000000000000000000000000001", "BNB", "BNB", 18, "");
+        token.is_native_token = Some(true);
+        token.max_price = Some(Price::new(Decimal::from(200)));
+        token
+    }
+
+    #[test]
+    fn suggest_fees_works() {
+        let suggestions = suggest_fees(&create_history(), U256::zero());
+        assert_eq!(suggestions.len(), 3);
+        // median of two blocks is the higher one
+        assert_eq!(suggestions[1].max_priority_fee_per_gas, gwei(3));
+        assert_eq!(suggestions[1].max_fee_per_gas, gwei(23));
+        assert_eq!(suggestions[1].gas_price, gwei(13));
+        assert_eq!(suggestions[2].max_priority_fee_per_gas, gwei(5));
+        assert!(suggestions.iter().all(|suggestion| !suggestion.is_clamped));
+    }
+
+    #[test]
+    fn suggest_fees_clamps_to_max_gas_price() {
+        let suggestions = suggest_fees(&create_history(), gwei(12));
+        assert_eq!(suggestions[0].max_fee_per_gas, gwei(12));
+        assert_eq!(suggestions[0].gas_price, gwei(11));
+        assert_eq!(suggestions[2].gas_price, gwei(12));
+        assert!(suggestions.iter().all(|suggestion| suggestion.is_clamped));
+    }
+
+    #[tokio::test]
+    async fn estimate_works() {
+        let (provider, mock) = Provider::mocked();
+        // popped from the back: estimateGas then feeHistory
+        mock.push(create_history()).unwrap();
+        mock.push(U256::from(100_000)).unwrap();
+        let tx = UnsignedTransaction::new(Address::repeat_byte(1), (Address::repeat_byte(2), Bytes::default()));
+        let estimate = estimate(&provider, &tx, U256::zero(), &create_native_token()).await.unwrap();
+        assert_eq!(estimate.gas_limit, U256::from(100_000));
+        assert_eq!(estimate.next_base_fee, gwei(10));
+        let normal = estimate.suggestion(GasSpeed::Normal).unwrap();
+        // 100k gas * 13 gwei = 0.0013 BNB = 0.26 USD
+        assert_eq!(normal.network_fee, U256::from(13) * U256::exp10(14));
+        assert_eq!(normal.network_fee_usd, U256::from(26) * U256::exp10(28));
+    }
+}
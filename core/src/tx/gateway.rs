@@ -0,0 +1,421 @@
+use ethabi::Contract;
+use ethers::types::{Address, Bytes, U256};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{Chain, Config};
+use crate::contracts::multicall::ChainMulticallTrait;
+use crate::contracts::quote::{Quote, QuoteKind};
+use crate::contracts::token::Token;
+use crate::contracts::vault_logic::BASIS_POINTS_DIVISOR;
+use super::{parse_account, UnsignedTransaction};
+
+pub(crate) const GATEWAY_ABI: &str = include_str!("../../abi/futurx_gateway.json");
+
+/// Open or increase a position, pays `amount_in` of `token_in`, swapped to `collateral_token` when they differ
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct IncreasePositionRequest {
+    pub token_in: String,
+    pub collateral_token: String,
+    pub index_token: String,
+    pub amount_in: U256,
+    // min collateral out of the token_in swap, zero without swap
+    pub min_out: U256,
+    // USD, 30 decimals
+    pub size_delta: U256,
+    pub is_long: bool,
+    pub slippage_basis_points: u64,
+    // keeper fee in native token, see `fetch_min_execution_fee`
+    pub execution_fee: U256,
+}
+
+/// Close or decrease a position, `collateral_delta` and `size_delta` in USD 30 decimals.
+/// The collateral is received as `token_out`, swapped when it differs from `collateral_token`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DecreasePositionRequest {
+    pub collateral_token: String,
+    pub index_token: String,
+    pub token_out: String,
+    pub collateral_delta: U256,
+    pub size_delta: U256,
+    pub is_long: bool,
+    pub min_out: U256,
+    pub slippage_basis_points: u64,
+    pub execution_fee: U256,
+}
+
+/// Deposit or withdraw collateral of an open position without changing its size. `token` is
+/// paid in or received, swapped from or to `collateral_token` when they differ, `amount` is in `token` units.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CollateralRequest {
+    pub token: String,
+    pub collateral_token: String,
+    pub index_token: String,
+    pub amount: U256,
+    pub is_long: bool,
+    pub execution_fee: U256,
+}
+
+/// Builds futurx gateway transactions for the selected account of `config`
+pub struct GatewayTx<'a> {
+    config: &'a Config,
+}
+
+impl<'a> GatewayTx<'a> {
+    pub fn new(config: &'a Config) -> Self {
+        Self { config }
+    }
+
+    fn build(&self, fn_name: &str, params: &[ethabi::Token]) -> anyhow::Result<UnsignedTransaction> {
+        let from = parse_account(self.config.selected_account.as_ref())?;
+        let to: Address = self.config.contract_address.futurx_gateway.parse()?;
+        let contract = Contract::load(GATEWAY_ABI.as_bytes())?;
+        let data: Bytes = contract.function(fn_name)?.encode_input(params)?.into();
+        Ok(UnsignedTransaction::new(from, (to, data)))
+    }
+
+    fn token(&self, address: &str) -> anyhow::Result<&Token> {
+        self.config
+            .tokens
+            .iter()
+            .find(|token| token.address.eq_ignore_ascii_case(address))
+            .ok_or_else(|| anyhow::anyhow!("unknown token {}", address))
+    }
+
+    /// Swap the amount of a `quote_swap` quote, out is bounded by `slippage_basis_points`
+    pub fn swap(&self, quote: &Quote, slippage_basis_points: u64) -> anyhow::Result<UnsignedTransaction> {
+        if quote.kind != QuoteKind::Swap {
+            anyhow::bail!("expected a Swap quote, got {:?}", quote.kind);
+        }
+        if !quote.is_available {
+            anyhow::bail!("quote unavailable: {}", quote.unavailable_reason.clone().unwrap_or_default());
+        }
+        let receiver = parse_account(self.config.selected_account.as_ref())?;
+        let token_in = self.token(&quote.token_in)?;
+        let token_out = self.token(&quote.token_out)?;
+        let path = to_path(&[&token_in.address, &token_out.address])?;
+        let min_out = ethabi::Token::Uint(quote.min_amount_out(slippage_basis_points));
+
+        if is_native(token_in) {
+            return Ok(self
+                .build("swapETHToTokens", &[path, min_out, ethabi::Token::Address(receiver)])?
+                .with_value(quote.amount_in));
+        }
+        let fn_name = if is_native(token_out) { "swapTokensToETH" } else { "swap" };
+        self.build(fn_name, &[path, ethabi::Token::Uint(quote.amount_in), min_out, ethabi::Token::Address(receiver)])
+    }
+
+    /// createIncreasePosition, the acceptable price is the index price moved against the trader by the slippage
+    pub fn increase_position(&self, request: &IncreasePositionRequest) -> anyhow::Result<UnsignedTransaction> {
+        let token_in = self.token(&request.token_in)?;
+        let index_token = self.token(&request.index_token)?;
+        let path = if token_in.address.eq_ignore_ascii_case(&request.collateral_token) {
+            to_path(&[&token_in.address])?
+        } else {
+            to_path(&[&token_in.address, &request.collateral_token])?
+        };
+        let price = acceptable_price(index_token, request.is_long, true, request.slippage_basis_points)?;
+        let tail = [
+            ethabi::Token::Uint(request.min_out),
+            ethabi::Token::Uint(request.size_delta),
+            ethabi::Token::Bool(request.is_long),
+            ethabi::Token::Uint(price),
+            ethabi::Token::Uint(request.execution_fee),
+            // no referral code, no callback
+            ethabi::Token::FixedBytes(vec![0u8; 32]),
+            ethabi::Token::Address(Address::zero()),
+        ];
+        let index_token = ethabi::Token::Address(index_token.address.parse()?);
+
+        if is_native(token_in) {
+            let params: Vec<_> = [path, index_token].into_iter().chain(tail).collect();
+            return Ok(self
+                .build("createIncreasePositionETH", &params)?
+                .with_value(request.amount_in + request.execution_fee));
+        }
+        let params: Vec<_> = [path, index_token, ethabi::Token::Uint(request.amount_in)].into_iter().chain(tail).collect();
+        Ok(self.build("createIncreasePosition", &params)?.with_value(request.execution_fee))
+    }
+
+    /// createDecreasePosition to the selected account, native `token_out` is withdrawn as ETH
+    pub fn decrease_position(&self, request: &DecreasePositionRequest) -> anyhow::Result<UnsignedTransaction> {
+        let receiver = parse_account(self.config.selected_account.as_ref())?;
+        let index_token = self.token(&request.index_token)?;
+        let token_out = self.token(&request.token_out)?;
+        let path = if token_out.address.eq_ignore_ascii_case(&request.collateral_token) {
+            to_path(&[&request.collateral_token])?
+        } else {
+            to_path(&[&request.collateral_token, &token_out.address])?
+        };
+        let price = acceptable_price(index_token, request.is_long, false, request.slippage_basis_points)?;
+        let params = [
+            path,
+            ethabi::Token::Address(index_token.address.parse()?),
+            ethabi::Token::Uint(request.collateral_delta),
+            ethabi::Token::Uint(request.size_delta),
+            ethabi::Token::Bool(request.is_long),
+            ethabi::Token::Address(receiver),
+            ethabi::Token::Uint(price),
+            ethabi::Token::Uint(request.min_out),
+            ethabi::Token::Uint(request.execution_fee),
+            ethabi::Token::Bool(is_native(token_out)),
+            ethabi::Token::Address(Address::zero()),
+        ];
+        Ok(self.build("createDecreasePosition", &params)?.with_value(request.execution_fee))
+    }
+
+    /// Gateway.addCollateral, a native `token` is sent along with the execution fee
+    pub fn add_collateral(&self, request: &CollateralRequest) -> anyhow::Result<UnsignedTransaction> {
+        let token_in = self.token(&request.token)?;
+        let path = if token_in.address.eq_ignore_ascii_case(&request.collateral_token) {
+            to_path(&[&token_in.address])?
+        } else {
+            to_path(&[&token_in.address, &request.collateral_token])?
+        };
+        let tx = self.build("addCollateral", &self.collateral_params(path, request)?)?;
+        let value = if is_native(token_in) { request.amount + request.execution_fee } else { request.execution_fee };
+        Ok(tx.with_value(value))
+    }
+
+    /// Gateway.removeCollateral, `amount` of collateral token swapped to `token` when they differ
+    pub fn remove_collateral(&self, request: &CollateralRequest) -> anyhow::Result<UnsignedTransaction> {
+        let token_out = self.token(&request.token)?;
+        let path = if token_out.address.eq_ignore_ascii_case(&request.collateral_token) {
+            to_path(&[&request.collateral_token])?
+        } else {
+            to_path(&[&request.collateral_token, &token_out.address])?
+        };
+        Ok(self.build("removeCollateral", &self.collateral_params(path, request)?)?.with_value(request.execution_fee))
+    }
+
+    fn collateral_params(&self, path: ethabi::Token, request: &CollateralRequest) -> anyhow::Result<[ethabi::Token; 4]> {
+        Ok([
+            path,
+            ethabi::Token::Address(self.token(&request.index_token)?.address.parse()?),
+            ethabi::Token::Bool(request.is_long),
+            ethabi::Token::Uint(request.amount),
+        ])
+    }
+}
+
+/// Worst index price accepted by the keeper: above the max price when buying (long increase,
+/// short decrease), below the min price when selling.
+pub fn acceptable_price(index_token: &Token, is_long: bool, is_increase: bool, slippage_basis_points: u64) -> anyhow::Result<U256> {
+    let slippage = U256::from(slippage_basis_points).min(*BASIS_POINTS_DIVISOR);
+    let price = if is_long == is_increase {
+        let max_price = index_token.max_price.ok_or_else(|| anyhow::anyhow!("no price for {}", index_token.symbol))?;
+        max_price.raw * (*BASIS_POINTS_DIVISOR + slippage) / *BASIS_POINTS_DIVISOR
+    } else {
+        let min_price = index_token.min_price.ok_or_else(|| anyhow::anyhow!("no price for {}", index_token.symbol))?;
+        min_price.raw * (*BASIS_POINTS_DIVISOR - slippage) / *BASIS_POINTS_DIVISOR
+    };
+    Ok(price)
+}
+
+/// Gateway.minExecutionFee(), the lowest fee the keepers accept
+pub async fn fetch_min_execution_fee(chain: &Chain, gateway: &str) -> anyhow::Result<U256> {
+    let contract = Contract::load(GATEWAY_ABI.as_bytes())?;
+    let call = (gateway.parse()?, contract.function("minExecutionFee")?.encode_input(&[])?.into());
+    let results = chain
+        .execute_multicall(vec![call], GATEWAY_ABI.to_string(), "minExecutionFee")
+        .await
+        .map_err(|e| anyhow::anyhow!(e))?;
+    results
+        .first()
+        .and_then(|result| result.first())
+        .and_then(|value| value.clone().into_uint())
+        .ok_or_else(|| anyhow::anyhow!("Failed to parse minExecutionFee"))
+}
+
+fn is_native(token: &Token) -> bool {
+    token.is_native_token == Some(true)
+}
+
+fn to_path(addresses: &[&String]) -> anyhow::Result<ethabi::Token> {
+    let path = addresses
+        .iter()
+        .map(|address| Ok(ethabi::Token::Address(address.parse()?)))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    Ok(ethabi::Token::Array(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use super::*;
+    use crate::config::load_config;
+    use crate::contracts::token::Price;
+    use crate::contracts::vault::VaultState;
+
+    const ACCOUNT: &str = "0x1f9840a85d5af5bf1d1762f925bdaddc4201f984";
+    const USDT: &str = "0x542e4676238562b518b968a1d03626d544a7bca2";
+    const BTC: &str = "0xc4900937c3222ca28cd4b300eb2575ee0868540f";
+    const BNB: &str = "0x0000000000000000000000000000000000000001";
+
+    fn create_token(address: &str, symbol: &str, price: u32, is_native_token: bool) -> Token {
+        let mut token = Token::new(97, address, symbol, symbol, 18, "");
+        token.is_tradeable = Some(true);
+        token.is_stable_token = Some(false);
+        token.is_native_token = Some(is_native_token);
+        token.token_weight = Some(30);
+        token.max_usdp_amount = Some(U256::zero());
+        token.update_vault_info(U256::exp10(21), U256::zero(), U256::exp10(21) / price, U256::zero());
+        let price = Price::new(Decimal::from(price));
+        token.min_price = Some(price);
+        token.max_price = Some(price);
+        token.ask_price = Some(price);
+        token.bid_price = Some(price);
+        token
+    }
+
+    fn create_config() -> Config {
+        let mut config = load_config(97).unwrap();
+        config.set_selected_account(ACCOUNT.to_string());
+        config.tokens = vec![
+            create_token(USDT, "USDT", 1, false),
+            create_token(BTC, "BTC", 20000, false),
+            create_token(BNB, "BNB", 200, true),
+        ];
+        config
+    }
+
+    fn decode(fn_name: &str, tx: &UnsignedTransaction) -> Vec<ethabi::Token> {
+        let contract = Contract::load(GATEWAY_ABI.as_bytes()).unwrap();
+        contract.function(fn_name).unwrap().decode_input(&tx.data[4..]).unwrap()
+    }
+
+    fn create_swap_quote(config: &Config, token_in: usize, token_out: usize) -> Quote {
+        let state = VaultState {
+            usdp_supply: U256::exp10(21) * 3,
+            total_token_weights: U256::from(90),
+            swap_fee_basis_points: U256::from(30),
+            is_swap_enabled: true,
+            ..VaultState::default()
+        };
+        state.quote_swap(&config.tokens[token_in], &config.tokens[token_out], U256::exp10(18))
+    }
+
+    #[test]
+    fn swap_works() {
+        let config = create_config();
+        let quote = create_swap_quote(&config, 0, 1);
+        let tx = GatewayTx::new(&config).swap(&quote, 50).unwrap();
+        assert_eq!(tx.to, config.contract_address.futurx_gateway.parse::<Address>().unwrap());
+        assert!(tx.value.is_zero());
+        let params = decode("swap", &tx);
+        assert_eq!(params[0], ethabi::Token::Array(vec![ethabi::Token::Address(USDT.parse().unwrap()), ethabi::Token::Address(BTC.parse().unwrap())]));
+        assert_eq!(params[2], ethabi::Token::Uint(quote.min_amount_out(50)));
+        assert_eq!(params[3], ethabi::Token::Address(ACCOUNT.parse().unwrap()));
+
+        let quote = create_swap_quote(&config, 2, 0);
+        let tx = GatewayTx::new(&config).swap(&quote, 50).unwrap();
+        assert_eq!(tx.value, U256::exp10(18));
+        assert_eq!(decode("swapETHToTokens", &tx).len(), 3);
+
+        let quote = create_swap_quote(&config, 0, 2);
+        let tx = GatewayTx::new(&config).swap(&quote, 50).unwrap();
+        assert_eq!(decode("swapTokensToETH", &tx)[1], ethabi::Token::Uint(U256::exp10(18)));
+    }
+
+    #[test]
+    fn increase_position_works() {
+        let config = create_config();
+        let request = IncreasePositionRequest {
+            token_in: USDT.to_string(),
+            collateral_token: BTC.to_string(),
+            index_token: BTC.to_string(),
+            amount_in: U256::exp10(20),
+            min_out: U256::from(1),
+            size_delta: U256::exp10(33),
+            is_long: true,
+            slippage_basis_points: 30,
+            execution_fee: U256::exp10(15),
+        };
+        let tx = GatewayTx::new(&config).increase_position(&request).unwrap();
+        assert_eq!(tx.value, U256::exp10(15));
+        let params = decode("createIncreasePosition", &tx);
+        assert_eq!(params[0], ethabi::Token::Array(vec![ethabi::Token::Address(USDT.parse().unwrap()), ethabi::Token::Address(BTC.parse().unwrap())]));
+        assert_eq!(params[2], ethabi::Token::Uint(U256::exp10(20)));
+        // 20000 * 1.003
+        assert_eq!(params[6], ethabi::Token::Uint(U256::from(20060) * U256::exp10(30)));
+        assert_eq!(params[7], ethabi::Token::Uint(U256::exp10(15)));
+
+        let tx = GatewayTx::new(&config).increase_position(&IncreasePositionRequest { token_in: BNB.to_string(), ..request }).unwrap();
+        assert_eq!(tx.value, U256::exp10(20) + U256::exp10(15));
+        let params = decode("createIncreasePositionETH", &tx);
+        assert_eq!(params[3], ethabi::Token::Uint(U256::exp10(33)));
+    }
+
+    #[test]
+    fn decrease_position_works() {
+        let config = create_config();
+        let request = DecreasePositionRequest {
+            collateral_token: USDT.to_string(),
+            index_token: BTC.to_string(),
+            token_out: USDT.to_string(),
+            collateral_delta: U256::exp10(31),
+            size_delta: U256::exp10(33),
+            is_long: false,
+            min_out: U256::zero(),
+            slippage_basis_points: 30,
+            execution_fee: U256::exp10(15),
+        };
+        let tx = GatewayTx::new(&config).decrease_position(&request).unwrap();
+        assert_eq!(tx.value, U256::exp10(15));
+        let params = decode("createDecreasePosition", &tx);
+        assert_eq!(params[0], ethabi::Token::Array(vec![ethabi::Token::Address(USDT.parse().unwrap())]));
+        // closing a short buys the index token back
+        assert_eq!(params[6], ethabi::Token::Uint(U256::from(20060) * U256::exp10(30)));
+        assert_eq!(params[9], ethabi::Token::Bool(false));
+
+        let tx = GatewayTx::new(&config).decrease_position(&DecreasePositionRequest { token_out: BNB.to_string(), ..request }).unwrap();
+        let params = decode("createDecreasePosition", &tx);
+        assert_eq!(params[0], ethabi::Token::Array(vec![ethabi::Token::Address(USDT.parse().unwrap()), ethabi::Token::Address(BNB.parse().unwrap())]));
+        assert_eq!(params[9], ethabi::Token::Bool(true));
+    }
+
+    #[test]
+    fn collateral_requests_work() {
+        let config = create_config();
+        let request = CollateralRequest {
+            token: BNB.to_string(),
+            collateral_token: USDT.to_string(),
+            index_token: BTC.to_string(),
+            amount: U256::exp10(18),
+            is_long: false,
+            execution_fee: U256::exp10(15),
+        };
+        let tx = GatewayTx::new(&config).add_collateral(&request).unwrap();
+        assert_eq!(tx.to, config.contract_address.futurx_gateway.parse::<Address>().unwrap());
+        assert_eq!(tx.value, U256::exp10(18) + U256::exp10(15));
+        let params = decode("addCollateral", &tx);
+        assert_eq!(params[0], ethabi::Token::Array(vec![ethabi::Token::Address(BNB.parse().unwrap()), ethabi::Token::Address(USDT.parse().unwrap())]));
+        assert_eq!(params[1], ethabi::Token::Address(BTC.parse().unwrap()));
+        assert_eq!(params[2], ethabi::Token::Bool(false));
+        assert_eq!(params[3], ethabi::Token::Uint(U256::exp10(18)));
+
+        let tx = GatewayTx::new(&config).remove_collateral(&CollateralRequest { token: USDT.to_string(), ..request }).unwrap();
+        assert_eq!(tx.value, U256::exp10(15));
+        let params = decode("removeCollateral", &tx);
+        assert_eq!(params[0], ethabi::Token::Array(vec![ethabi::Token::Address(USDT.parse().unwrap())]));
+        assert_eq!(params[3], ethabi::Token::Uint(U256::exp10(18)));
+    }
+
+    #[test]
+    fn gateway_abi_has_max_global_sizes() {
+        let contract = Contract::load(GATEWAY_ABI.as_bytes()).unwrap();
+        for name in ["maxGlobalLongSizes", "maxGlobalShortSizes"] {
+            let function = contract.function(name).unwrap();
+            assert_eq!(function.signature(), format!("{}(address):(uint256)", name));
+        }
+    }
+
+    #[test]
+    fn acceptable_price_works() {
+        let token = create_token(BTC, "BTC", 20000, false);
+        assert_eq!(acceptable_price(&token, true, true, 100).unwrap(), U256::from(20200) * U256::exp10(30));
+        assert_eq!(acceptable_price(&token, true, false, 100).unwrap(), U256::from(19800) * U256::exp10(30));
+        assert_eq!(acceptable_price(&token, false, true, 100).unwrap(), U256::from(19800) * U256::exp10(30));
+        assert!(acceptable_price(&Token::new(97, BTC, "BTC", "BTC", 18, ""), true, true, 100).is_err());
+    }
+}
@@ -0,0 +1,50 @@
+pub mod reward_router;
+pub mod gateway;
+pub mod gas;
+pub mod simulate;
+#[cfg(feature = "signer")]
+pub mod signer;
+
+use ethers::providers::{Http, Provider};
+use ethers::types::{Address, Bytes, U256};
+use serde::{Deserialize, Serialize};
+
+use crate::config::Chain;
+use crate::contracts::multicall::ChainMulticallTrait;
+
+/// Transaction ready to be signed by the wallet of `from`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct UnsignedTransaction {
+    pub from: Address,
+    pub to: Address,
+    pub data: Bytes,
+    // native token sent with the call, wei
+    pub value: U256,
+}
+
+impl UnsignedTransaction {
+    pub fn new(from: Address, (to, data): (Address, Bytes)) -> Self {
+        Self { from, to, data, value: U256::zero() }
+    }
+
+    pub fn with_value(mut self, value: U256) -> Self {
+        self.value = value;
+        self
+    }
+}
+
+pub(crate) fn parse_account(account: Option<&String>) -> anyhow::Result<Address> {
+    match account {
+        Some(account) => Ok(account.parse()?),
+        None => anyhow::bail!("no account selected"),
+    }
+}
+
+/// Provider on one of the chain rpc urls
+pub fn http_provider(chain: &Chain) -> anyhow::Result<Provider<Http>> {
+    let rpc_url = chain
+        .rpc_urls
+        .get(chain.random_rpc())
+        .ok_or_else(|| anyhow::anyhow!("no rpc url for chain {}", chain.chain_id))?;
+    Ok(Provider::<Http>::try_from(rpc_url.as_str())?)
+}
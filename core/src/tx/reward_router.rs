@@ -0,0 +1,148 @@
+use ethabi::Contract;
+use ethers::types::{Address, Bytes, U256};
+
+use crate::config::Config;
+use crate::contracts::quote::{Quote, QuoteKind};
+use crate::contracts::token::Token;
+use super::{parse_account, UnsignedTransaction};
+
+const REWARD_ROUTER_ABI: &str = include_str!("../../abi/reward_router.json");
+
+/// Builds RewardRouter transactions for the selected account of `config`
+pub struct RewardRouterTx<'a> {
+    config: &'a Config,
+}
+
+impl<'a> RewardRouterTx<'a> {
+    pub fn new(config: &'a Config) -> Self {
+        Self { config }
+    }
+
+    fn build(&self, fn_name: &str, params: &[ethabi::Token]) -> anyhow::Result<UnsignedTransaction> {
+        let from = parse_account(self.config.selected_account.as_ref())?;
+        let to: Address = self.config.contract_address.reward_router.parse()?;
+        let contract = Contract::load(REWARD_ROUTER_ABI.as_bytes())?;
+        let data: Bytes = contract.function(fn_name)?.encode_input(params)?.into();
+        Ok(UnsignedTransaction::new(from, (to, data)))
+    }
+
+    /// mintAndStakePlp with the amount of a `quote_buy_plp` quote, PLP out is bounded by `slippage_basis_points`.
+    /// Native tokens use mintAndStakePlpETH and send the amount as value.
+    pub fn mint_and_stake_plp(&self, token: &Token, quote: &Quote, slippage_basis_points: u64) -> anyhow::Result<UnsignedTransaction> {
+        check_quote(quote, QuoteKind::BuyPlp)?;
+        let min_plp = ethabi::Token::Uint(quote.min_amount_out(slippage_basis_points));
+        // like the web app, the PLP minimum is the only bound
+        let min_usdp = ethabi::Token::Uint(U256::zero());
+        if token.is_native_token == Some(true) {
+            return Ok(self.build("mintAndStakePlpETH", &[min_usdp, min_plp])?.with_value(quote.amount_in));
+        }
+        self.build("mintAndStakePlp", &[
+            ethabi::Token::Address(token.address.parse()?),
+            ethabi::Token::Uint(quote.amount_in),
+            min_usdp,
+            min_plp,
+        ])
+    }
+
+    /// unstakeAndRedeemPlp of the PLP amount of a `quote_sell_plp` quote to the selected account.
+    /// Native tokens use unstakeAndRedeemPlpETH.
+    pub fn unstake_and_redeem_plp(&self, token: &Token, quote: &Quote, slippage_basis_points: u64) -> anyhow::Result<UnsignedTransaction> {
+        check_quote(quote, QuoteKind::SellPlp)?;
+        let receiver = ethabi::Token::Address(parse_account(self.config.selected_account.as_ref())?);
+        let plp_amount = ethabi::Token::Uint(quote.amount_in);
+        let min_out = ethabi::Token::Uint(quote.min_amount_out(slippage_basis_points));
+        if token.is_native_token == Some(true) {
+            return self.build("unstakeAndRedeemPlpETH", &[plp_amount, min_out, receiver]);
+        }
+        self.build("unstakeAndRedeemPlp", &[ethabi::Token::Address(token.address.parse()?), plp_amount, min_out, receiver])
+    }
+
+    /// Claim the rewards of every tracker
+    pub fn claim(&self) -> anyhow::Result<UnsignedTransaction> {
+        self.build("claim", &[])
+    }
+
+    /// Restake the claimable rewards
+    pub fn compound(&self) -> anyhow::Result<UnsignedTransaction> {
+        self.build("compound", &[])
+    }
+}
+
+fn check_quote(quote: &Quote, kind: QuoteKind) -> anyhow::Result<()> {
+    if quote.kind != kind {
+        anyhow::bail!("expected a {:?} quote, got {:?}", kind, quote.kind);
+    }
+    if !quote.is_available {
+        anyhow::bail!("quote unavailable: {}", quote.unavailable_reason.clone().unwrap_or_default());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::utils::hex;
+
+    use super::*;
+    use crate::config::load_config;
+    use crate::contracts::fixtures::{create_state, create_token, USDT};
+
+    const ACCOUNT: &str = "0x1f9840a85d5af5bf1d1762f925bdaddc4201f984";
+
+    fn create_config() -> Config {
+        let mut config = load_config(97).unwrap();
+        config.set_selected_account(ACCOUNT.to_string());
+        config
+    }
+
+    #[test]
+    fn mint_and_stake_plp_works() {
+        let config = create_config();
+        let token = create_token(USDT, "USDT", 500);
+        let quote = create_state().quote_buy_plp(&token, U256::exp10(18));
+        let tx = RewardRouterTx::new(&config).mint_and_stake_plp(&token, &quote, 50).unwrap();
+        assert_eq!(tx.from, ACCOUNT.parse::<Address>().unwrap());
+        assert_eq!(tx.to, config.contract_address.reward_router.parse::<Address>().unwrap());
+        assert!(tx.value.is_zero());
+
+        let contract = Contract::load(REWARD_ROUTER_ABI.as_bytes()).unwrap();
+        let params = contract.function("mintAndStakePlp").unwrap().decode_input(&tx.data[4..]).unwrap();
+        assert_eq!(params[0], ethabi::Token::Address(token.address.parse().unwrap()));
+        assert_eq!(params[1], ethabi::Token::Uint(U256::exp10(18)));
+        assert_eq!(params[3], ethabi::Token::Uint(quote.min_amount_out(50)));
+
+        let mut native = token.clone();
+        native.is_native_token = Some(true);
+        let tx = RewardRouterTx::new(&config).mint_and_stake_plp(&native, &quote, 50).unwrap();
+        assert_eq!(tx.value, U256::exp10(18));
+        let params = contract.function("mintAndStakePlpETH").unwrap().decode_input(&tx.data[4..]).unwrap();
+        assert_eq!(params[1], ethabi::Token::Uint(quote.min_amount_out(50)));
+    }
+
+    #[test]
+    fn unstake_and_redeem_plp_works() {
+        let config = create_config();
+        let token = create_token(USDT, "USDT", 500);
+        let quote = create_state().quote_sell_plp(&token, U256::exp10(18));
+        let tx = RewardRouterTx::new(&config).unstake_and_redeem_plp(&token, &quote, 100).unwrap();
+        let contract = Contract::load(REWARD_ROUTER_ABI.as_bytes()).unwrap();
+        let params = contract.function("unstakeAndRedeemPlp").unwrap().decode_input(&tx.data[4..]).unwrap();
+        assert_eq!(params[1], ethabi::Token::Uint(U256::exp10(18)));
+        assert_eq!(params[2], ethabi::Token::Uint(quote.min_amount_out(100)));
+        assert_eq!(params[3], ethabi::Token::Address(ACCOUNT.parse().unwrap()));
+
+        // a buy quote can't be redeemed
+        let buy_quote = create_state().quote_buy_plp(&token, U256::exp10(18));
+        assert!(RewardRouterTx::new(&config).unstake_and_redeem_plp(&token, &buy_quote, 100).is_err());
+    }
+
+    #[test]
+    fn claim_and_compound_work() {
+        let config = create_config();
+        assert_eq!(hex::encode(RewardRouterTx::new(&config).claim().unwrap().data), "4e71d92d");
+        assert_eq!(hex::encode(RewardRouterTx::new(&config).compound().unwrap().data), "f69e2046");
+
+        let mut config = create_config();
+        config.selected_account = None;
+        assert!(RewardRouterTx::new(&config).claim().is_err());
+    }
+}
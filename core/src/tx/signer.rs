@@ -0,0 +1,236 @@
+use ethers::middleware::{NonceManagerMiddleware, SignerMiddleware};
+use ethers::providers::{Http, Middleware, Provider};
+use ethers::signers::coins_bip39::English;
+use ethers::signers::{LocalWallet, MnemonicBuilder, Signer};
+use ethers::types::transaction::eip2718::TypedTransaction;
+use ethers::types::{Address, Bytes, Eip1559TransactionRequest, TransactionReceipt, TransactionRequest, TxHash};
+
+use crate::config::Chain;
+use super::{http_provider, UnsignedTransaction};
+
+/// Signs with the wallet, nonces are counted locally so concurrent sends don't collide
+pub type SignerClient<M> = NonceManagerMiddleware<SignerMiddleware<M, LocalWallet>>;
+
+/// Signs and sends the transactions of the tx builders with a local key.
+/// Gas and EIP-1559 fees are estimated by the node, chains without EIP-1559 (eg: BSC) fall back to a legacy gas price.
+pub struct TxSender<M> {
+    client: SignerClient<M>,
+    confirmations: usize,
+}
+
+impl TxSender<Provider<Http>> {
+    pub fn from_private_key(chain: &Chain, private_key: &str) -> anyhow::Result<Self> {
+        let wallet: LocalWallet = private_key.trim_start_matches("0x").parse()?;
+        Ok(Self::new(http_provider(chain)?, wallet.with_chain_id(chain.chain_id)))
+    }
+
+    /// Wallet at m/44'/60'/0'/0/{index} of the mnemonic
+    pub fn from_mnemonic(chain: &Chain, phrase: &str, index: u32) -> anyhow::Result<Self> {
+        let wallet = MnemonicBuilder::<English>::default().phrase(phrase).index(index)?.build()?;
+        Ok(Self::new(http_provider(chain)?, wallet.with_chain_id(chain.chain_id)))
+    }
+}
+
+impl<M: Middleware + 'static> TxSender<M> {
+    /// `wallet` must carry the chain id of `provider`, see `Signer::with_chain_id`
+    pub fn new(provider: M, wallet: LocalWallet) -> Self {
+        let address = wallet.address();
+        Self {
+            client: NonceManagerMiddleware::new(SignerMiddleware::new(provider, wallet), address),
+            confirmations: 1,
+        }
+    }
+
+    /// Blocks to wait for in `send_and_wait`, 1 by default
+    pub fn with_confirmations(mut self, confirmations: usize) -> Self {
+        self.confirmations = confirmations;
+        self
+    }
+
+    pub fn address(&self) -> Address {
+        self.client.inner().address()
+    }
+
+    // EIP-1559 fees from the fee history, a legacy gas price when the node has none (no base fee on BSC)
+    async fn to_request(&self, tx: &UnsignedTransaction) -> anyhow::Result<TypedTransaction> {
+        if tx.from != self.address() {
+            anyhow::bail!("transaction from {:?} can't be signed by {:?}", tx.from, self.address());
+        }
+        match self.client.estimate_eip1559_fees(None).await {
+            Ok((max_fee_per_gas, max_priority_fee_per_gas)) => Ok(Eip1559TransactionRequest::new()
+                .from(tx.from)
+                .to(tx.to)
+                .data(tx.data.clone())
+                .value(tx.value)
+                .max_fee_per_gas(max_fee_per_gas)
+                .max_priority_fee_per_gas(max_priority_fee_per_gas)
+                .into()),
+            Err(_) => Ok(TransactionRequest::new()
+                .from(tx.from)
+                .to(tx.to)
+                .data(tx.data.clone())
+                .value(tx.value)
+                .gas_price(self.client.get_gas_price().await?)
+                .into()),
+        }
+    }
+
+    /// Fill nonce, gas and fees then sign without sending, the nonce is taken from the local counter
+    pub async fn sign(&self, tx: &UnsignedTransaction) -> anyhow::Result<(TypedTransaction, Bytes)> {
+        let mut request = self.to_request(tx).await?;
+        self.client.fill_transaction(&mut request, None).await?;
+        let signature = self.client.inner().signer().sign_transaction(&request).await?;
+        let raw = request.rlp_signed(&signature);
+        Ok((request, raw))
+    }
+
+    /// Broadcast without waiting, a nonce mismatch is resynced with the node and retried once
+    pub async fn send(&self, tx: &UnsignedTransaction) -> anyhow::Result<TxHash> {
+        let pending = self.client.send_transaction(self.to_request(tx).await?, None).await?;
+        Ok(pending.tx_hash())
+    }
+
+    /// Broadcast and wait for the receipt, fails if the transaction is dropped or reverts
+    pub async fn send_and_wait(&self, tx: &UnsignedTransaction) -> anyhow::Result<TransactionReceipt> {
+        let pending = self.client.send_transaction(self.to_request(tx).await?, None).await?;
+        let tx_hash = pending.tx_hash();
+        let receipt = pending
+            .confirmations(self.confirmations)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("transaction {:?} dropped from the mempool", tx_hash))?;
+        if receipt.status.is_some_and(|status| status.is_zero()) {
+            anyhow::bail!("transaction {:?} reverted", tx_hash);
+        }
+        Ok(receipt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use ethers::providers::MockProvider;
+    use ethers::types::{Block, FeeHistory, Transaction, U256, U64};
+
+    use super::*;
+
+    // first anvil account
+    const PRIVATE_KEY: &str = "0xac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80";
+    const ACCOUNT: &str = "0xf39fd6e51aad88f6f4ce6ab8827279cfffb92266";
+    const MNEMONIC: &str = "test test test test test test test test test test test junk";
+
+    fn create_sender() -> (TxSender<Provider<MockProvider>>, MockProvider) {
+        let (provider, mock) = Provider::mocked();
+        let wallet: LocalWallet = PRIVATE_KEY.trim_start_matches("0x").parse().unwrap();
+        let provider = provider.interval(Duration::from_millis(1));
+        (TxSender::new(provider, wallet.with_chain_id(1u64)), mock)
+    }
+
+    fn create_tx() -> UnsignedTransaction {
+        UnsignedTransaction::new(
+            ACCOUNT.parse().unwrap(),
+            ("0x1f9840a85d5af5bf1d1762f925bdaddc4201f984".parse().unwrap(), Bytes::from(vec![0x4e, 0x71, 0xd9, 0x2d])),
+        )
+    }
+
+    // mock responses are popped from the back, push them in reverse call order
+    fn push_eip1559_fees(mock: &MockProvider) {
+        mock.push(FeeHistory {
+            base_fee_per_gas: vec![U256::exp10(9)],
+            gas_used_ratio: vec![0.5],
+            oldest_block: U256::from(1),
+            reward: vec![vec![U256::exp10(9)]],
+        }).unwrap();
+        mock.push(Block::<TxHash> {
+            base_fee_per_gas: Some(U256::exp10(9)),
+            ..Block::default()
+        }).unwrap();
+    }
+
+    // a latest block without base fee, as on BSC, then eth_gasPrice
+    fn push_legacy_fees(mock: &MockProvider, gas_price: U256) {
+        mock.push(gas_price).unwrap();
+        mock.push(Block::<TxHash>::default()).unwrap();
+    }
+
+    #[test]
+    fn wallets_works() {
+        let chain = Chain { chain_id: 1, rpc_urls: vec!["http://localhost:8545".to_string()], multicall_address: "".to_string() };
+        let sender = TxSender::from_private_key(&chain, PRIVATE_KEY).unwrap();
+        assert_eq!(sender.address(), ACCOUNT.parse::<Address>().unwrap());
+        let sender = TxSender::from_mnemonic(&chain, MNEMONIC, 0).unwrap();
+        assert_eq!(sender.address(), ACCOUNT.parse::<Address>().unwrap());
+        assert!(TxSender::from_private_key(&chain, "0x1234").is_err());
+    }
+
+    #[tokio::test]
+    async fn sign_works() {
+        let (sender, mock) = create_sender();
+        mock.push(U256::from(21000)).unwrap();
+        push_eip1559_fees(&mock);
+        // the nonce is only read once
+        mock.push(U256::from(50000)).unwrap();
+        mock.push(U256::from(5)).unwrap();
+        push_eip1559_fees(&mock);
+
+        let (request, raw) = sender.sign(&create_tx()).await.unwrap();
+        assert_eq!(request.nonce(), Some(&U256::from(5)));
+        assert_eq!(request.gas(), Some(&U256::from(50000)));
+        match &request {
+            TypedTransaction::Eip1559(inner) => assert!(inner.max_fee_per_gas >= inner.max_priority_fee_per_gas),
+            _ => panic!("expected an EIP-1559 transaction"),
+        }
+        let (decoded, signature) = TypedTransaction::decode_signed(&ethers::utils::rlp::Rlp::new(&raw)).unwrap();
+        assert_eq!(signature.recover(decoded.sighash()).unwrap(), sender.address());
+
+        let (request, _) = sender.sign(&create_tx()).await.unwrap();
+        assert_eq!(request.nonce(), Some(&U256::from(6)));
+
+        // transactions of another account are refused
+        let mut tx = create_tx();
+        tx.from = Address::zero();
+        assert!(sender.sign(&tx).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn sign_falls_back_to_legacy_gas_price() {
+        let (sender, mock) = create_sender();
+        let gas_price = U256::exp10(9) * 5;
+        mock.push(U256::from(21000)).unwrap();
+        mock.push(U256::zero()).unwrap();
+        push_legacy_fees(&mock, gas_price);
+
+        let (request, raw) = sender.sign(&create_tx()).await.unwrap();
+        match &request {
+            TypedTransaction::Legacy(inner) => assert_eq!(inner.gas_price, Some(gas_price)),
+            _ => panic!("expected a legacy transaction"),
+        }
+        assert_eq!(request.gas(), Some(&U256::from(21000)));
+        let (decoded, signature) = TypedTransaction::decode_signed(&ethers::utils::rlp::Rlp::new(&raw)).unwrap();
+        assert_eq!(signature.recover(decoded.sighash()).unwrap(), sender.address());
+    }
+
+    #[tokio::test]
+    async fn send_and_wait_works() {
+        let (sender, mock) = create_sender();
+        let tx_hash = TxHash::repeat_byte(1);
+        mock.push(TransactionReceipt {
+            transaction_hash: tx_hash,
+            block_number: Some(U64::from(10)),
+            status: Some(U64::from(1)),
+            ..TransactionReceipt::default()
+        }).unwrap();
+        mock.push(Transaction {
+            hash: tx_hash,
+            block_number: Some(U64::from(10)),
+            ..Transaction::default()
+        }).unwrap();
+        mock.push(tx_hash).unwrap();
+        mock.push(U256::from(21000)).unwrap();
+        mock.push(U256::zero()).unwrap();
+        push_eip1559_fees(&mock);
+
+        let receipt = sender.send_and_wait(&create_tx()).await.unwrap();
+        assert_eq!(receipt.transaction_hash, tx_hash);
+    }
+}
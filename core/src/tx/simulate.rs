@@ -0,0 +1,157 @@
+use std::fmt;
+
+use ethabi::ParamType;
+use ethers::providers::{Middleware, MiddlewareError};
+use ethers::types::transaction::eip2718::TypedTransaction;
+use ethers::types::{BlockNumber, Bytes, Eip1559TransactionRequest};
+use serde::{Deserialize, Serialize};
+
+use super::UnsignedTransaction;
+
+// Error(string) and Panic(uint256)
+const ERROR_SELECTOR: [u8; 4] = [0x08, 0xc3, 0x79, 0xa0];
+const PANIC_SELECTOR: [u8; 4] = [0x4e, 0x48, 0x7b, 0x71];
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum RevertReason {
+    // require(cond, "Vault: max USDP exceeded")
+    Error(String),
+    // assert, overflow, division by zero ...
+    Panic { code: u64, description: String },
+    // empty revert or unknown selector, the vault, gateway and reward router only revert with strings
+    Unknown(Bytes),
+}
+
+impl fmt::Display for RevertReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RevertReason::Error(reason) => write!(f, "{}", reason),
+            RevertReason::Panic { code, description } => write!(f, "panic 0x{:02x}: {}", code, description),
+            RevertReason::Unknown(data) if data.is_empty() => write!(f, "reverted without reason"),
+            RevertReason::Unknown(data) => write!(f, "reverted with unknown data {}", data),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "status")]
+pub enum SimulationResult {
+    Success { return_data: Bytes },
+    Reverted { reason: RevertReason, message: String },
+}
+
+impl SimulationResult {
+    pub fn is_success(&self) -> bool {
+        matches!(self, SimulationResult::Success { .. })
+    }
+}
+
+/// Human readable reason of the revert data of a call
+pub fn decode_revert(data: &[u8]) -> RevertReason {
+    if data.len() < 4 {
+        return RevertReason::Unknown(data.to_vec().into());
+    }
+    let (selector, params) = data.split_at(4);
+    if selector == ERROR_SELECTOR {
+        if let Some(reason) = ethabi::decode(&[ParamType::String], params).ok().and_then(|tokens| tokens[0].clone().into_string()) {
+            return RevertReason::Error(reason);
+        }
+    }
+    if selector == PANIC_SELECTOR {
+        if let Some(code) = ethabi::decode(&[ParamType::Uint(256)], params).ok().and_then(|tokens| tokens[0].clone().into_uint()) {
+            let code = code.low_u64();
+            return RevertReason::Panic { code, description: panic_description(code).to_string() };
+        }
+    }
+    RevertReason::Unknown(data.to_vec().into())
+}
+
+fn panic_description(code: u64) -> &'static str {
+    match code {
+        0x01 => "assertion failed",
+        0x11 => "arithmetic overflow or underflow",
+        0x12 => "division or modulo by zero",
+        0x21 => "invalid enum value",
+        0x22 => "invalid storage byte array",
+        0x31 => "pop on an empty array",
+        0x32 => "array index out of bounds",
+        0x41 => "out of memory",
+        0x51 => "call to a zero internal function",
+        _ => "unknown panic",
+    }
+}
+
+/// eth_call of `tx` from its sender at the latest block. A revert is a `Reverted` result,
+/// transport failures are errors.
+pub async fn simulate<M: Middleware + 'static>(provider: &M, tx: &UnsignedTransaction) -> anyhow::Result<SimulationResult> {
+    let request: TypedTransaction = Eip1559TransactionRequest::new()
+        .from(tx.from)
+        .to(tx.to)
+        .data(tx.data.clone())
+        .value(tx.value)
+        .into();
+    match provider.call(&request, Some(BlockNumber::Latest.into())).await {
+        Ok(return_data) => Ok(SimulationResult::Success { return_data }),
+        Err(e) => match e.as_error_response().and_then(|error| error.as_revert_data().map(|data| (error, data))) {
+            Some((error, data)) => Ok(SimulationResult::Reverted {
+                reason: decode_revert(&data),
+                message: error.message.clone(),
+            }),
+            None => Err(e.into()),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::providers::{JsonRpcError, MockResponse, Provider};
+    use ethers::types::{Address, U256};
+
+    use super::*;
+
+    fn encode_error(reason: &str) -> Vec<u8> {
+        [ERROR_SELECTOR.to_vec(), ethabi::encode(&[ethabi::Token::String(reason.to_string())])].concat()
+    }
+
+    fn create_tx() -> UnsignedTransaction {
+        UnsignedTransaction::new(Address::repeat_byte(1), (Address::repeat_byte(2), Bytes::default()))
+    }
+
+    #[test]
+    fn decode_revert_works() {
+        assert_eq!(decode_revert(&encode_error("Vault: max USDP exceeded")), RevertReason::Error("Vault: max USDP exceeded".to_string()));
+
+        let panic = [PANIC_SELECTOR.to_vec(), ethabi::encode(&[ethabi::Token::Uint(U256::from(0x11))])].concat();
+        let reason = decode_revert(&panic);
+        assert_eq!(reason, RevertReason::Panic { code: 0x11, description: "arithmetic overflow or underflow".to_string() });
+        assert_eq!(reason.to_string(), "panic 0x11: arithmetic overflow or underflow");
+
+        assert_eq!(decode_revert(&[]).to_string(), "reverted without reason");
+        assert!(matches!(decode_revert(&[0xde, 0xad, 0xbe, 0xef]), RevertReason::Unknown(_)));
+    }
+
+    #[tokio::test]
+    async fn simulate_works() {
+        let (provider, mock) = Provider::mocked();
+        mock.push_response(MockResponse::Error(JsonRpcError {
+            code: 3,
+            message: "execution reverted: Vault: poolAmount exceeded".to_string(),
+            data: Some(serde_json::Value::String(Bytes::from(encode_error("Vault: poolAmount exceeded")).to_string())),
+        }));
+        mock.push::<Bytes, _>(Bytes::from(vec![1u8])).unwrap();
+
+        let result = simulate(&provider, &create_tx()).await.unwrap();
+        assert_eq!(result, SimulationResult::Success { return_data: Bytes::from(vec![1u8]) });
+
+        let result = simulate(&provider, &create_tx()).await.unwrap();
+        assert!(!result.is_success());
+        match result {
+            SimulationResult::Reverted { reason, .. } => assert_eq!(reason.to_string(), "Vault: poolAmount exceeded"),
+            _ => panic!("expected a revert"),
+        }
+
+        // not a revert, eg: rate limited
+        mock.push_response(MockResponse::Error(JsonRpcError { code: -32005, message: "limit exceeded".to_string(), data: None }));
+        assert!(simulate(&provider, &create_tx()).await.is_err());
+    }
+}
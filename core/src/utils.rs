@@ -33,3 +33,21 @@ pub fn get_vault_variable_selector(vault_address: &str, variable_name: &str) ->
     let fn_selector_raw = _get_function_selector(&format!("{}()", variable_name));
     (address, Bytes::from(fn_selector_raw))
 }
+
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen::prelude::wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(js_namespace = Date, js_name = now)]
+    fn date_now() -> f64;
+}
+
+/// Wall clock in unix milliseconds, SystemTime panics in the browser
+#[cfg(target_arch = "wasm32")]
+pub fn now_millis() -> u64 {
+    date_now() as u64
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub fn now_millis() -> u64 {
+    std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|elapsed| elapsed.as_millis() as u64).unwrap_or_default()
+}
@@ -6,7 +6,7 @@ use ethabi::ethereum_types::U256;
 use rust_decimal::prelude::Decimal;
 use serde::{Deserialize, Serialize};
 use serde_wasm_bindgen::{from_value, to_value};
-use std::cell::RefCell;
+use std::cell::{BorrowMutError, RefCell};
 use std::ops::Deref;
 use std::panic;
 use std::rc::Rc;
@@ -17,7 +17,14 @@ use wasm_bindgen::__rt::IntoJsResult;
 use wasm_bindgen::prelude::*;
 use wasm_logger::*;
 
-use core::contracts::vault_logic;
+use core::contracts::{account_history, composition, events, fee_curve, history_export, optimizer, staking, vault_logic, vester};
+use core::tx::gateway::{self, GatewayTx};
+use core::tx::{self, gas, simulate};
+use core::tx::reward_router::RewardRouterTx;
+use core::scheduler::{RefreshIntervals, RefreshScheduler};
+use core::subscription::RouterMut;
+use core::persistence::{self, RouterSnapshot};
+use core::fetch_plan::{FetchComponent, FetchPlan};
 // use core::contracts::Va;
 use ethaddr::Address;
 use std::collections::HashMap;
@@ -35,6 +42,52 @@ pub struct SwapDetails {
 pub struct WasmRouter {
     router: Rc<RefCell<Router>>,
     lock: Arc<Mutex<u64>>,
+    scheduler: RefreshScheduler,
+}
+
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(js_name = setTimeout)]
+    fn set_timeout(handler: &js_sys::Function, timeout: i32) -> JsValue;
+}
+
+/// JS object with `load(key) => string | null` and `save(key, value)`, both may return a promise.
+/// eg: `{load: (key) => localStorage.getItem(key), save: (key, value) => localStorage.setItem(key, value)}` or an IndexedDB wrapper
+struct JsSnapshotStorage {
+    storage: JsValue,
+}
+
+impl JsSnapshotStorage {
+    async fn call(&self, name: &str, args: &js_sys::Array) -> anyhow::Result<JsValue> {
+        let js_error = |e: JsValue| anyhow::anyhow!("snapshot storage {}: {:?}", name, e);
+        let function: js_sys::Function = js_sys::Reflect::get(&self.storage, &JsValue::from_str(name))
+            .map_err(js_error)?
+            .dyn_into()
+            .map_err(|_| anyhow::anyhow!("snapshot storage has no {} function", name))?;
+        let result = function.apply(&self.storage, args).map_err(js_error)?;
+        match result.dyn_into::<js_sys::Promise>() {
+            Ok(promise) => wasm_bindgen_futures::JsFuture::from(promise).await.map_err(js_error),
+            Err(result) => Ok(result),
+        }
+    }
+
+    // same as SnapshotStorage, which can't be implemented here: async_trait expands to paths of std `core`
+    async fn load(&self, key: &str) -> anyhow::Result<Option<String>> {
+        Ok(self.call("load", &js_sys::Array::of1(&JsValue::from_str(key))).await?.as_string())
+    }
+
+    async fn save(&self, key: &str, value: &str) -> anyhow::Result<()> {
+        self.call("save", &js_sys::Array::of2(&JsValue::from_str(key), &JsValue::from_str(value))).await?;
+        Ok(())
+    }
+}
+
+// setTimeout as a future, the timer of the auto refresh loop
+async fn sleep(duration: std::time::Duration) {
+    let promise = js_sys::Promise::new(&mut |resolve, _| {
+        set_timeout(&resolve, duration.as_millis() as i32);
+    });
+    let _ = wasm_bindgen_futures::JsFuture::from(promise).await;
 }
 
 // Note: this is the example to custom export struct to js
@@ -85,9 +138,15 @@ impl WasmRouter {
         WasmRouter {
             router: Rc::new(RefCell::new(router)),
             lock: Arc::new(Mutex::new(0)),
+            scheduler: RefreshScheduler::default(),
         }
     }
 
+    // borrow of the entry points that fetch, the subscribers are called once it's dropped
+    fn router_mut(&self) -> Result<RouterMut<'_>, BorrowMutError> {
+        RouterMut::try_borrow(&self.router)
+    }
+
     #[wasm_bindgen]
     pub fn load_config(&self, chain_id: u64) -> Result<JsValue, JsValue> {
         log::info!("start load_config {:?}", chain_id);
@@ -136,7 +195,7 @@ impl WasmRouter {
 
     #[wasm_bindgen]
     pub async fn fetch_balance(&self) {
-        match self.router.try_borrow_mut() {
+        match self.router_mut() {
             Ok(mut router) => {
                 let mut lock = self.lock.lock().await;
                 log::info!("lock done");
@@ -157,7 +216,7 @@ impl WasmRouter {
 
     #[wasm_bindgen]
     pub async fn fetch_vault(&self) {
-        match self.router.try_borrow_mut() {
+        match self.router_mut() {
             Ok(mut router) => {
                 let mut lock = self.lock.lock().await;
                 log::info!("lock done");
@@ -176,10 +235,259 @@ impl WasmRouter {
 
     }
 
+    #[wasm_bindgen]
+    pub async fn fetch_staking(&self) -> Result<(), JsValue> {
+        let mut router = self.router_mut().map_err(|e| JsValue::from_str(&e.to_string()))?;
+        let mut lock = self.lock.lock().await;
+        router.fetch_staking().await.map_err(|e| JsValue::from_str(&e.to_string()))?;
+        *lock += 1;
+        Ok(())
+    }
+
+    /// Fetches the given components in one multicall, eg: ["Prices", "UserBalances"].
+    /// The components they depend on are fetched along.
+    #[wasm_bindgen]
+    pub async fn execute_fetch_plan(&self, components: JsValue) -> Result<(), JsValue> {
+        let components: Vec<FetchComponent> = from_value(components).map_err(|e| JsValue::from_str(&e.to_string()))?;
+        let mut router = self.router_mut().map_err(|e| JsValue::from_str(&e.to_string()))?;
+        let mut lock = self.lock.lock().await;
+        router.execute_plan(&FetchPlan::new(&components)).await.map_err(|e| JsValue::from_str(&e.to_string()))?;
+        *lock += 1;
+        Ok(())
+    }
+
+    /// Open positions of the selected account, fetched with the "Positions" component
+    /// @return PositionInfo[]
+    #[wasm_bindgen]
+    pub fn get_positions(&self) -> Result<JsValue, JsValue> {
+        let router = self.router.try_borrow().map_err(|e| JsValue::from_str(&e.to_string()))?;
+        let positions = match router.config.selected_account.as_ref().and_then(|account| account.parse().ok()) {
+            Some(account) => router.vault.state.get_positions(&account),
+            None => vec![],
+        };
+        Ok(to_value(&positions).unwrap())
+    }
+
+    /// Accounts fetched along with the selected account in the same multicall, replaces the previous list
+    /// @param accounts string[]
+    #[wasm_bindgen]
+    pub fn watch_accounts(&self, accounts: JsValue) -> Result<(), JsValue> {
+        let accounts: Vec<String> = from_value(accounts).map_err(|e| JsValue::from_str(&e.to_string()))?;
+        let accounts = accounts
+            .iter()
+            .map(|account| account.parse().map_err(|_| JsValue::from_str(&format!("invalid account {}", account))))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut router = self.router.try_borrow_mut().map_err(|e| JsValue::from_str(&e.to_string()))?;
+        router.watch_accounts(accounts);
+        Ok(())
+    }
+
+    /// Balances, staking and open positions summed over the selected and watched accounts
+    /// @return AccountTotals
+    #[wasm_bindgen]
+    pub fn get_account_totals(&self) -> Result<JsValue, JsValue> {
+        let router = self.router.try_borrow().map_err(|e| JsValue::from_str(&e.to_string()))?;
+        let totals = router.account_totals().map_err(|e| JsValue::from_str(&e.to_string()))?;
+        Ok(to_value(&totals).unwrap())
+    }
+
+    /// USD net worth of `account` with a per asset breakdown, from the last fetched data
+    /// @return Portfolio
+    #[wasm_bindgen]
+    pub fn get_portfolio(&self, account: &str) -> Result<JsValue, JsValue> {
+        let account: ethers::types::Address = account.parse().map_err(|_| JsValue::from_str(&format!("invalid account {}", account)))?;
+        let router = self.router.try_borrow().map_err(|e| JsValue::from_str(&e.to_string()))?;
+        Ok(to_value(&router.portfolio(&account)).unwrap())
+    }
+
+    /// Reward trackers and the staking positions of the selected account
+    /// @return {reward_trackers: RewardTrackerInfo[], staking_info: StakingInfo[]}
+    #[wasm_bindgen]
+    pub fn get_staking_info(&self) -> Result<JsValue, JsValue> {
+        let router = self.router.try_borrow().map_err(|e| JsValue::from_str(&e.to_string()))?;
+        let staking_info = match router.config.selected_account.as_ref().and_then(|account| account.parse().ok()) {
+            Some(account) => router.vault.state.get_staking_info(&account),
+            None => vec![],
+        };
+        Ok(to_value(&StakingResult {
+            reward_trackers: router.vault.state.reward_trackers.clone(),
+            staking_info,
+        }).unwrap())
+    }
+
+    /// APR per reward tracker with the pool and selected account rewards per day
+    /// @return StakingApr
+    #[wasm_bindgen]
+    pub fn get_staking_apr(&self) -> Result<JsValue, JsValue> {
+        let router = self.router.try_borrow().map_err(|e| JsValue::from_str(&e.to_string()))?;
+        let account = router.config.selected_account.as_ref().and_then(|account| account.parse().ok());
+        let apr = router.vault.state.staking_apr(&router.config.tokens, account.as_ref());
+        Ok(to_value(&apr).unwrap())
+    }
+
+    #[wasm_bindgen]
+    pub async fn fetch_vesting(&self) -> Result<(), JsValue> {
+        let mut router = self.router_mut().map_err(|e| JsValue::from_str(&e.to_string()))?;
+        let mut lock = self.lock.lock().await;
+        router.fetch_vesting().await.map_err(|e| JsValue::from_str(&e.to_string()))?;
+        *lock += 1;
+        Ok(())
+    }
+
+    /// Vesting position of the selected account and its remaining schedule
+    /// @return {info: VestingInfo, schedule: VestingSchedule} | null
+    #[wasm_bindgen]
+    pub fn get_vesting_info(&self) -> Result<JsValue, JsValue> {
+        let router = self.router.try_borrow().map_err(|e| JsValue::from_str(&e.to_string()))?;
+        let vesting = router
+            .config
+            .selected_account
+            .as_ref()
+            .and_then(|account| account.parse().ok())
+            .and_then(|account| router.vault.state.get_vesting_info(&account))
+            .map(|info| {
+                let schedule = info.schedule((instant::now() / 1000.0) as u64);
+                VestingResult { info, schedule }
+            });
+        Ok(to_value(&vesting).unwrap())
+    }
+
+    /// Unsigned RewardRouter transaction minting and staking PLP with `amount_in` of `token`
+    /// @return {from, to, data, value}
+    #[wasm_bindgen]
+    pub fn build_mint_and_stake_plp(&self, token: &str, amount_in: &str, slippage_basis_points: u64) -> Result<JsValue, JsValue> {
+        let router = self.router.try_borrow().map_err(|e| JsValue::from_str(&e.to_string()))?;
+        let amount_in = U256::from_dec_str(amount_in).map_err(|e| JsValue::from_str(&e.to_string()))?;
+        let token = router.config.get_token_by_token_address(token.to_lowercase()).ok_or_else(|| JsValue::from_str("unknown token"))?;
+        let quote = router.vault.state.quote_buy_plp(&token, amount_in);
+        let tx = RewardRouterTx::new(&router.config)
+            .mint_and_stake_plp(&token, &quote, slippage_basis_points)
+            .map_err(|e| JsValue::from_str(&e.to_string()))?;
+        Ok(to_value(&tx).unwrap())
+    }
+
+    /// Unsigned RewardRouter transaction redeeming `plp_amount` of staked PLP for `token`
+    /// @return {from, to, data, value}
+    #[wasm_bindgen]
+    pub fn build_unstake_and_redeem_plp(&self, token: &str, plp_amount: &str, slippage_basis_points: u64) -> Result<JsValue, JsValue> {
+        let router = self.router.try_borrow().map_err(|e| JsValue::from_str(&e.to_string()))?;
+        let plp_amount = U256::from_dec_str(plp_amount).map_err(|e| JsValue::from_str(&e.to_string()))?;
+        let token = router.config.get_token_by_token_address(token.to_lowercase()).ok_or_else(|| JsValue::from_str("unknown token"))?;
+        let quote = router.vault.state.quote_sell_plp(&token, plp_amount);
+        let tx = RewardRouterTx::new(&router.config)
+            .unstake_and_redeem_plp(&token, &quote, slippage_basis_points)
+            .map_err(|e| JsValue::from_str(&e.to_string()))?;
+        Ok(to_value(&tx).unwrap())
+    }
+
+    /// Unsigned RewardRouter claim (is_compound = false) or compound transaction
+    /// @return {from, to, data, value}
+    #[wasm_bindgen]
+    pub fn build_claim_rewards(&self, is_compound: bool) -> Result<JsValue, JsValue> {
+        let router = self.router.try_borrow().map_err(|e| JsValue::from_str(&e.to_string()))?;
+        let reward_router = RewardRouterTx::new(&router.config);
+        let tx = if is_compound { reward_router.compound() } else { reward_router.claim() };
+        Ok(to_value(&tx.map_err(|e| JsValue::from_str(&e.to_string()))?).unwrap())
+    }
+
+    /// Gateway.minExecutionFee(), the keeper fee to send with position requests
+    #[wasm_bindgen]
+    pub async fn fetch_min_execution_fee(&self) -> Result<JsValue, JsValue> {
+        let (chain, gateway) = {
+            let router = self.router.try_borrow().map_err(|e| JsValue::from_str(&e.to_string()))?;
+            (router.config.chain.clone(), router.config.contract_address.futurx_gateway.clone())
+        };
+        let fee = gateway::fetch_min_execution_fee(&chain, &gateway).await.map_err(|e| JsValue::from_str(&e.to_string()))?;
+        Ok(to_value(&fee.to_string()).unwrap())
+    }
+
+    /// Gas limit and slow/normal/fast EIP-1559 fees of a built transaction, capped by the vault maxGasPrice
+    /// @param tx {from, to, data, value} returned by the build_* functions
+    /// @return GasEstimate, network fees in native token and USD (30 decimals)
+    #[wasm_bindgen]
+    pub async fn estimate_gas(&self, tx: JsValue) -> Result<JsValue, JsValue> {
+        let tx = from_value(tx).map_err(|e| JsValue::from_str(&e.to_string()))?;
+        let (chain, max_gas_price, native_token) = {
+            let router = self.router.try_borrow().map_err(|e| JsValue::from_str(&e.to_string()))?;
+            let native_token = router.config.tokens.iter().find(|token| token.is_native_token == Some(true)).cloned();
+            (router.config.chain.clone(), router.vault.state.max_gas_price, native_token)
+        };
+        let native_token = native_token.ok_or_else(|| JsValue::from_str("no native token in config"))?;
+        let provider = tx::http_provider(&chain).map_err(|e| JsValue::from_str(&e.to_string()))?;
+        let estimate = gas::estimate(&provider, &tx, max_gas_price, &native_token)
+            .await
+            .map_err(|e| JsValue::from_str(&e.to_string()))?;
+        Ok(to_value(&estimate).unwrap())
+    }
+
+    /// eth_call of a built transaction from its sender, to block transactions that would revert
+    /// @param tx {from, to, data, value} returned by the build_* functions
+    /// @return {status: "Success", return_data} | {status: "Reverted", reason, message}
+    #[wasm_bindgen]
+    pub async fn simulate_transaction(&self, tx: JsValue) -> Result<JsValue, JsValue> {
+        let tx = from_value(tx).map_err(|e| JsValue::from_str(&e.to_string()))?;
+        let chain = {
+            let router = self.router.try_borrow().map_err(|e| JsValue::from_str(&e.to_string()))?;
+            router.config.chain.clone()
+        };
+        let provider = tx::http_provider(&chain).map_err(|e| JsValue::from_str(&e.to_string()))?;
+        let result = simulate::simulate(&provider, &tx).await.map_err(|e| JsValue::from_str(&e.to_string()))?;
+        Ok(to_value(&result).unwrap())
+    }
+
+    /// Unsigned futurx gateway swap of `amount_in` of `token_in`, native tokens are sent as value
+    /// @return {from, to, data, value}
+    #[wasm_bindgen]
+    pub fn build_swap(&self, token_in: &str, token_out: &str, amount_in: &str, slippage_basis_points: u64) -> Result<JsValue, JsValue> {
+        let router = self.router.try_borrow().map_err(|e| JsValue::from_str(&e.to_string()))?;
+        let amount_in = U256::from_dec_str(amount_in).map_err(|e| JsValue::from_str(&e.to_string()))?;
+        let token_in = router.config.get_token_by_token_address(token_in.to_lowercase()).ok_or_else(|| JsValue::from_str("unknown token"))?;
+        let token_out = router.config.get_token_by_token_address(token_out.to_lowercase()).ok_or_else(|| JsValue::from_str("unknown token"))?;
+        let quote = router.vault.state.quote_swap(&token_in, &token_out, amount_in);
+        let tx = GatewayTx::new(&router.config)
+            .swap(&quote, slippage_basis_points)
+            .map_err(|e| JsValue::from_str(&e.to_string()))?;
+        Ok(to_value(&tx).unwrap())
+    }
+
+    /// Unsigned createIncreasePosition
+    /// @param request IncreasePositionRequest, amounts as hex or decimal strings
+    /// @return {from, to, data, value}
+    #[wasm_bindgen]
+    pub fn build_increase_position(&self, request: JsValue) -> Result<JsValue, JsValue> {
+        let router = self.router.try_borrow().map_err(|e| JsValue::from_str(&e.to_string()))?;
+        let request: gateway::IncreasePositionRequest = from_value(request).map_err(|e| JsValue::from_str(&e.to_string()))?;
+        let tx = GatewayTx::new(&router.config).increase_position(&request).map_err(|e| JsValue::from_str(&e.to_string()))?;
+        Ok(to_value(&tx).unwrap())
+    }
+
+    /// Unsigned createDecreasePosition
+    /// @param request DecreasePositionRequest, amounts as hex or decimal strings
+    /// @return {from, to, data, value}
+    #[wasm_bindgen]
+    pub fn build_decrease_position(&self, request: JsValue) -> Result<JsValue, JsValue> {
+        let router = self.router.try_borrow().map_err(|e| JsValue::from_str(&e.to_string()))?;
+        let request: gateway::DecreasePositionRequest = from_value(request).map_err(|e| JsValue::from_str(&e.to_string()))?;
+        let tx = GatewayTx::new(&router.config).decrease_position(&request).map_err(|e| JsValue::from_str(&e.to_string()))?;
+        Ok(to_value(&tx).unwrap())
+    }
+
+    /// Unsigned addCollateral, or removeCollateral when `remove` is true
+    /// @param request CollateralRequest, amounts as hex or decimal strings
+    /// @return {from, to, data, value}
+    #[wasm_bindgen]
+    pub fn build_collateral(&self, request: JsValue, remove: bool) -> Result<JsValue, JsValue> {
+        let router = self.router.try_borrow().map_err(|e| JsValue::from_str(&e.to_string()))?;
+        let request: gateway::CollateralRequest = from_value(request).map_err(|e| JsValue::from_str(&e.to_string()))?;
+        let gateway_tx = GatewayTx::new(&router.config);
+        let tx = if remove { gateway_tx.remove_collateral(&request) } else { gateway_tx.add_collateral(&request) };
+        Ok(to_value(&tx.map_err(|e| JsValue::from_str(&e.to_string()))?).unwrap())
+    }
+
     #[wasm_bindgen]
     pub async fn fetch_async(&self, account: String) {
         log::info!("check set_account {}", account.clone());
-        match self.router.try_borrow_mut() {
+        match self.router_mut() {
             Ok(mut router) => {
                 if account.len() > 0 {
                     log::info!("into set account {}", account.clone());
@@ -195,6 +503,7 @@ impl WasmRouter {
                     .map_err(|e| JsValue::from_str(&e.to_string()))
                     .expect("fetch data failure");
                 log::info!("fetch data done");
+                let before = router.change_baseline();
                 router
                     .vault
                     .init_vault_state()
@@ -203,6 +512,7 @@ impl WasmRouter {
                     .expect("init vault state failure");
                 log::info!("init vault state done");
                 router.calculate_price_plp();
+                router.emit_changes(before);
                 *lock += 1;
                 log::info!("fetch async done");
             }
@@ -212,6 +522,124 @@ impl WasmRouter {
         }
     }
 
+    /// Block and time each data class was fetched, and the classes older than the freshness policy
+    /// @return {fetched_at: {[DataClass]: {block_number, timestamp}}, policy: FreshnessPolicy, stale: DataClass[]}
+    #[wasm_bindgen]
+    pub fn get_freshness(&self) -> Result<JsValue, JsValue> {
+        let router = self.router.try_borrow().map_err(|e| JsValue::from_str(&e.to_string()))?;
+        Ok(to_value(&router.vault.state.freshness()).unwrap())
+    }
+
+    /// Max age of each data class for quotes, 0 for no limit. Stale quotes are only flagged in `stale_data`
+    /// unless refuse_stale is set, then they are unavailable.
+    /// @param policy {max_age: {token_configuration_ms, vault_state_ms, vault_info_ms, prices_ms, balances_ms}, refuse_stale: boolean}
+    #[wasm_bindgen]
+    pub fn set_freshness_policy(&self, policy: JsValue) -> Result<(), JsValue> {
+        let policy = from_value(policy).map_err(|e| JsValue::from_str(&e.to_string()))?;
+        let mut router = self.router.try_borrow_mut().map_err(|e| JsValue::from_str(&e.to_string()))?;
+        router.vault.state.freshness_policy = policy;
+        Ok(())
+    }
+
+    /// Versioned JSON of the config, tokens, vault state and fetch block, to render before the first fetch
+    #[wasm_bindgen]
+    pub fn get_snapshot(&self) -> Result<String, JsValue> {
+        let router = self.router.try_borrow().map_err(|e| JsValue::from_str(&e.to_string()))?;
+        router.snapshot().to_json().map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    /// Note: Need to call load_config first, the snapshot must be of the same chain
+    #[wasm_bindgen]
+    pub fn restore_snapshot(&self, json: String) -> Result<(), JsValue> {
+        let snapshot = RouterSnapshot::from_json(&json).map_err(|e| JsValue::from_str(&e.to_string()))?;
+        let mut router = self.router_mut().map_err(|e| JsValue::from_str(&e.to_string()))?;
+        router.restore(snapshot).map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    /// @param storage {load(key), save(key, value)}, eg: a localStorage or IndexedDB wrapper
+    #[wasm_bindgen]
+    pub async fn save_snapshot(&self, storage: JsValue) -> Result<(), JsValue> {
+        let (key, json) = {
+            let router = self.router.try_borrow().map_err(|e| JsValue::from_str(&e.to_string()))?;
+            let json = router.snapshot().to_json().map_err(|e| JsValue::from_str(&e.to_string()))?;
+            (persistence::snapshot_key(router.config.chain.chain_id), json)
+        };
+        JsSnapshotStorage { storage }.save(&key, &json).await.map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
+    /// Restores the saved snapshot of the current chain, then refresh in the background
+    /// @return false when nothing was saved
+    #[wasm_bindgen]
+    pub async fn load_snapshot(&self, storage: JsValue) -> Result<bool, JsValue> {
+        let key = {
+            let router = self.router.try_borrow().map_err(|e| JsValue::from_str(&e.to_string()))?;
+            persistence::snapshot_key(router.config.chain.chain_id)
+        };
+        let json = JsSnapshotStorage { storage }.load(&key).await.map_err(|e| JsValue::from_str(&e.to_string()))?;
+        match json {
+            Some(json) => self.restore_snapshot(json).map(|_| true),
+            None => Ok(false),
+        }
+    }
+
+    /// Calls `callback` with each change found by a fetch or an auto refresh, returns the id to unsubscribe.
+    /// The callback runs once the fetch is done, it may call the router and pause or stop the auto refresh.
+    /// @param callback (event: {type: "PriceChanged" | "BalanceChanged" | "AllowanceChanged" | "PoolAmountChanged" | "VaultStateChanged", old, new, ...}) => void
+    #[wasm_bindgen]
+    pub fn subscribe(&self, callback: js_sys::Function) -> Result<u32, JsValue> {
+        let router = self.router.try_borrow().map_err(|e| JsValue::from_str(&e.to_string()))?;
+        Ok(router.subscriptions.subscribe(move |event| {
+            if let Err(e) = callback.call1(&JsValue::NULL, &to_value(event).unwrap()) {
+                log::error!("change callback error: {:?}", e);
+            }
+        }))
+    }
+
+    #[wasm_bindgen]
+    pub fn unsubscribe(&self, id: u32) -> Result<bool, JsValue> {
+        let router = self.router.try_borrow().map_err(|e| JsValue::from_str(&e.to_string()))?;
+        Ok(router.subscriptions.unsubscribe(id))
+    }
+
+    /// Refreshes token configuration, vault state, vault info, prices and balances in the background,
+    /// each on its own interval, instead of calling fetch_async on a timer. Restarts a running loop.
+    /// @param intervals {token_configuration_ms, vault_state_ms, vault_info_ms, prices_ms, balances_ms} | null for the defaults, 0 disables a class
+    #[wasm_bindgen]
+    pub fn start_auto_refresh(&self, intervals: JsValue) -> Result<(), JsValue> {
+        let intervals: RefreshIntervals = if intervals.is_null() || intervals.is_undefined() {
+            RefreshIntervals::default()
+        } else {
+            from_value(intervals).map_err(|e| JsValue::from_str(&e.to_string()))?
+        };
+        self.scheduler.set_intervals(intervals);
+        self.scheduler.resume();
+        let scheduler = self.scheduler.clone();
+        let router = Rc::clone(&self.router);
+        wasm_bindgen_futures::spawn_local(async move { scheduler.run(router, sleep).await });
+        Ok(())
+    }
+
+    #[wasm_bindgen]
+    pub fn pause_auto_refresh(&self) {
+        self.scheduler.pause();
+    }
+
+    #[wasm_bindgen]
+    pub fn resume_auto_refresh(&self) {
+        self.scheduler.resume();
+    }
+
+    #[wasm_bindgen]
+    pub fn stop_auto_refresh(&self) {
+        self.scheduler.stop();
+    }
+
+    /// @return {is_running, is_paused, intervals, errors: {[DataClass]: string}}
+    #[wasm_bindgen]
+    pub fn get_auto_refresh_status(&self) -> Result<JsValue, JsValue> {
+        Ok(to_value(&self.scheduler.status()).unwrap())
+    }
+
     // #[wasm_bindgen]
     // pub async fn fetch_data(&self) -> Result<(), JsValue> {
     //     self.router.borrow_mut().fetch_data().await.map_err(|e| JsValue::from_str(&e.to_string())).expect("fetch data failure");
@@ -222,8 +650,9 @@ impl WasmRouter {
 
     #[wasm_bindgen]
     pub async fn init_vault_state(&self) -> Result<(), JsValue> {
-        match self.router.try_borrow_mut() {
+        match self.router_mut() {
             Ok(mut router) => {
+                let before = router.change_baseline();
                 router
                     .vault
                     .init_vault_state()
@@ -231,6 +660,7 @@ impl WasmRouter {
                     .map_err(|e| JsValue::from_str(&e.to_string()))
                     .expect("init vault state failure");
                 router.calculate_price_plp();
+                router.emit_changes(before);
                 Ok(())
             }
             Err(e) => {
@@ -443,12 +873,116 @@ impl WasmRouter {
         Ok(to_value(&result).unwrap())
     }
 
+    /// Vault events of a confirmed transaction and the executed amounts of its trade
+    /// @param receipt JSON-RPC receipt, as returned by eth_getTransactionReceipt
+    /// @return {events: VaultEvent[], summary: TradeSummary | null}
+    #[wasm_bindgen]
+    pub fn get_trade_summary(&self, receipt: JsValue) -> Result<JsValue, JsValue> {
+        let router = self.router.try_borrow().map_err(|e| JsValue::from_str(&e.to_string()))?;
+        let receipt = from_value(receipt).map_err(|e| JsValue::from_str(&e.to_string()))?;
+        let vault = router.config.contract_address.vault.parse().map_err(|_| JsValue::from_str("invalid vault address"))?;
+        let events = events::decode_receipt(&receipt, vault);
+        let summary = events::summarize(&events, router.vault.state.usdp_address);
+        Ok(to_value(&TradeResult { events, summary }).unwrap())
+    }
+
+    /// PLP mints/redeems, swaps and position changes of an account, oldest first
+    /// Note: Need to call fetch_vault first, for the USDP address
+    /// @return HistoryEntry[], USD values in 30 decimals
+    #[wasm_bindgen]
+    pub async fn get_account_history(&self, account: String, from_block: u64) -> Result<JsValue, JsValue> {
+        // the scan can take long, the router stays usable meanwhile
+        let (config, usdp) = {
+            let router = self.router.try_borrow().map_err(|e| JsValue::from_str(&e.to_string()))?;
+            (router.config.clone(), router.vault.state.usdp_address)
+        };
+        let history = account_history::fetch_config_history(&config, usdp, &account, from_block)
+            .await
+            .map_err(|e| JsValue::from_str(&e.to_string()))?;
+        Ok(to_value(&history).unwrap())
+    }
+
+    /// Account history for tax reporting, one row per trade
+    /// @param format "csv" or "json"
+    /// @return CSV with the columns timestamp,transaction_hash,action,token_in,amount_in,token_out,amount_out,usd_value,fee_token,fee_amount,fee_usd,block_number
+    /// or a JSON array of objects with the same keys
+    #[wasm_bindgen]
+    pub async fn export_account_history(&self, account: String, from_block: u64, format: String) -> Result<String, JsValue> {
+        let format = format.parse().map_err(|e: anyhow::Error| JsValue::from_str(&e.to_string()))?;
+        let (config, usdp) = {
+            let router = self.router.try_borrow().map_err(|e| JsValue::from_str(&e.to_string()))?;
+            (router.config.clone(), router.vault.state.usdp_address)
+        };
+        let history = account_history::fetch_config_history(&config, usdp, &account, from_block)
+            .await
+            .map_err(|e| JsValue::from_str(&e.to_string()))?;
+        history_export::export(&history, &config.tokens, usdp, format).map_err(|e| JsValue::from_str(&e.to_string()))
+    }
+
     #[wasm_bindgen]
     pub fn get_plp_price(&self, is_buy: bool) -> Result<JsValue, JsValue> {
         let router = self.router.try_borrow().map_err(|e| JsValue::from_str(&e.to_string()))?;
         Ok(to_value(&router.vault.state.get_plp_price(is_buy)).unwrap())
     }
 
+    /// Current vs target weight of every tradeable token, sorted by cheapest entry
+    /// Note: Need to call fetch_async first
+    #[wasm_bindgen]
+    pub fn get_pool_composition(&self) -> Result<JsValue, JsValue> {
+        let router = self.router.try_borrow().map_err(|e| JsValue::from_str(&e.to_string()))?;
+        let mut composition = router.vault.state.composition(&router.config.tokens);
+        composition::sort_by_cheapest_entry(&mut composition);
+        Ok(to_value(&composition).unwrap())
+    }
+
+    /// Rank every tradeable token by the cost of minting (is_buy) or redeeming PLP
+    /// @param usd_amount The trade size in USD, 30 decimals
+    /// @return {best: Quote | null, ranking: Quote[]}
+    #[wasm_bindgen]
+    pub fn get_plp_advice(&self, usd_amount: &str, is_buy: bool) -> Result<JsValue, JsValue> {
+        let router = self.router.try_borrow().map_err(|e| JsValue::from_str(&e.to_string()))?;
+        let usd_amount = U256::from_dec_str(usd_amount).map_err(|e| JsValue::from_str(&e.to_string()))?;
+        let advice = if is_buy {
+            router.vault.state.advise_buy_plp(usd_amount, &router.config.tokens)
+        } else {
+            router.vault.state.advise_sell_plp(usd_amount, &router.config.tokens)
+        };
+        Ok(to_value(&advice).unwrap())
+    }
+
+    /// Split a large PLP mint across the selected account's tokens to minimise fees
+    /// @param usd_budget The mint size in USD, 30 decimals
+    /// @param chunks Number of slices the budget is allocated in, 0 for the default
+    /// @return MintPlan
+    #[wasm_bindgen]
+    pub fn get_plp_mint_plan(&self, usd_budget: &str, chunks: u32) -> Result<JsValue, JsValue> {
+        let router = self.router.try_borrow().map_err(|e| JsValue::from_str(&e.to_string()))?;
+        let usd_budget = U256::from_dec_str(usd_budget).map_err(|e| JsValue::from_str(&e.to_string()))?;
+        let account = router.config.selected_account.clone().ok_or_else(|| JsValue::from_str("account not set"))?;
+        let chunks = if chunks == 0 { optimizer::DEFAULT_MINT_CHUNKS } else { chunks };
+        let plan = router.vault.state.optimize_plp_mint(usd_budget, &router.config.tokens, &account, chunks);
+        Ok(to_value(&plan).unwrap())
+    }
+
+    /// Fee bps, effective price and output for a range of trade sizes, for charts
+    /// @param kind "BuyPlp" | "SellPlp" | "Swap"
+    /// @param token_out Only used for swaps
+    /// @param from, to Amounts of the input token (PLP for SellPlp), in its decimals
+    /// @param steps Number of points, at most 1000
+    /// @return CurvePoint[]
+    #[wasm_bindgen]
+    pub fn get_fee_curve(&self, kind: JsValue, token_in: &str, token_out: &str, from: &str, to: &str, steps: u32) -> Result<JsValue, JsValue> {
+        let router = self.router.try_borrow().map_err(|e| JsValue::from_str(&e.to_string()))?;
+        let kind = from_value(kind).map_err(|e| JsValue::from_str(&e.to_string()))?;
+        let from = U256::from_dec_str(from).map_err(|e| JsValue::from_str(&e.to_string()))?;
+        let to = U256::from_dec_str(to).map_err(|e| JsValue::from_str(&e.to_string()))?;
+        let find_token = |address: &str| router.config.tokens.iter().find(|token| token.address.eq_ignore_ascii_case(address));
+        let token_in = find_token(token_in).ok_or_else(|| JsValue::from_str("unknown token"))?;
+        let amounts = fee_curve::amount_range(from, to, steps);
+        let curve = router.vault.state.fee_curve(kind, token_in, find_token(token_out), &amounts);
+        Ok(to_value(&curve).unwrap())
+    }
+
     #[wasm_bindgen(getter)]
     pub fn connected_chain(&self) -> u64 {
         let router_ref = self.router.try_borrow();
@@ -459,6 +993,24 @@ impl WasmRouter {
     }
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct StakingResult {
+    pub reward_trackers: Vec<staking::RewardTrackerInfo>,
+    pub staking_info: Vec<staking::StakingInfo>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct VestingResult {
+    pub info: vester::VestingInfo,
+    pub schedule: vester::VestingSchedule,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TradeResult {
+    pub events: Vec<events::VaultEvent>,
+    pub summary: Option<events::TradeSummary>,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct GetAmountOut {
     pub amount_out: U256,